@@ -1,6 +1,25 @@
 //! Slotpaste chooser UI: UDP listener for agent, overlay window, send chosen/cancel to agent.
+//! Messages are `clip_protocol` types, signed both ways with the agent's secret
+//! (`clip_config::auth`); the secret file is read for every message, so the UI keeps working
+//! across agent restarts. At startup the UI sends `hello` and logs the agent's answer, then a
+//! `heartbeat` every `HEARTBEAT_INTERVAL` so the agent knows it is running.
+//! Show/hide from the agent are acked (and handled once); chosen/cancel are resent until the
+//! agent acks them.
+//!
+//! With `ipc.transport = "unix"` the UI also keeps a connection to the agent's socket
+//! (`clip_config::agent_socket_path`), reconnecting when the agent restarts; while connected,
+//! messages in both directions go over it as frames. UDP stays up as the fallback.
 
+use clip_config::auth::Secret;
+use clip_config::Config;
+use clip_protocol::{
+    decode, encode, ChooserKeys, DecodeError, Dedupe, FromAgent, MessageIds, ToAgent, ACK_TIMEOUT,
+    HEARTBEAT_INTERVAL, PROTOCOL_VERSION, SEND_ATTEMPTS,
+};
+use std::io::ErrorKind;
 use std::net::UdpSocket;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
 use std::sync::Mutex;
 use std::sync::OnceLock;
 use std::thread;
@@ -8,22 +27,292 @@ use std::time::Duration;
 use tauri::Emitter;
 use tauri::Manager;
 
-const UI_PORT: u16 = 45454;
-const AGENT_PORT: u16 = 45455;
 const BIND_ADDR: &str = "127.0.0.1";
 
 /// When true, show chooser window ~800ms after startup to confirm visibility.
 const STARTUP_SHOW_TEST: bool = true;
 
 static CURRENT_TOKEN: OnceLock<Mutex<Option<String>>> = OnceLock::new();
+static CONFIG: OnceLock<Config> = OnceLock::new();
+static MESSAGE_IDS: OnceLock<MessageIds> = OnceLock::new();
+/// Writer half of the connection to the agent's socket, while there is one.
+#[cfg(unix)]
+static AGENT_STREAM: OnceLock<Mutex<Option<UnixStream>>> = OnceLock::new();
+
+/// Shared Slotpaste config (ports, slot labels). Falls back to defaults if the file is invalid.
+fn config() -> &'static Config {
+    CONFIG.get_or_init(|| match Config::load() {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("[clip-ui] config error: {} (using defaults)", e);
+            Config::default()
+        }
+    })
+}
 
 fn current_token() -> &'static Mutex<Option<String>> {
     CURRENT_TOKEN.get_or_init(|| Mutex::new(None))
 }
 
-fn send_to_agent(msg: &str) {
+/// How the UI introduces itself in `hello` and `heartbeat`.
+fn client_name() -> String {
+    format!("clip-ui {}", env!("CARGO_PKG_VERSION"))
+}
+
+fn next_msg_id() -> Option<u64> {
+    Some(MESSAGE_IDS.get_or_init(MessageIds::new).next())
+}
+
+#[cfg(unix)]
+fn agent_stream() -> &'static Mutex<Option<UnixStream>> {
+    AGENT_STREAM.get_or_init(|| Mutex::new(None))
+}
+
+/// Sends `msg` to the agent on its own thread: down the socket connection if there is one,
+/// else over UDP, resending until the agent acks it.
+fn send_to_agent(msg: ToAgent) {
+    thread::spawn(move || {
+        #[cfg(unix)]
+        if send_on_stream(&msg) {
+            return;
+        }
+        if let Err(e) = send_acked(&msg) {
+            eprintln!("[clip-ui] agent did not acknowledge {:?}: {}", msg, e);
+        }
+    });
+}
+
+/// Tells the agent the UI is running: down the socket connection if there is one, else one
+/// datagram. Skipped quietly while no agent is running (there is no secret to sign with).
+fn send_heartbeat() {
+    let msg = ToAgent::Heartbeat {
+        client: client_name(),
+    };
+    #[cfg(unix)]
+    if send_on_stream(&msg) {
+        return;
+    }
+    let Ok(secret) = Secret::load() else { return };
     if let Ok(sock) = UdpSocket::bind("127.0.0.1:0") {
-        let _ = sock.send_to(msg.as_bytes(), (BIND_ADDR, AGENT_PORT));
+        let _ = sock.send_to(
+            secret.sign(&encode(&msg)).as_bytes(),
+            (BIND_ADDR, config().ipc.agent_port),
+        );
+    }
+}
+
+/// Sends `msg` up to SEND_ATTEMPTS times, until an ack for its msg_id comes back.
+fn send_acked(msg: &ToAgent) -> Result<(), String> {
+    let msg_id = msg.msg_id().ok_or("message has no msg_id")?;
+    let secret = Secret::load().map_err(|e| format!("{} (is the agent running?)", e))?;
+    let sock = UdpSocket::bind("127.0.0.1:0").map_err(|e| e.to_string())?;
+    sock.connect((BIND_ADDR, config().ipc.agent_port))
+        .map_err(|e| e.to_string())?;
+    sock.set_read_timeout(Some(ACK_TIMEOUT))
+        .map_err(|e| e.to_string())?;
+    let datagram = secret.sign(&encode(msg));
+    let mut buf = [0u8; 2048];
+    for _ in 0..SEND_ATTEMPTS {
+        sock.send(datagram.as_bytes()).map_err(|e| e.to_string())?;
+        // Anything but our ack (a stray datagram) costs the rest of this attempt's wait.
+        match sock.recv(&mut buf) {
+            Ok(n) => {
+                let reply = std::str::from_utf8(&buf[..n])
+                    .ok()
+                    .and_then(|s| secret.open(s).ok());
+                if reply.and_then(|r| decode::<FromAgent>(&r).ok())
+                    == Some(FromAgent::Ack { msg_id })
+                {
+                    return Ok(());
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused => {
+                return Err("agent is not running".to_string())
+            }
+            Err(_) => {}
+        }
+    }
+    Err(format!("no ack after {} attempts", SEND_ATTEMPTS))
+}
+
+/// Writes `msg` to the agent's socket connection. The stream keeps order and does not lose
+/// frames, so there is no resend; false (connection dropped) if there is none or the write fails.
+#[cfg(unix)]
+fn send_on_stream(msg: &ToAgent) -> bool {
+    let mut stream = agent_stream().lock().unwrap_or_else(|p| p.into_inner());
+    let Some(s) = stream.as_mut() else {
+        return false;
+    };
+    let Some(frame) = signed(msg) else {
+        return false;
+    };
+    match clip_protocol::frame::write_frame(s, frame.as_bytes()) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("[clip-ui] socket write failed: {} (falling back to UDP)", e);
+            *stream = None;
+            false
+        }
+    }
+}
+
+/// Keeps a connection to the agent's socket: says hello, handles show/hide frames (acked on the
+/// stream) and reconnects a second after the agent goes away.
+#[cfg(unix)]
+fn run_socket_client(handle: tauri::AppHandle) {
+    use clip_protocol::frame::{read_frame, write_frame};
+
+    let path = match clip_config::agent_socket_path() {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("[clip-ui] agent socket unavailable: {} (using UDP)", e);
+            return;
+        }
+    };
+    let mut waiting_logged = false;
+    loop {
+        let stream = match UnixStream::connect(&path) {
+            Ok(s) => s,
+            Err(e) => {
+                if !waiting_logged {
+                    eprintln!(
+                        "[clip-ui] no agent on {}: {} (retrying; UDP meanwhile)",
+                        path.display(),
+                        e
+                    );
+                    waiting_logged = true;
+                }
+                thread::sleep(Duration::from_secs(1));
+                continue;
+            }
+        };
+        waiting_logged = false;
+        let hello = ToAgent::Hello {
+            client: client_name(),
+        };
+        let (Ok(mut writer), Some(frame)) = (stream.try_clone(), signed(&hello)) else {
+            thread::sleep(Duration::from_secs(1));
+            continue;
+        };
+        if write_frame(&mut writer, frame.as_bytes()).is_err() {
+            continue;
+        }
+        eprintln!("[clip-ui] connected to agent socket {}", path.display());
+        *agent_stream().lock().unwrap_or_else(|p| p.into_inner()) = Some(writer);
+        let mut reader = std::io::BufReader::new(stream);
+        let mut seen = Dedupe::default();
+        while let Ok(Some(frame)) = read_frame(&mut reader) {
+            let Ok(text) = std::str::from_utf8(&frame) else {
+                continue;
+            };
+            let Some(s) = open_from_agent(text) else {
+                continue;
+            };
+            let message = decode::<FromAgent>(&s);
+            if let Some(msg_id) = message.as_ref().ok().and_then(FromAgent::msg_id) {
+                if let Some(ack) = signed(&ToAgent::Ack { msg_id }) {
+                    if let Some(w) = agent_stream()
+                        .lock()
+                        .unwrap_or_else(|p| p.into_inner())
+                        .as_mut()
+                    {
+                        let _ = write_frame(w, ack.as_bytes());
+                    }
+                }
+                if !seen.first_time(msg_id) {
+                    continue;
+                }
+            }
+            handle_from_agent(&handle, message);
+        }
+        *agent_stream().lock().unwrap_or_else(|p| p.into_inner()) = None;
+        eprintln!("[clip-ui] agent socket closed; reconnecting");
+        thread::sleep(Duration::from_secs(1));
+    }
+}
+
+/// Acts on one message from the agent, whichever way it came.
+fn handle_from_agent(handle: &tauri::AppHandle, message: Result<FromAgent, DecodeError>) {
+    match message {
+        Ok(FromAgent::Show {
+            mode,
+            token,
+            timeout_ms,
+            keys,
+            ..
+        }) => {
+            eprintln!(
+                "[clip-ui] show received mode={} token={} -> calling show_chooser_on_main_thread",
+                mode.as_str(),
+                token
+            );
+            show_chooser_on_main_thread(
+                handle.clone(),
+                mode.as_str().to_string(),
+                token,
+                timeout_ms,
+                keys,
+            );
+        }
+        Ok(FromAgent::Hide { token, .. }) => {
+            if let Ok(guard) = current_token().lock() {
+                if guard.as_deref() == Some(token.as_str()) {
+                    let hide_handle = handle.clone();
+                    let _ = handle.run_on_main_thread(move || {
+                        if let Some(win) = hide_handle.get_webview_window("chooser") {
+                            let _ = win.hide();
+                        }
+                        if let Ok(mut t) = current_token().lock() {
+                            *t = None;
+                        }
+                    });
+                }
+            }
+        }
+        Ok(FromAgent::Welcome { agent }) => {
+            eprintln!(
+                "[clip-ui] connected to clip-agent {} (protocol {})",
+                agent, PROTOCOL_VERSION
+            );
+        }
+        Ok(FromAgent::Unsupported {
+            min_version,
+            max_version,
+        }) => {
+            eprintln!(
+                "[clip-ui] ERROR: agent speaks protocol {}..={}, this UI speaks {}; update one of them",
+                min_version, max_version, PROTOCOL_VERSION
+            );
+        }
+        Ok(FromAgent::Ack { .. } | FromAgent::Response(_)) => {}
+        Err(e) => eprintln!("[clip-ui] recv: {}", e),
+    }
+}
+
+/// `msg` signed with the running agent's secret, or None (logged) if there is none.
+fn signed(msg: &ToAgent) -> Option<String> {
+    match Secret::load() {
+        Ok(secret) => Some(secret.sign(&encode(msg))),
+        Err(e) => {
+            eprintln!(
+                "[clip-ui] cannot sign message: {} (is the agent running?)",
+                e
+            );
+            None
+        }
+    }
+}
+
+/// The message inside a datagram from the agent, or None (logged) if it is not signed with the
+/// current secret.
+fn open_from_agent(datagram: &str) -> Option<String> {
+    let opened = Secret::load().and_then(|secret| secret.open(datagram).map_err(|e| e.to_string()));
+    match opened {
+        Ok(msg) => Some(msg),
+        Err(e) => {
+            eprintln!("[clip-ui] recv: rejected message: {}", e);
+            None
+        }
     }
 }
 
@@ -33,7 +322,10 @@ fn log_windows(handle: &tauri::AppHandle) {
     eprintln!("[clip-ui] webview windows count: {}", windows.len());
     for (label, win) in windows.iter() {
         let visible = win.is_visible().unwrap_or(false);
-        eprintln!("[clip-ui]   window label={:?} is_visible={}", label, visible);
+        eprintln!(
+            "[clip-ui]   window label={:?} is_visible={}",
+            label, visible
+        );
     }
 }
 
@@ -41,16 +333,25 @@ fn log_windows(handle: &tauri::AppHandle) {
 fn log_monitors(handle: &tauri::AppHandle) {
     eprintln!("[clip-ui] --- monitor diagnostics ---");
     if let Ok(Some(primary)) = handle.primary_monitor() {
-        eprintln!("[clip-ui] primary_monitor: position={:?} size={:?} scale_factor={:?}",
-            primary.position(), primary.size(), primary.scale_factor());
+        eprintln!(
+            "[clip-ui] primary_monitor: position={:?} size={:?} scale_factor={:?}",
+            primary.position(),
+            primary.size(),
+            primary.scale_factor()
+        );
     } else {
         eprintln!("[clip-ui] primary_monitor: none or error");
     }
     if let Ok(monitors) = handle.available_monitors() {
         eprintln!("[clip-ui] available_monitors count: {}", monitors.len());
         for (i, m) in monitors.iter().enumerate() {
-            eprintln!("[clip-ui]   monitor[{}]: position={:?} size={:?} scale_factor={:?}",
-                i, m.position(), m.size(), m.scale_factor());
+            eprintln!(
+                "[clip-ui]   monitor[{}]: position={:?} size={:?} scale_factor={:?}",
+                i,
+                m.position(),
+                m.size(),
+                m.scale_factor()
+            );
         }
     } else {
         eprintln!("[clip-ui] available_monitors: error");
@@ -63,8 +364,12 @@ fn show_chooser_on_main_thread(
     mode: String,
     token: String,
     timeout_ms: u64,
+    keys: Option<ChooserKeys>,
 ) {
-    eprintln!("[clip-ui] show_chooser_on_main_thread called mode={} token={} timeout_ms={}", mode, token, timeout_ms);
+    eprintln!(
+        "[clip-ui] show_chooser_on_main_thread called mode={} token={} timeout_ms={}",
+        mode, token, timeout_ms
+    );
     let h = handle.clone();
     let _ = handle.run_on_main_thread(move || {
         eprintln!("[clip-ui] show_chooser_on_main_thread: main thread closure entered");
@@ -77,11 +382,17 @@ fn show_chooser_on_main_thread(
         if let Ok(mut t) = current_token().lock() {
             *t = Some(token.clone());
         }
-        let _ = h.emit("chooser-show", serde_json::json!({
-            "mode": mode,
-            "token": token,
-            "timeout_ms": timeout_ms
-        }));
+        let _ = h.emit(
+            "chooser-show",
+            serde_json::json!({
+                "mode": mode,
+                "token": token,
+                "timeout_ms": timeout_ms,
+                "labels": config().slots.labels,
+                // The agent's bindings for this chooser; the page falls back to the defaults without them.
+                "keys": keys
+            }),
+        );
 
         let chooser = h.get_webview_window("chooser");
         if chooser.is_none() {
@@ -90,15 +401,30 @@ fn show_chooser_on_main_thread(
         }
         eprintln!("[clip-ui] UDP show: got chooser window, applying show sequence");
         if let Some(win) = chooser {
-            eprintln!("[clip-ui]   set_always_on_top(true): {:?}", win.set_always_on_top(true));
-            eprintln!("[clip-ui]   set_visible_on_all_workspaces(true): {:?}", win.set_visible_on_all_workspaces(true));
+            eprintln!(
+                "[clip-ui]   set_always_on_top(true): {:?}",
+                win.set_always_on_top(true)
+            );
+            eprintln!(
+                "[clip-ui]   set_visible_on_all_workspaces(true): {:?}",
+                win.set_visible_on_all_workspaces(true)
+            );
             eprintln!("[clip-ui]   unminimize: {:?}", win.unminimize());
             eprintln!("[clip-ui]   hide: {:?}", win.hide());
             eprintln!("[clip-ui]   show: {:?}", win.show());
-            let size_phys = tauri::PhysicalSize { width: 600, height: 250 };
+            let size_phys = tauri::PhysicalSize {
+                width: 600,
+                height: 250,
+            };
             let pos_phys = tauri::PhysicalPosition { x: 20, y: 20 };
-            eprintln!("[clip-ui]   set_size(Physical 600x250): {:?}", win.set_size(tauri::Size::Physical(size_phys)));
-            eprintln!("[clip-ui]   set_position(Physical 20,20): {:?}", win.set_position(tauri::Position::Physical(pos_phys)));
+            eprintln!(
+                "[clip-ui]   set_size(Physical 600x250): {:?}",
+                win.set_size(tauri::Size::Physical(size_phys))
+            );
+            eprintln!(
+                "[clip-ui]   set_position(Physical 20,20): {:?}",
+                win.set_position(tauri::Position::Physical(pos_phys))
+            );
             eprintln!("[clip-ui]   set_focus: {:?}", win.set_focus());
             if let Ok(pos) = win.outer_position() {
                 eprintln!("[clip-ui]   outer_position() after set: {:?}", pos);
@@ -106,7 +432,10 @@ fn show_chooser_on_main_thread(
             if let Ok(sz) = win.inner_size() {
                 eprintln!("[clip-ui]   inner_size() after set: {:?}", sz);
             }
-            eprintln!("[clip-ui]   after show sequence, is_visible: {:?}", win.is_visible());
+            eprintln!(
+                "[clip-ui]   after show sequence, is_visible: {:?}",
+                win.is_visible()
+            );
         }
         eprintln!("[clip-ui] UDP show: done");
         log_windows(&h);
@@ -115,9 +444,15 @@ fn show_chooser_on_main_thread(
 
 #[tauri::command]
 fn send_chosen(token: String, slot: u8) {
-    eprintln!("[clip-ui] send_chosen: token={} slot={} -> UDP 45455", token, slot);
-    let msg = format!(r#"{{"type":"chosen","token":"{}","slot":{}}}"#, token, slot);
-    send_to_agent(&msg);
+    eprintln!(
+        "[clip-ui] send_chosen: token={} slot={} -> agent",
+        token, slot
+    );
+    send_to_agent(ToAgent::Chosen {
+        token,
+        slot,
+        msg_id: next_msg_id(),
+    });
     if let Ok(mut t) = current_token().lock() {
         *t = None;
     }
@@ -125,9 +460,15 @@ fn send_chosen(token: String, slot: u8) {
 
 #[tauri::command]
 fn send_cancel(token: String, reason: String) {
-    eprintln!("[clip-ui] send_cancel: token={} reason={} -> UDP 45455", token, reason);
-    let msg = format!(r#"{{"type":"cancel","token":"{}","reason":"{}"}}"#, token, reason);
-    send_to_agent(&msg);
+    eprintln!(
+        "[clip-ui] send_cancel: token={} reason={} -> agent",
+        token, reason
+    );
+    send_to_agent(ToAgent::Cancel {
+        token,
+        reason,
+        msg_id: next_msg_id(),
+    });
     if let Ok(mut t) = current_token().lock() {
         *t = None;
     }
@@ -194,23 +535,41 @@ pub fn run() {
                 });
             }
 
-            // UDP listener
+            thread::spawn(|| loop {
+                send_heartbeat();
+                thread::sleep(HEARTBEAT_INTERVAL);
+            });
+
+            #[cfg(unix)]
+            if config().ipc.transport == clip_config::IpcTransport::Unix {
+                let handle = handle.clone();
+                thread::spawn(move || run_socket_client(handle));
+            }
+
+            // UDP listener, also the fallback when the socket is off or unavailable
+            let ui_port = config().ipc.ui_port;
             thread::spawn(move || {
-                let sock = match UdpSocket::bind((BIND_ADDR, UI_PORT)) {
+                let sock = match UdpSocket::bind((BIND_ADDR, ui_port)) {
                     Ok(s) => {
-                        eprintln!("[clip-ui] UDP listening on {}:{}", BIND_ADDR, UI_PORT);
+                        eprintln!("[clip-ui] UDP listening on {}:{}", BIND_ADDR, ui_port);
                         s
                     }
                     Err(e) => {
-                        eprintln!("[clip-ui] UDP bind failed: {} (is port {} in use?)", e, UI_PORT);
+                        eprintln!("[clip-ui] UDP bind failed: {} (is port {} in use?)", e, ui_port);
                         return;
                     }
                 };
                 sock.set_read_timeout(Some(Duration::from_millis(500))).ok();
-                let mut buf = [0u8; 512];
+                // From the listening socket, so the agent's welcome arrives below.
+                let hello = ToAgent::Hello { client: client_name() };
+                if let Some(datagram) = signed(&hello) {
+                    let _ = sock.send_to(datagram.as_bytes(), (BIND_ADDR, config().ipc.agent_port));
+                }
+                let mut seen = Dedupe::default();
+                let mut buf = [0u8; 2048];
                 loop {
                     match sock.recv_from(&mut buf) {
-                        Ok((n, _)) => {
+                        Ok((n, from)) => {
                             eprintln!("[clip-ui] UDP recv {} bytes", n);
                             let s = match std::str::from_utf8(&buf[..n]) {
                                 Ok(x) => x.trim(),
@@ -219,46 +578,18 @@ pub fn run() {
                                     continue;
                                 }
                             };
-                            if let Ok(v) = serde_json::from_str::<serde_json::Value>(s) {
-                                let typ = v.get("type").and_then(|t| t.as_str());
-                                eprintln!("[clip-ui] UDP parsed JSON type={:?}", typ);
-                                if typ == Some("show") {
-                                    if let (Some(mode), Some(token), Some(timeout_ms)) = (
-                                        v.get("mode").and_then(|m| m.as_str()),
-                                        v.get("token").and_then(|t| t.as_str()),
-                                        v.get("timeout_ms").and_then(|t| t.as_u64()),
-                                    ) {
-                                        eprintln!(
-                                            "[clip-ui] UDP show received mode={} token={} -> calling show_chooser_on_main_thread",
-                                            mode, token
-                                        );
-                                        show_chooser_on_main_thread(
-                                            handle.clone(),
-                                            mode.to_string(),
-                                            token.to_string(),
-                                            timeout_ms,
-                                        );
-                                    }
-                                } else if v.get("type").and_then(|t| t.as_str()) == Some("hide") {
-                                    if let Some(token) = v.get("token").and_then(|t| t.as_str()) {
-                                        if let Ok(guard) = current_token().lock() {
-                                            if guard.as_deref() == Some(token) {
-                                                let hide_handle = handle.clone();
-                                                let _ = handle.run_on_main_thread(move || {
-                                                    if let Some(win) =
-                                                        hide_handle.get_webview_window("chooser")
-                                                    {
-                                                        let _ = win.hide();
-                                                    }
-                                                    if let Ok(mut t) = current_token().lock() {
-                                                        *t = None;
-                                                    }
-                                                });
-                                            }
-                                        }
-                                    }
+                            let Some(s) = open_from_agent(s) else { continue };
+                            let message = decode::<FromAgent>(&s);
+                            if let Some(msg_id) = message.as_ref().ok().and_then(FromAgent::msg_id) {
+                                if let Some(ack) = signed(&ToAgent::Ack { msg_id }) {
+                                    let _ = sock.send_to(ack.as_bytes(), from);
+                                }
+                                if !seen.first_time(msg_id) {
+                                    eprintln!("[clip-ui] UDP recv: resent message {}, already handled", msg_id);
+                                    continue;
                                 }
                             }
+                            handle_from_agent(&handle, message);
                         }
                         Err(_) => {}
                     }
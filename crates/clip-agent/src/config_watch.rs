@@ -0,0 +1,185 @@
+//! Live config reload: polls the config file and hands each valid edit to an apply callback.
+//! Invalid edits (parse errors, out-of-range values, or a rejected apply) are logged and the
+//! last good config stays in effect. `[ipc]` is read once: edits to it are logged and wait for a
+//! restart while the rest of the edit applies.
+
+use clip_config::{Config, ConfigError};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// How often the config file is checked for changes.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Tracks the config file's contents and reports when they change.
+pub struct ConfigWatcher {
+    path: PathBuf,
+    last_text: Option<String>,
+}
+
+impl ConfigWatcher {
+    /// Starts from the file's current contents, so the first poll reports nothing.
+    pub fn new(path: PathBuf) -> Self {
+        let last_text = std::fs::read_to_string(&path).ok();
+        Self { path, last_text }
+    }
+
+    /// Returns None if the file is unchanged since the last poll, else the newly loaded config
+    /// (or why it could not be loaded). A deleted file loads as the defaults, as at startup.
+    pub fn poll(&mut self) -> Option<Result<Config, ConfigError>> {
+        let text = std::fs::read_to_string(&self.path).ok();
+        if text == self.last_text {
+            return None;
+        }
+        self.last_text = text;
+        Some(Config::load_from(&self.path))
+    }
+}
+
+/// Sections that differ between two configs, for the reload log line.
+pub fn changed_sections(old: &Config, new: &Config) -> Vec<&'static str> {
+    let mut out = Vec::new();
+    if old.chooser != new.chooser {
+        out.push("chooser");
+    }
+    if old.ipc != new.ipc {
+        out.push("ipc");
+    }
+    if old.paste != new.paste {
+        out.push("paste");
+    }
+    if old.keys != new.keys {
+        out.push("keys");
+    }
+    if old.slots != new.slots {
+        out.push("slots");
+    }
+    if old.history != new.history {
+        out.push("history");
+    }
+    out
+}
+
+/// `new` with the `[ipc]` the agent started with: the UI also reads its ports and transport only
+/// when it starts, so the agent alone switching over would cut it off.
+pub fn live_config(started: &Config, new: &Config) -> Config {
+    Config {
+        ipc: started.ipc.clone(),
+        ..new.clone()
+    }
+}
+
+/// Polls `path` every `POLL_INTERVAL` on a background thread. For each valid edit, calls
+/// `apply` with the edit minus its `[ipc]` changes (`live_config`); on Ok the edit becomes the
+/// current config, on Err it is rejected.
+/// `apply` must check everything first and only then switch components over, so a rejected
+/// edit leaves the agent untouched.
+pub fn spawn<F>(path: PathBuf, config: Config, mut apply: F)
+where
+    F: FnMut(&Config) -> Result<(), String> + Send + 'static,
+{
+    // Read before the thread starts, so an edit made right after this returns is still seen.
+    let mut watcher = ConfigWatcher::new(path.clone());
+    thread::spawn(move || {
+        let started = config.clone();
+        let mut current = config;
+        info!("config: watching {}", path.display());
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let new = match watcher.poll() {
+                None => continue,
+                Some(Ok(new)) => new,
+                Some(Err(e)) => {
+                    warn!("config edit rejected, keeping last good config: {}", e);
+                    continue;
+                }
+            };
+            if new.ipc != current.ipc {
+                warn!("config: [ipc] changes take effect after restarting clip-agent and the UI");
+            }
+            let live = live_config(&started, &new);
+            let changed = changed_sections(&live_config(&started, &current), &live);
+            if changed.is_empty() {
+                debug!("config: no settings to apply until restart");
+                current = new;
+                continue;
+            }
+            match apply(&live) {
+                Ok(()) => {
+                    info!(
+                        "config reloaded from {} (changed: {})",
+                        path.display(),
+                        changed.join(", ")
+                    );
+                    current = new;
+                }
+                Err(e) => warn!(
+                    "config edit rejected, keeping last good config: {}: {}",
+                    path.display(),
+                    e
+                ),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poll_reports_only_changes_and_surfaces_invalid_edits() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "[chooser]\ntimeout_ms = 900\n").unwrap();
+
+        let mut watcher = ConfigWatcher::new(path.clone());
+        assert!(watcher.poll().is_none());
+
+        std::fs::write(&path, "[chooser]\ntimeout_ms = 1200\n").unwrap();
+        let c = watcher.poll().unwrap().unwrap();
+        assert_eq!(c.chooser.timeout_ms, 1200);
+        assert_eq!(changed_sections(&Config::default(), &c), vec!["chooser"]);
+        assert!(watcher.poll().is_none());
+
+        std::fs::write(&path, "[chooser]\ntimeout_ms = 1\n").unwrap();
+        assert!(matches!(
+            watcher.poll(),
+            Some(Err(ConfigError::Invalid { .. }))
+        ));
+        assert!(watcher.poll().is_none(), "a bad edit is reported once");
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(watcher.poll().unwrap().unwrap(), Config::default());
+    }
+
+    #[test]
+    fn an_edit_to_ipc_and_keys_still_updates_the_keymap() {
+        use crate::keys::Keymap;
+        use std::sync::mpsc;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "").unwrap();
+        let (tx, rx) = mpsc::channel();
+        spawn(path.clone(), Config::default(), move |new| {
+            let keymap = Keymap::from_config(&new.keys)?;
+            tx.send((keymap, new.ipc.clone()))
+                .map_err(|e| e.to_string())
+        });
+
+        std::fs::write(
+            &path,
+            "[ipc]\nagent_port = 47000\n\n[keys]\ncancel = \"q\"\n",
+        )
+        .unwrap();
+        let (keymap, ipc) = rx
+            .recv_timeout(POLL_INTERVAL * 5)
+            .expect("the edit was applied");
+        let mut expected = Config::default();
+        expected.keys.cancel = clip_config::hotkey::Hotkey::parse("q").unwrap();
+        assert_eq!(keymap, Keymap::from_config(&expected.keys).unwrap());
+        assert_eq!(ipc, Config::default().ipc, "[ipc] waits for a restart");
+    }
+}
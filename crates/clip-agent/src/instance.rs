@@ -0,0 +1,170 @@
+//! One agent per user: `agent.pid` in `clip_config::runtime_dir()` holds the running agent's pid
+//! and stays locked (`File::try_lock`) while it runs. The OS drops the lock when the process
+//! exits however it exits, so a locked file means a live agent and an unlocked one with a pid in
+//! it was left by an agent that crashed; the next agent takes it over.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::{process, thread};
+use tracing::warn;
+
+/// Exit status of an agent that found the lock held, so a service manager can tell it from a
+/// crash and not restart it (`RestartPreventExitStatus=` in the unit `clip install` writes).
+pub const ALREADY_RUNNING: i32 = 3;
+
+/// Where the lock lives.
+pub fn lock_path() -> Result<PathBuf, String> {
+    Ok(clip_config::runtime_dir()?.join("agent.pid"))
+}
+
+/// The held lock. Dropping it clears the pid and releases the lock.
+pub struct InstanceLock {
+    file: File,
+}
+
+impl InstanceLock {
+    /// Takes the lock at `path`, or fails naming the agent that holds it.
+    pub fn acquire(path: &Path) -> Result<Self, String> {
+        let mut file = open(path)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(std::fs::TryLockError::WouldBlock) => {
+                return Err(match read_pid(&mut file) {
+                    Some(pid) => format!(
+                        "another clip-agent is running (pid {}); stop it with `clip stop`",
+                        pid
+                    ),
+                    None => "another clip-agent is running; stop it with `clip stop`".to_string(),
+                })
+            }
+            Err(std::fs::TryLockError::Error(e)) => {
+                return Err(format!("lock {}: {}", path.display(), e))
+            }
+        }
+        if let Some(pid) = read_pid(&mut file) {
+            warn!(
+                "took over the lock of clip-agent pid {}, which is gone",
+                pid
+            );
+        }
+        let pid = process::id();
+        write_pid(&mut file, Some(pid)).map_err(|e| format!("write {}: {}", path.display(), e))?;
+        Ok(InstanceLock { file })
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        // The file stays: removing it could let two agents lock different files at `path`.
+        let _ = write_pid(&mut self.file, None);
+    }
+}
+
+/// The pid of the agent holding the lock at `path`, if one does. None for a missing or stale file.
+pub fn running_pid(path: &Path) -> Option<u32> {
+    let mut file = File::open(path).ok()?;
+    match file.try_lock_shared() {
+        Ok(()) => None,
+        // Held, but the pid may not be written yet.
+        Err(std::fs::TryLockError::WouldBlock) => Some(read_pid(&mut file).unwrap_or(0)),
+        Err(std::fs::TryLockError::Error(_)) => None,
+    }
+}
+
+/// Waits up to `timeout` for the agent holding the lock at `path` to exit.
+pub fn wait_for_exit(path: &Path, timeout: Duration) -> bool {
+    let start = Instant::now();
+    while running_pid(path).is_some() {
+        if start.elapsed() >= timeout {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    true
+}
+
+#[cfg(unix)]
+fn open(path: &Path) -> Result<File, String> {
+    use std::os::unix::fs::OpenOptionsExt;
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o600)
+        .open(path)
+        .map_err(|e| format!("open {}: {}", path.display(), e))
+}
+
+#[cfg(not(unix))]
+fn open(path: &Path) -> Result<File, String> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .map_err(|e| format!("open {}: {}", path.display(), e))
+}
+
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut text = String::new();
+    file.seek(SeekFrom::Start(0)).ok()?;
+    file.read_to_string(&mut text).ok()?;
+    text.trim().parse().ok()
+}
+
+fn write_pid(file: &mut File, pid: Option<u32>) -> std::io::Result<()> {
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    if let Some(pid) = pid {
+        writeln!(file, "{}", pid)?;
+    }
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_second_agent_is_refused_while_the_first_holds_the_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("agent.pid");
+        assert_eq!(running_pid(&path), None);
+
+        let lock = InstanceLock::acquire(&path).unwrap();
+        assert_eq!(running_pid(&path), Some(process::id()));
+        let err = InstanceLock::acquire(&path).err().unwrap();
+        assert_eq!(
+            err,
+            format!(
+                "another clip-agent is running (pid {}); stop it with `clip stop`",
+                process::id()
+            )
+        );
+
+        drop(lock);
+        assert_eq!(running_pid(&path), None);
+        assert!(wait_for_exit(&path, Duration::ZERO));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+        InstanceLock::acquire(&path).unwrap();
+    }
+
+    #[test]
+    fn takes_over_a_lock_left_by_a_crashed_agent() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("agent.pid");
+        std::fs::write(&path, "999999\n").unwrap();
+        assert_eq!(running_pid(&path), None, "nobody holds the lock");
+
+        let _lock = InstanceLock::acquire(&path).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("{}\n", process::id())
+        );
+        assert!(!wait_for_exit(&path, Duration::from_millis(100)));
+    }
+}
@@ -0,0 +1,262 @@
+//! What the agent does with one signed message, whichever transport (`udp`, `unix`) it came in
+//! on: verify, decode, then answer hellos, ack the UI's chosen/cancel (dropping resent copies),
+//! turn responses, triggers and the UI's hellos and heartbeats into state machine events, and
+//! hand requests back to the transport, which answers them (`answer_request`) where waiting on
+//! the state machine holds up nothing else.
+
+use super::request::{self, to_request};
+use crate::state_machine::Event;
+use clip_config::auth::Secret;
+use clip_protocol::{
+    decode, DecodeError, Dedupe, FromAgent, Mode, SlotRequest, SlotResponse, ToAgent,
+    MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+use std::fmt::Display;
+use std::sync::mpsc::Sender;
+use tracing::{debug, info, warn};
+
+/// Outcome of `handle`.
+#[derive(Debug, PartialEq)]
+pub enum Inbound {
+    /// Send this back to the sender.
+    Reply(FromAgent),
+    /// A slot request; send the sender `answer_request`'s response.
+    Request(SlotRequest),
+    /// The sender acked our message with this id.
+    Acked(u64),
+    /// Nothing to send back.
+    Done,
+    /// The state machine is gone; the listener can stop.
+    Stopped,
+}
+
+/// Handles one message `text` that came in over `transport` ("udp" or "unix") from `from` (for
+/// logs). `seen` remembers the UI's message ids.
+pub fn handle(
+    secret: &Secret,
+    text: &str,
+    tx: &Sender<Event>,
+    seen: &mut Dedupe,
+    transport: &'static str,
+    from: impl Display,
+) -> Inbound {
+    let line = match secret.open(text.trim()) {
+        Ok(msg) => msg,
+        Err(e) => {
+            warn!("ipc: rejected message from {}: {}", from, e);
+            return Inbound::Done;
+        }
+    };
+    match decode::<ToAgent>(&line) {
+        Ok(ToAgent::Request(request)) => Inbound::Request(request),
+        Ok(ToAgent::Hello { client }) => {
+            info!(
+                "ipc: hello from {} at {} (protocol {})",
+                client, from, PROTOCOL_VERSION
+            );
+            if tx.send(Event::UiSeen { client, transport }).is_err() {
+                return Inbound::Stopped;
+            }
+            Inbound::Reply(FromAgent::Welcome {
+                agent: env!("CARGO_PKG_VERSION").to_string(),
+            })
+        }
+        Ok(ToAgent::Heartbeat { client }) => match tx.send(Event::UiSeen { client, transport }) {
+            Ok(()) => Inbound::Done,
+            Err(_) => Inbound::Stopped,
+        },
+        Ok(ToAgent::Ack { msg_id }) => Inbound::Acked(msg_id),
+        Ok(message) => {
+            let msg_id = message.msg_id();
+            if let Some(id) = msg_id {
+                if !seen.first_time(id) {
+                    debug!("ipc: dropped resent message {} from {}", id, from);
+                    return Inbound::Reply(FromAgent::Ack { msg_id: id });
+                }
+            }
+            if let Some(ev) = to_event(message) {
+                if tx.send(ev).is_err() {
+                    return Inbound::Stopped;
+                }
+            }
+            match msg_id {
+                Some(msg_id) => Inbound::Reply(FromAgent::Ack { msg_id }),
+                None => Inbound::Done,
+            }
+        }
+        Err(e @ DecodeError::UnsupportedVersion(_)) => {
+            warn!("ipc: message from {}: {}", from, e);
+            Inbound::Reply(FromAgent::Unsupported {
+                min_version: MIN_PROTOCOL_VERSION,
+                max_version: PROTOCOL_VERSION,
+            })
+        }
+        Err(e) => {
+            warn!("ipc: ignored message from {}: {}", from, e);
+            Inbound::Done
+        }
+    }
+}
+
+/// Runs a request message through the state machine and returns the response. Blocks for up to
+/// `request::REQUEST_TIMEOUT`.
+pub fn answer_request(message: SlotRequest, tx: &Sender<Event>) -> SlotResponse {
+    let result = to_request(&message).and_then(|request| {
+        info!("ipc: request {:?}", request);
+        request::call(tx, request)
+    });
+    SlotResponse::new(message.id, result)
+}
+
+/// The state machine event for a UI response or trigger.
+fn to_event(message: ToAgent) -> Option<Event> {
+    match message {
+        ToAgent::Trigger { action } => {
+            info!("ipc: received trigger {}", action.as_str());
+            Some(match action {
+                Mode::Save => Event::SaveTrigger,
+                Mode::Paste => Event::PasteTrigger,
+            })
+        }
+        ToAgent::Chosen { token, slot, .. } if (1..=6).contains(&slot) => {
+            info!(
+                "ipc: received from UI -> ChooserChosen token={} slot={}",
+                token, slot
+            );
+            Some(Event::ChooserChosen {
+                token,
+                slot_num: slot,
+            })
+        }
+        ToAgent::Cancel { token, reason, .. } => {
+            info!(
+                "ipc: received from UI -> ChooserCancel token={} reason={}",
+                token, reason
+            );
+            Some(Event::ChooserCancel { token, reason })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clip_protocol::{encode, Op};
+    use serde_json::{json, Value};
+    use std::sync::mpsc;
+    use std::thread;
+
+    #[test]
+    fn answers_requests_through_the_state_machine() {
+        let (tx, rx) = mpsc::channel();
+        let agent = thread::spawn(move || {
+            if let Ok(Event::Request(request, reply)) = rx.recv() {
+                assert_eq!(request, crate::ipc::request::Request::List);
+                reply.send(Ok(json!([]))).unwrap();
+            }
+        });
+        let list = SlotRequest {
+            id: json!("a"),
+            op: Op::List,
+            slot: None,
+            text: None,
+            entry: None,
+        };
+        let response = answer_request(list, &tx);
+        assert_eq!(response, SlotResponse::new(json!("a"), Ok(json!([]))));
+        agent.join().unwrap();
+
+        let bad = SlotRequest {
+            id: Value::Null,
+            op: Op::Get,
+            slot: Some(clip_protocol::SlotRef::Number(9)),
+            text: None,
+            entry: None,
+        };
+        assert!(answer_request(bad, &tx)
+            .into_result()
+            .unwrap_err()
+            .contains("invalid slot 9"));
+    }
+
+    #[test]
+    fn acks_ui_messages_once_and_passes_acks_and_heartbeats_through() {
+        let secret = Secret::generate().unwrap();
+        let (tx, rx) = mpsc::channel();
+        let mut seen = Dedupe::default();
+        let mut handle = |msg: &ToAgent| {
+            handle(
+                &secret,
+                &secret.sign(&encode(msg)),
+                &tx,
+                &mut seen,
+                "udp",
+                "test",
+            )
+        };
+
+        let chosen = ToAgent::Chosen {
+            token: "t".into(),
+            slot: 2,
+            msg_id: Some(9),
+        };
+        assert_eq!(
+            handle(&chosen),
+            Inbound::Reply(FromAgent::Ack { msg_id: 9 })
+        );
+        assert_eq!(
+            handle(&chosen),
+            Inbound::Reply(FromAgent::Ack { msg_id: 9 })
+        );
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(Event::ChooserChosen { slot_num: 2, .. })
+        ));
+        assert!(rx.try_recv().is_err(), "the resent chosen is handled once");
+
+        assert_eq!(handle(&ToAgent::Ack { msg_id: 4 }), Inbound::Acked(4));
+        assert_eq!(
+            handle(&ToAgent::Heartbeat {
+                client: "clip-ui".into()
+            }),
+            Inbound::Done
+        );
+        assert!(
+            matches!(rx.try_recv(), Ok(Event::UiSeen { client, transport: "udp" }) if client == "clip-ui")
+        );
+        assert_eq!(
+            handle(&ToAgent::Trigger { action: Mode::Save }),
+            Inbound::Done
+        );
+        assert!(matches!(rx.try_recv(), Ok(Event::SaveTrigger)));
+        drop(rx);
+        assert_eq!(
+            handle(&ToAgent::Trigger { action: Mode::Save }),
+            Inbound::Stopped
+        );
+    }
+
+    #[test]
+    fn maps_ui_responses_and_triggers_to_events() {
+        let event = |line: &str| to_event(decode(line).unwrap());
+        assert!(matches!(
+            event(r#"{"type":"chosen","token":"t1","slot":3}"#),
+            Some(Event::ChooserChosen { slot_num: 3, .. })
+        ));
+        assert!(event(r#"{"type":"chosen","token":"t1","slot":7}"#).is_none());
+        assert!(matches!(
+            event(r#"{"type":"cancel","token":"t1"}"#),
+            Some(Event::ChooserCancel { reason, .. }) if reason == "timeout"
+        ));
+        assert!(matches!(
+            event(r#"{"type":"trigger","action":"save"}"#),
+            Some(Event::SaveTrigger)
+        ));
+        assert!(matches!(
+            event(r#"{"type":"trigger","action":"paste"}"#),
+            Some(Event::PasteTrigger)
+        ));
+        assert!(decode::<ToAgent>(r#"{"type":"trigger","action":"copy"}"#).is_err());
+    }
+}
@@ -1,3 +1,235 @@
-//! IPC with chooser UI over localhost UDP.
+//! IPC with chooser UI over localhost UDP or a Unix socket (`ipc.transport`), slot requests
+//! from scripts, and the JSON-RPC control API for other tools (`rpc`).
 
+pub mod inbound;
+pub mod request;
+#[cfg(unix)]
+pub mod rpc;
 pub mod udp;
+#[cfg(unix)]
+pub mod unix;
+
+use crate::state_machine::Event;
+use clip_config::auth::Secret;
+use clip_config::IpcConfig;
+use clip_protocol::{
+    decode, Anchor, ChooserKeys, FromAgent, MessageIds, Mode, Op, SlotRequest, SlotResponse,
+    PROTOCOL_VERSION,
+};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use tracing::{info, warn};
+
+/// Sends a save or paste trigger to the running agent: over its socket when `ipc.transport` is
+/// "unix" and the socket exists, else over UDP.
+pub fn send_trigger(ipc: &IpcConfig, secret: &Secret, action: Mode) -> Result<(), String> {
+    #[cfg(unix)]
+    if ipc.transport == clip_config::IpcTransport::Unix
+        && unix::send_trigger(&clip_config::agent_socket_path()?, secret, action)?
+    {
+        return Ok(());
+    }
+    udp::send_trigger(ipc, secret, action)
+}
+
+/// Sends one request to the running agent, choosing the transport like `send_trigger`. None if
+/// no agent answers on either.
+pub fn send_request(
+    ipc: &IpcConfig,
+    secret: &Secret,
+    request: &SlotRequest,
+) -> Result<Option<SlotResponse>, String> {
+    #[cfg(unix)]
+    if ipc.transport == clip_config::IpcTransport::Unix {
+        if let Some(response) =
+            unix::send_request(&clip_config::agent_socket_path()?, secret, request)?
+        {
+            return Ok(Some(response));
+        }
+    }
+    udp::send_request(ipc, secret, request)
+}
+
+/// Whether an agent already answers a status request signed with `secret`, on its socket (with
+/// either transport) or on `ipc.agent_port`.
+pub fn agent_answers(ipc: &IpcConfig, secret: &Secret) -> bool {
+    let status = SlotRequest {
+        id: serde_json::Value::Null,
+        op: Op::Status,
+        slot: None,
+        text: None,
+        entry: None,
+    };
+    #[cfg(unix)]
+    if let Ok(path) = clip_config::agent_socket_path() {
+        if matches!(unix::send_request(&path, secret, &status), Ok(Some(_))) {
+            return true;
+        }
+    }
+    matches!(udp::send_request(ipc, secret, &status), Ok(Some(_)))
+}
+
+/// Verifies and decodes the agent's answer to a request.
+fn open_response(secret: &Secret, text: &str) -> Result<SlotResponse, String> {
+    let response = secret
+        .open(text)
+        .map_err(|e| format!("ipc: response rejected: {}", e))?;
+    match decode::<FromAgent>(&response).map_err(|e| format!("ipc: {}", e))? {
+        FromAgent::Response(response) => Ok(response),
+        FromAgent::Unsupported {
+            min_version,
+            max_version,
+        } => Err(format!(
+            "ipc: the agent speaks protocol {}..={}, this is {}",
+            min_version, max_version, PROTOCOL_VERSION
+        )),
+        other => Err(format!("ipc: unexpected reply {:?}", other)),
+    }
+}
+
+/// Where the state machine shows and hides the chooser.
+pub trait ChooserUi: Send {
+    /// `keys` are the bindings that pick a slot or cancel in this chooser, for the UI to match.
+    fn show(
+        &mut self,
+        ipc: &IpcConfig,
+        mode: &str,
+        token: &str,
+        timeout_ms: u64,
+        keys: ChooserKeys,
+    );
+    fn hide(&mut self, ipc: &IpcConfig, token: &str);
+}
+
+/// The Tauri chooser, reached with messages signed by the agent's secret. Messages go out in
+/// order on one thread, each sent until the UI acks it; a show that is never acked becomes
+/// `Event::UiUnreachable` on the state machine's channel.
+pub struct RemoteChooserUi {
+    outbox: Sender<(u16, FromAgent)>,
+    ids: MessageIds,
+}
+
+impl RemoteChooserUi {
+    /// Over UDP to `ipc.ui_port` (`udp::send_acked`).
+    pub fn udp(secret: Secret, events: Sender<Event>) -> Self {
+        Self::spawn(events, move |port, msg| udp::send_acked(port, &secret, msg))
+    }
+
+    /// Down the UI's socket connection while it has one (`unix::UiLink`), else over UDP.
+    #[cfg(unix)]
+    pub fn unix(secret: Secret, events: Sender<Event>, link: unix::UiLink) -> Self {
+        Self::spawn(events, move |port, msg| {
+            if link.is_connected() {
+                link.send_acked(&secret, msg)
+            } else {
+                udp::send_acked(port, &secret, msg)
+            }
+        })
+    }
+
+    fn spawn(
+        events: Sender<Event>,
+        send: impl Fn(u16, &FromAgent) -> Result<(), String> + Send + 'static,
+    ) -> Self {
+        let (outbox, rx) = mpsc::channel::<(u16, FromAgent)>();
+        thread::spawn(move || {
+            for (port, msg) in rx {
+                if let Err(e) = send(port, &msg) {
+                    match msg {
+                        FromAgent::Show { token, .. } => {
+                            warn!("ipc: UI did not acknowledge show (token={}): {}", token, e);
+                            let _ = events.send(Event::UiUnreachable { token, error: e });
+                        }
+                        other => warn!("ipc: UI did not acknowledge {:?}: {}", other, e),
+                    }
+                }
+            }
+        });
+        Self {
+            outbox,
+            ids: MessageIds::new(),
+        }
+    }
+}
+
+impl ChooserUi for RemoteChooserUi {
+    fn show(
+        &mut self,
+        ipc: &IpcConfig,
+        mode: &str,
+        token: &str,
+        timeout_ms: u64,
+        keys: ChooserKeys,
+    ) {
+        let mode = match mode.parse::<Mode>() {
+            Ok(m) => m,
+            Err(e) => {
+                warn!("ipc: show: {}", e);
+                return;
+            }
+        };
+        info!("ipc: show (mode={}, token={})", mode.as_str(), token);
+        let msg_id = Some(self.ids.next());
+        let msg = FromAgent::Show {
+            mode,
+            token: token.to_string(),
+            timeout_ms,
+            anchor: Anchor::Mouse,
+            keys: Some(keys),
+            msg_id,
+        };
+        let _ = self.outbox.send((ipc.ui_port, msg));
+    }
+
+    fn hide(&mut self, ipc: &IpcConfig, token: &str) {
+        let msg = FromAgent::Hide {
+            token: token.to_string(),
+            msg_id: Some(self.ids.next()),
+        };
+        let _ = self.outbox.send((ipc.ui_port, msg));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    #[test]
+    fn unacknowledged_show_is_reported() {
+        let port = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let ipc = IpcConfig {
+            ui_port: port,
+            ..IpcConfig::default()
+        };
+        let (tx, rx) = mpsc::channel();
+        let mut ui = RemoteChooserUi::udp(Secret::generate().unwrap(), tx);
+        ui.show(
+            &ipc,
+            "save",
+            "t1",
+            800,
+            ChooserKeys {
+                slots: vec![],
+                cancel: "escape".into(),
+            },
+        );
+        ui.hide(&ipc, "t1");
+        match rx.recv_timeout(Duration::from_secs(5)) {
+            Ok(Event::UiUnreachable { token, error }) => {
+                assert_eq!(token, "t1");
+                assert!(error.contains("nothing listens"), "{}", error);
+            }
+            other => panic!("expected UiUnreachable, got {:?}", other),
+        }
+        assert!(
+            rx.recv_timeout(Duration::from_millis(100)).is_err(),
+            "a missed hide is only logged"
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! Requests scripts send the running agent: one signed `clip_protocol::SlotRequest` datagram
+//! (`clip_config::auth`) to `ipc.agent_port`, answered with one signed `SlotResponse` back to
+//! the sender. `clip-agent request` does the signing for scripts.
+//!
+//! ```text
+//! {"type":"request","id":1,"op":"save","slot":1,"text":"hello"}
+//! {"type":"response","id":1,"ok":true,"result":{"slot":1,"id":"J","size":5}}
+//! ```
+//!
+//! `op` is `list`, `get`, `save`, `clear`, `paste`, `copy`, `status`, `stop`, `history` or
+//! `promote`; `slot` is a number 1..6 or a slot id (`"J"`). `save` without `text` saves the
+//! current clipboard, `copy` puts the slot on the clipboard without pasting and `stop` shuts the
+//! agent down after replying. `history` lists the clipboard history and `promote` saves history
+//! entry `entry` into `slot`. Failures reply with `"ok":false` and an `"error"` message.
+
+use crate::keys::SlotId;
+use crate::payload::ClipPayload;
+use crate::state_machine::Event;
+use clip_protocol::{Op, SlotRef, SlotRequest};
+use serde_json::Value;
+use std::sync::mpsc::{self, Sender};
+use std::time::Duration;
+
+/// How long a request waits for the state machine before the caller gets an error.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// One operation on the slots.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    List,
+    Get {
+        slot: SlotId,
+    },
+    /// Saves `content`, or the clipboard when None.
+    Save {
+        slot: SlotId,
+        content: Option<ClipPayload>,
+    },
+    Clear {
+        slot: SlotId,
+    },
+    Paste {
+        slot: SlotId,
+    },
+    Copy {
+        slot: SlotId,
+    },
+    /// The agent's version, chooser state and chooser UI.
+    Status,
+    /// Shut the agent down.
+    Stop,
+    /// Clipboard history, newest first.
+    History,
+    /// Saves a history entry into a slot.
+    Promote {
+        entry: i64,
+        slot: SlotId,
+    },
+}
+
+/// Where the state machine sends a request's result.
+pub type Reply = Sender<Result<Value, String>>;
+
+/// Runs `request` through the state machine behind `tx` and waits for its result.
+pub fn call(tx: &Sender<Event>, request: Request) -> Result<Value, String> {
+    let (reply, rx) = mpsc::channel();
+    tx.send(Event::Request(request, reply))
+        .map_err(|_| "agent is stopping".to_string())?;
+    rx.recv_timeout(REQUEST_TIMEOUT)
+        .map_err(|_| "agent did not answer".to_string())?
+}
+
+/// Parses the fields of a request (`op`, `slot`, `text`) from JSON.
+pub fn parse_request(v: &Value) -> Result<Request, String> {
+    to_request(&serde_json::from_value(v.clone()).map_err(|e| e.to_string())?)
+}
+
+/// Checks a request message's slot and builds the `Request`.
+pub fn to_request(message: &SlotRequest) -> Result<Request, String> {
+    let slot = || parse_slot(message.slot.as_ref().ok_or("missing \"slot\"")?);
+    Ok(match message.op {
+        Op::List => Request::List,
+        Op::Status => Request::Status,
+        Op::Stop => Request::Stop,
+        Op::Get => Request::Get { slot: slot()? },
+        Op::Save => Request::Save {
+            slot: slot()?,
+            content: message.text.as_deref().map(ClipPayload::from_text),
+        },
+        Op::Clear => Request::Clear { slot: slot()? },
+        Op::Paste => Request::Paste { slot: slot()? },
+        Op::Copy => Request::Copy { slot: slot()? },
+        Op::History => Request::History,
+        Op::Promote => Request::Promote {
+            entry: message.entry.ok_or("missing \"entry\"")?,
+            slot: slot()?,
+        },
+    })
+}
+
+/// A slot given as its chooser number (1..6) or its id ("J").
+fn parse_slot(slot: &SlotRef) -> Result<SlotId, String> {
+    let id = match slot {
+        SlotRef::Number(n) => u8::try_from(*n).ok().and_then(SlotId::from_slot_num),
+        SlotRef::Name(s) => SlotId::from_label(&s.to_uppercase())
+            .or_else(|| s.parse().ok().and_then(SlotId::from_slot_num)),
+    };
+    id.ok_or_else(|| format!("invalid slot {} (expected 1..6 or J/K/L/U/I/O)", slot))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ops_and_slots() {
+        let parse = |s: &str| parse_request(&serde_json::from_str(s).unwrap());
+        assert_eq!(parse(r#"{"op":"list"}"#), Ok(Request::List));
+        assert_eq!(parse(r#"{"op":"status"}"#), Ok(Request::Status));
+        assert_eq!(parse(r#"{"op":"stop"}"#), Ok(Request::Stop));
+        assert_eq!(
+            parse(r#"{"op":"get","slot":2}"#),
+            Ok(Request::Get { slot: SlotId::K })
+        );
+        assert_eq!(
+            parse(r#"{"op":"clear","slot":"o"}"#),
+            Ok(Request::Clear { slot: SlotId::O })
+        );
+        assert_eq!(
+            parse(r#"{"op":"paste","slot":"3"}"#),
+            Ok(Request::Paste { slot: SlotId::L })
+        );
+        assert_eq!(
+            parse(r#"{"op":"save","slot":"J","text":"hi"}"#),
+            Ok(Request::Save {
+                slot: SlotId::J,
+                content: Some(ClipPayload::from_text("hi"))
+            })
+        );
+        assert_eq!(
+            parse(r#"{"op":"save","slot":1}"#),
+            Ok(Request::Save {
+                slot: SlotId::J,
+                content: None
+            })
+        );
+        assert!(parse(r#"{"op":"get","slot":7}"#)
+            .unwrap_err()
+            .contains("invalid slot 7"));
+        assert!(parse(r#"{"op":"get"}"#).is_err());
+        assert!(parse(r#"{"op":"save","slot":1,"text":5}"#).is_err());
+        assert!(parse(r#"{"op":"get","slot":true}"#).is_err());
+        assert_eq!(
+            parse(r#"{"op":"copy","slot":6}"#),
+            Ok(Request::Copy { slot: SlotId::O })
+        );
+        assert_eq!(parse(r#"{"op":"history"}"#), Ok(Request::History));
+        assert_eq!(
+            parse(r#"{"op":"promote","entry":4,"slot":"K"}"#),
+            Ok(Request::Promote {
+                entry: 4,
+                slot: SlotId::K
+            })
+        );
+        assert!(parse(r#"{"op":"promote","slot":"K"}"#)
+            .unwrap_err()
+            .contains("missing \"entry\""));
+        assert!(parse(r#"{"op":"move","slot":1}"#)
+            .unwrap_err()
+            .contains("unknown variant `move`"));
+        assert!(parse(r#"{"slot":1}"#)
+            .unwrap_err()
+            .contains("missing field `op`"));
+    }
+}
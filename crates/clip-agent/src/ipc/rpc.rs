@@ -0,0 +1,323 @@
+//! JSON-RPC 2.0 control API for editors, launchers and scripts, on a per-user Unix socket
+//! (`control.sock` in `clip_config::runtime_dir()`). One message per line in each direction;
+//! batches and notifications work as the spec says.
+//!
+//! Methods take params by name, or positionally as `[slot, text]`; `slot` is 1..6 or a slot id
+//! ("J"). `list_slots`, `get_slot`, `set_slot`, `clear_slot`, `paste_slot`, `copy_slot` and
+//! `get_status` are the `ipc::request` ops; `subscribe_events` makes the connection receive
+//! `event` notifications from the state machine. The README has the full schema.
+
+use super::request::{self, parse_request};
+use crate::state_machine::Event;
+use serde_json::{json, Map, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tracing::{debug, info, warn};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// The call was valid but the agent could not do it (empty slot, empty clipboard, …).
+const REQUEST_FAILED: i64 = -32000;
+
+/// Names of positional params, in order.
+const POSITIONAL: [&str; 2] = ["slot", "text"];
+
+/// Where the agent listens.
+pub fn socket_path() -> Result<PathBuf, String> {
+    Ok(clip_config::runtime_dir()?.join("control.sock"))
+}
+
+/// Listens on `path` and serves each connection on its own thread. A socket left behind by an
+/// agent that is gone is replaced; one that still answers is an error.
+pub fn start_control_server(path: &Path, tx: Sender<Event>) -> Result<(), String> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(format!(
+                "{} is in use (is another agent running?)",
+                path.display()
+            ));
+        }
+        std::fs::remove_file(path)
+            .map_err(|e| format!("remove stale {}: {}", path.display(), e))?;
+    }
+    let listener =
+        UnixListener::bind(path).map_err(|e| format!("bind {}: {}", path.display(), e))?;
+    info!("rpc: listening on {}", path.display());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let tx = tx.clone();
+                    thread::spawn(move || serve(stream, tx));
+                }
+                Err(e) => warn!("rpc: accept failed: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Answers one connection until the client hangs up.
+fn serve(stream: UnixStream, tx: Sender<Event>) {
+    let writer = match stream.try_clone() {
+        Ok(w) => Arc::new(Mutex::new(w)),
+        Err(e) => {
+            warn!("rpc: {}", e);
+            return;
+        }
+    };
+    let mut subscribed = false;
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else { break };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut subscribe = false;
+        if let Some(response) = handle_line(&line, &tx, &mut subscribe) {
+            if write_line(&writer, &response).is_err() {
+                break;
+            }
+        }
+        // After the response, so the client sees the result before the first event.
+        if subscribe && !subscribed {
+            subscribed = true;
+            forward_events(&tx, writer.clone());
+        }
+    }
+    debug!("rpc: client disconnected");
+}
+
+fn write_line(writer: &Mutex<UnixStream>, message: &Value) -> std::io::Result<()> {
+    let mut stream = writer.lock().unwrap_or_else(|p| p.into_inner());
+    writeln!(stream, "{}", message)
+}
+
+/// Subscribes to state machine events and writes them to the client as notifications until
+/// the connection closes.
+fn forward_events(tx: &Sender<Event>, writer: Arc<Mutex<UnixStream>>) {
+    let (events_tx, events) = mpsc::channel();
+    if tx.send(Event::Subscribe(events_tx)).is_err() {
+        return;
+    }
+    thread::spawn(move || {
+        for event in events {
+            let notification = json!({"jsonrpc": "2.0", "method": "event", "params": event});
+            if write_line(&writer, &notification).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// Response to one line: a single call or a batch. None when there is nothing to send back
+/// (only notifications). Sets `subscribe` if a call asked for events.
+fn handle_line(line: &str, tx: &Sender<Event>, subscribe: &mut bool) -> Option<Value> {
+    let message: Value = match serde_json::from_str(line) {
+        Ok(v) => v,
+        Err(e) => {
+            return Some(error_response(
+                Value::Null,
+                PARSE_ERROR,
+                &format!("parse error: {}", e),
+            ))
+        }
+    };
+    match message {
+        Value::Array(calls) if calls.is_empty() => {
+            Some(error_response(Value::Null, INVALID_REQUEST, "empty batch"))
+        }
+        Value::Array(calls) => {
+            let responses: Vec<Value> = calls
+                .iter()
+                .filter_map(|c| handle_call(c, tx, subscribe))
+                .collect();
+            (!responses.is_empty()).then_some(Value::Array(responses))
+        }
+        call => handle_call(&call, tx, subscribe),
+    }
+}
+
+/// Response to one call; None for a notification (no `id`).
+fn handle_call(call: &Value, tx: &Sender<Event>, subscribe: &mut bool) -> Option<Value> {
+    let method = call.get("method").and_then(Value::as_str);
+    let (Some(method), Some("2.0")) = (method, call.get("jsonrpc").and_then(Value::as_str)) else {
+        let id = call.get("id").cloned().unwrap_or(Value::Null);
+        return Some(error_response(id, INVALID_REQUEST, "invalid request"));
+    };
+    let result = dispatch(method, call.get("params"), tx, subscribe);
+    let id = call.get("id")?.clone();
+    Some(match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err((code, message)) => error_response(id, code, &message),
+    })
+}
+
+fn dispatch(
+    method: &str,
+    params: Option<&Value>,
+    tx: &Sender<Event>,
+    subscribe: &mut bool,
+) -> Result<Value, (i64, String)> {
+    let op = match method {
+        "list_slots" => "list",
+        "get_slot" => "get",
+        "set_slot" => "save",
+        "clear_slot" => "clear",
+        "paste_slot" => "paste",
+        "copy_slot" => "copy",
+        "get_status" => "status",
+        "subscribe_events" => {
+            *subscribe = true;
+            return Ok(json!({"subscribed": true}));
+        }
+        other => return Err((METHOD_NOT_FOUND, format!("method not found: {}", other))),
+    };
+    let mut fields = named_params(params).map_err(|e| (INVALID_PARAMS, e))?;
+    fields.insert("op".to_string(), op.into());
+    let request = parse_request(&Value::Object(fields)).map_err(|e| (INVALID_PARAMS, e))?;
+    debug!("rpc: {} {:?}", method, request);
+    request::call(tx, request).map_err(|e| (REQUEST_FAILED, e))
+}
+
+/// Params as named fields; positional ones are named from `POSITIONAL`.
+fn named_params(params: Option<&Value>) -> Result<Map<String, Value>, String> {
+    match params {
+        None | Some(Value::Null) => Ok(Map::new()),
+        Some(Value::Object(fields)) => Ok(fields.clone()),
+        Some(Value::Array(values)) if values.len() <= POSITIONAL.len() => Ok(POSITIONAL
+            .iter()
+            .map(|n| n.to_string())
+            .zip(values.iter().cloned())
+            .collect()),
+        Some(Value::Array(_)) => Err(format!("at most {} positional params", POSITIONAL.len())),
+        Some(_) => Err("params must be an object or an array".to_string()),
+    }
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::request::Request;
+    use crate::keys::SlotId;
+
+    /// Answers requests like a state machine with one filled slot.
+    fn fake_agent() -> Sender<Event> {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for event in rx {
+                if let Event::Request(request, reply) = event {
+                    let _ = reply.send(match request {
+                        Request::Get { slot: SlotId::J } => Ok(json!({"text": "hi"})),
+                        Request::Get { .. } => Ok(Value::Null),
+                        Request::Paste { slot } => Err(format!("slot {} is empty", slot.label())),
+                        other => Ok(json!(format!("{:?}", other))),
+                    });
+                }
+            }
+        });
+        tx
+    }
+
+    fn answer(tx: &Sender<Event>, line: &str) -> Option<Value> {
+        handle_line(line, tx, &mut false)
+    }
+
+    #[test]
+    fn maps_methods_params_and_errors() {
+        let tx = fake_agent();
+        assert_eq!(
+            answer(
+                &tx,
+                r#"{"jsonrpc":"2.0","id":1,"method":"get_slot","params":{"slot":"j"}}"#
+            ),
+            Some(json!({"jsonrpc": "2.0", "id": 1, "result": {"text": "hi"}}))
+        );
+        let positional = answer(
+            &tx,
+            r#"{"jsonrpc":"2.0","id":2,"method":"set_slot","params":[2,"x"]}"#,
+        );
+        assert_eq!(
+            positional.unwrap()["result"],
+            format!(
+                "{:?}",
+                Request::Save {
+                    slot: SlotId::K,
+                    content: Some(crate::payload::ClipPayload::from_text("x"))
+                }
+            )
+        );
+
+        let status = answer(&tx, r#"{"jsonrpc":"2.0","id":8,"method":"get_status"}"#);
+        assert_eq!(status.unwrap()["result"], format!("{:?}", Request::Status));
+
+        let code = |line: &str| {
+            answer(&tx, line).unwrap()["error"]["code"]
+                .as_i64()
+                .unwrap()
+        };
+        assert_eq!(code("{"), PARSE_ERROR);
+        assert_eq!(code(r#"{"id":3,"method":"list_slots"}"#), INVALID_REQUEST);
+        assert_eq!(code("[]"), INVALID_REQUEST);
+        assert_eq!(
+            code(r#"{"jsonrpc":"2.0","id":4,"method":"nope"}"#),
+            METHOD_NOT_FOUND
+        );
+        assert_eq!(
+            code(r#"{"jsonrpc":"2.0","id":5,"method":"get_slot","params":{"slot":9}}"#),
+            INVALID_PARAMS
+        );
+        assert_eq!(
+            code(r#"{"jsonrpc":"2.0","id":6,"method":"get_slot","params":"J"}"#),
+            INVALID_PARAMS
+        );
+        let failed = answer(
+            &tx,
+            r#"{"jsonrpc":"2.0","id":7,"method":"paste_slot","params":{"slot":1}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            failed["error"],
+            json!({"code": REQUEST_FAILED, "message": "slot J is empty"})
+        );
+    }
+
+    #[test]
+    fn batches_skip_notifications() {
+        let tx = fake_agent();
+        assert_eq!(
+            answer(
+                &tx,
+                r#"{"jsonrpc":"2.0","method":"clear_slot","params":{"slot":1}}"#
+            ),
+            None
+        );
+        let batch = answer(
+            &tx,
+            r#"[{"jsonrpc":"2.0","method":"clear_slot","params":[1]},
+                {"jsonrpc":"2.0","id":"b","method":"get_slot","params":[3]}]"#,
+        );
+        assert_eq!(
+            batch,
+            Some(json!([{"jsonrpc": "2.0", "id": "b", "result": null}]))
+        );
+
+        let mut subscribe = false;
+        let reply = handle_line(
+            r#"{"jsonrpc":"2.0","id":9,"method":"subscribe_events"}"#,
+            &tx,
+            &mut subscribe,
+        );
+        assert!(subscribe);
+        assert_eq!(reply.unwrap()["result"], json!({"subscribed": true}));
+    }
+}
@@ -1,64 +1,167 @@
-//! UDP IPC: agent sends show/hide to UI:45454; agent listens on 45455 for chosen/cancel.
+//! UDP IPC: agent sends show/hide to UI (`ipc.ui_port`, default 45454); agent listens on
+//! `ipc.agent_port` (default 45455) for chosen/cancel, triggers from `clip-agent trigger`, and
+//! slot requests from scripts (`request`), which are answered to the sender.
+//!
+//! Messages are `clip_protocol` types. Every datagram in both directions is signed with the
+//! agent's `Secret` (`clip_config::auth`); the listener logs and drops anything that does not
+//! verify or decode, and answers a UI's `hello` with `welcome` (or `unsupported`).
+//!
+//! Show and hide are resent until the UI acks them (`send_acked`, used by `RemoteChooserUi`);
+//! the listener acks the UI's chosen/cancel and drops resent copies.
 
+use super::inbound::{self, Inbound};
+use super::open_response;
+use super::request::REQUEST_TIMEOUT;
 use crate::state_machine::Event;
+use clip_config::auth::Secret;
+use clip_config::IpcConfig;
+use clip_protocol::{
+    decode, encode, Dedupe, FromAgent, Mode, SlotRequest, SlotResponse, ToAgent, ACK_TIMEOUT,
+    SEND_ATTEMPTS,
+};
+use std::io::ErrorKind;
+use std::net::SocketAddr;
 use std::net::UdpSocket;
-use std::sync::mpsc::Sender;
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
 use std::thread;
-use tracing::{error, info, warn};
-
-const UI_PORT: u16 = 45454;
-const AGENT_PORT: u16 = 45455;
-const BIND_ADDR: &str = "127.0.0.1";
-
-/// Send show chooser to UI. Best-effort.
-pub fn send_show(mode: &str, token: &str, timeout_ms: u64) {
-    let msg = format!(
-        r#"{{"type":"show","mode":"{}","token":"{}","timeout_ms":{},"anchor":"mouse"}}"#,
-        mode, token, timeout_ms
-    );
-    info!("ipc: send_show -> {}:{} (mode={}, token={})", BIND_ADDR, UI_PORT, mode, token);
-    if let Ok(sock) = UdpSocket::bind("127.0.0.1:0") {
-        match sock.send_to(msg.as_bytes(), (BIND_ADDR, UI_PORT)) {
-            Ok(n) => info!("ipc: send_show sent {} bytes", n),
-            Err(e) => info!("ipc: send_show failed: {} (UI may not be running)", e),
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+pub const BIND_ADDR: &str = "127.0.0.1";
+
+/// Largest UDP payload over IPv4; longer responses are replaced by an error.
+const MAX_DATAGRAM: usize = 65507;
+
+/// Sends `msg` to `port` until an ack for its `msg_id` comes back, `SEND_ATTEMPTS` times at
+/// most. Fails at once if nothing listens on the port.
+pub fn send_acked(port: u16, secret: &Secret, msg: &FromAgent) -> Result<(), String> {
+    let msg_id = msg.msg_id().ok_or("message has no msg_id")?;
+    let sock = UdpSocket::bind("127.0.0.1:0").map_err(|e| format!("bind: {}", e))?;
+    // Connected, so a closed port reports ConnectionRefused instead of timing out.
+    sock.connect((BIND_ADDR, port))
+        .map_err(|e| format!("connect to port {}: {}", port, e))?;
+    let datagram = secret.sign(&encode(msg));
+    let mut buf = [0u8; 2048];
+    for attempt in 1..=SEND_ATTEMPTS {
+        if attempt > 1 {
+            debug!("ipc: resending message {} (attempt {})", msg_id, attempt);
+        }
+        match sock.send(datagram.as_bytes()) {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused => {
+                return Err(format!("nothing listens on port {}", port))
+            }
+            Err(e) => return Err(format!("send to port {}: {}", port, e)),
+        }
+        let deadline = Instant::now() + ACK_TIMEOUT;
+        while let Some(wait) = deadline
+            .checked_duration_since(Instant::now())
+            .filter(|d| !d.is_zero())
+        {
+            sock.set_read_timeout(Some(wait))
+                .map_err(|e| e.to_string())?;
+            match sock.recv(&mut buf) {
+                Ok(n) => {
+                    let acked = std::str::from_utf8(&buf[..n])
+                        .ok()
+                        .and_then(|text| secret.open(text).ok())
+                        .and_then(|reply| decode::<ToAgent>(&reply).ok());
+                    if acked == Some(ToAgent::Ack { msg_id }) {
+                        return Ok(());
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::ConnectionRefused => {
+                    return Err(format!("nothing listens on port {}", port))
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => break,
+                Err(e) => return Err(format!("receive ack: {}", e)),
+            }
         }
-    } else {
-        info!("ipc: send_show failed to bind ephemeral socket");
     }
+    Err(format!("no ack after {} attempts", SEND_ATTEMPTS))
+}
+
+/// Sends a save or paste trigger to the running agent, as a compositor key binding would.
+pub fn send_trigger(ipc: &IpcConfig, secret: &Secret, action: Mode) -> Result<(), String> {
+    let msg = ToAgent::Trigger { action };
+    let sock = UdpSocket::bind("127.0.0.1:0").map_err(|e| format!("ipc: bind: {}", e))?;
+    sock.send_to(
+        secret.sign(&encode(&msg)).as_bytes(),
+        (BIND_ADDR, ipc.agent_port),
+    )
+    .map_err(|e| format!("ipc: send trigger to port {}: {}", ipc.agent_port, e))?;
+    Ok(())
 }
 
-/// Send hide chooser to UI. Best-effort.
-pub fn send_hide(token: &str) {
-    let msg = format!(r#"{{"type":"hide","token":"{}"}}"#, token);
-    if let Ok(sock) = UdpSocket::bind("127.0.0.1:0") {
-        let _ = sock.send_to(msg.as_bytes(), (BIND_ADDR, UI_PORT));
+/// Sends one request datagram to the agent and returns its response. None if no agent is
+/// listening on `ipc.agent_port`.
+pub fn send_request(
+    ipc: &IpcConfig,
+    secret: &Secret,
+    request: &SlotRequest,
+) -> Result<Option<SlotResponse>, String> {
+    let sock = UdpSocket::bind("127.0.0.1:0").map_err(|e| format!("ipc: bind: {}", e))?;
+    // Connected, so a closed port reports ConnectionRefused instead of timing out.
+    sock.connect((BIND_ADDR, ipc.agent_port))
+        .map_err(|e| format!("ipc: connect to port {}: {}", ipc.agent_port, e))?;
+    sock.set_read_timeout(Some(REQUEST_TIMEOUT + Duration::from_secs(1)))
+        .map_err(|e| format!("ipc: {}", e))?;
+    match sock.send(
+        secret
+            .sign(&encode(&ToAgent::Request(request.clone())))
+            .as_bytes(),
+    ) {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => return Ok(None),
+        Err(e) => return Err(format!("ipc: send request: {}", e)),
+    }
+    let mut buf = vec![0u8; 65536];
+    match sock.recv(&mut buf) {
+        Ok(n) => open_response(secret, &String::from_utf8_lossy(&buf[..n])).map(Some),
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => Ok(None),
+        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Err(format!(
+            "ipc: no response on port {} (is another program using it?)",
+            ipc.agent_port
+        )),
+        Err(e) => Err(format!("ipc: receive response: {}", e)),
     }
 }
 
-/// Run listener on 127.0.0.1:45455; parse newline-delimited JSON and send ChooserChosen/ChooserCancel to tx.
-pub fn start_response_listener(tx: Sender<Event>) {
+/// Binds 127.0.0.1:`agent_port`, then hands each datagram to `inbound::handle` on a background
+/// thread, which sends ChooserChosen/ChooserCancel (or SaveTrigger/PasteTrigger) to tx. Replies
+/// (welcome, acks) go back to the sender at once; requests are answered in turn on a worker
+/// thread, so one waiting on the state machine does not hold up the UI's messages. The port is
+/// fixed for the agent's lifetime: the UI reads it once too.
+pub fn start_response_listener(
+    agent_port: u16,
+    secret: Secret,
+    tx: Sender<Event>,
+) -> Result<(), String> {
+    let sock = UdpSocket::bind((BIND_ADDR, agent_port))
+        .map_err(|e| format!("bind port {}: {}", agent_port, e))?;
+    info!("ipc: listening on {}:{}", BIND_ADDR, agent_port);
+    let sock = Arc::new(sock);
+    let requests = spawn_request_worker(sock.clone(), secret.clone(), tx.clone());
     thread::spawn(move || {
-        let sock = match UdpSocket::bind((BIND_ADDR, AGENT_PORT)) {
-            Ok(s) => s,
-            Err(e) => {
-                error!("ipc listener bind failed: {}", e);
-                return;
-            }
-        };
-        let mut buf = [0u8; 1024];
+        let mut seen = Dedupe::default();
+        let mut buf = [0u8; 65536];
         loop {
             match sock.recv_from(&mut buf) {
-                Ok((n, _)) => {
-                    let s = match std::str::from_utf8(&buf[..n]) {
-                        Ok(x) => x.trim(),
-                        Err(_) => continue,
+                Ok((n, from)) => {
+                    let Ok(text) = std::str::from_utf8(&buf[..n]) else {
+                        continue;
                     };
-                    for line in s.lines() {
-                        if let Some(ev) = parse_response(line) {
-                            if tx.send(ev).is_err() {
+                    match inbound::handle(&secret, text, &tx, &mut seen, "udp", from) {
+                        Inbound::Reply(answer) => reply(&sock, &secret, from, &answer),
+                        Inbound::Request(request) => {
+                            if requests.send((request, from)).is_err() {
                                 return;
                             }
                         }
+                        // Acks go to `send_acked`'s own socket, not here.
+                        Inbound::Acked(_) | Inbound::Done => {}
+                        Inbound::Stopped => return,
                     }
                 }
                 Err(e) => {
@@ -67,26 +170,346 @@ pub fn start_response_listener(tx: Sender<Event>) {
             }
         }
     });
+    Ok(())
 }
 
-fn parse_response(line: &str) -> Option<Event> {
-    let v: serde_json::Value = serde_json::from_str(line).ok()?;
-    let typ = v.get("type")?.as_str()?;
-    let token = v.get("token")?.as_str()?.to_string();
-    match typ {
-        "chosen" => {
-            let slot = v.get("slot")?.as_u64()? as u8;
-            if (1..=6).contains(&slot) {
-                info!("ipc: received from UI -> ChooserChosen token={} slot={}", token, slot);
-                return Some(Event::ChooserChosen { token, slot_num: slot });
+/// Answers queued requests one at a time and sends each response to its sender.
+fn spawn_request_worker(
+    sock: Arc<UdpSocket>,
+    secret: Secret,
+    tx: Sender<Event>,
+) -> Sender<(SlotRequest, SocketAddr)> {
+    let (requests, queue) = mpsc::channel::<(SlotRequest, SocketAddr)>();
+    thread::spawn(move || {
+        for (request, from) in queue {
+            let response = inbound::answer_request(request, &tx);
+            let id = response.id.clone();
+            let signed = secret.sign(&encode(&FromAgent::Response(response)));
+            if signed.len() <= MAX_DATAGRAM {
+                send_signed(&sock, from, &signed);
+                continue;
             }
+            let error = format!(
+                "the response is {} bytes, more than a UDP datagram holds ({}); set ipc.transport = \"unix\"",
+                signed.len(),
+                MAX_DATAGRAM
+            );
+            warn!("ipc: reply to {}: {}", from, error);
+            reply(
+                &sock,
+                &secret,
+                from,
+                &FromAgent::Response(SlotResponse::new(id, Err(error))),
+            );
         }
-        "cancel" => {
-            let reason = v.get("reason").and_then(|r| r.as_str()).unwrap_or("timeout").to_string();
-            info!("ipc: received from UI -> ChooserCancel token={} reason={}", token, reason);
-            return Some(Event::ChooserCancel { token, reason });
+    });
+    requests
+}
+
+fn reply(sock: &UdpSocket, secret: &Secret, to: SocketAddr, msg: &FromAgent) {
+    send_signed(sock, to, &secret.sign(&encode(msg)));
+}
+
+fn send_signed(sock: &UdpSocket, to: SocketAddr, signed: &str) {
+    if let Err(e) = sock.send_to(signed.as_bytes(), to) {
+        warn!("ipc: reply to {} failed: {}", to, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clip_protocol::{Op, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+    use serde_json::json;
+    use std::sync::mpsc;
+
+    fn list_request() -> SlotRequest {
+        SlotRequest {
+            id: json!("a"),
+            op: Op::List,
+            slot: None,
+            text: None,
+            entry: None,
         }
-        _ => {}
     }
-    None
+
+    /// A port nothing listens on (right after this returns).
+    fn free_port() -> u16 {
+        UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    /// Sends `datagram` to the listener and returns the opened reply.
+    fn exchange(sock: &UdpSocket, secret: &Secret, port: u16, datagram: &str) -> FromAgent {
+        sock.send_to(datagram.as_bytes(), (BIND_ADDR, port))
+            .unwrap();
+        let mut buf = [0u8; 1024];
+        let n = sock.recv(&mut buf).unwrap();
+        decode(
+            &secret
+                .open(std::str::from_utf8(&buf[..n]).unwrap())
+                .unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn listener_answers_signed_requests_and_drops_everything_else() {
+        let secret = Secret::generate().unwrap();
+        let ipc = IpcConfig {
+            agent_port: free_port(),
+            ..IpcConfig::default()
+        };
+        let (tx, rx) = mpsc::channel();
+        start_response_listener(ipc.agent_port, secret.clone(), tx).unwrap();
+
+        let client = {
+            let (ipc, secret) = (ipc.clone(), secret.clone());
+            thread::spawn(move || {
+                send_request(&ipc, &secret, &list_request())
+                    .unwrap()
+                    .unwrap()
+            })
+        };
+        match rx.recv_timeout(Duration::from_secs(5)) {
+            Ok(Event::Request(request, reply)) => {
+                assert_eq!(request, crate::ipc::request::Request::List);
+                reply.send(Ok(json!([]))).unwrap();
+            }
+            other => panic!("expected a request, got {:?}", other),
+        }
+        assert_eq!(client.join().unwrap().into_result(), Ok(json!([])));
+
+        let chosen = encode(&ToAgent::Chosen {
+            token: "t".into(),
+            slot: 1,
+            msg_id: None,
+        });
+        let forger = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for datagram in [
+            chosen.clone(),
+            Secret::generate().unwrap().sign(&chosen),
+            secret.sign(&chosen),
+        ] {
+            forger
+                .send_to(datagram.as_bytes(), (BIND_ADDR, ipc.agent_port))
+                .unwrap();
+        }
+        assert!(matches!(
+            rx.recv_timeout(Duration::from_secs(5)),
+            Ok(Event::ChooserChosen { slot_num: 1, .. })
+        ));
+        assert!(
+            rx.recv_timeout(Duration::from_millis(100)).is_err(),
+            "unsigned and forged messages are dropped"
+        );
+    }
+
+    #[test]
+    fn send_acked_resends_until_acked_and_gives_up() {
+        let secret = Secret::generate().unwrap();
+        let ui = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = ui.local_addr().unwrap().port();
+        let show = FromAgent::Hide {
+            token: "t".into(),
+            msg_id: Some(42),
+        };
+        let fake_ui = {
+            let secret = secret.clone();
+            thread::spawn(move || {
+                let mut buf = [0u8; 2048];
+                // The first copy is "lost"; the second is acked.
+                ui.recv_from(&mut buf).unwrap();
+                let (n, from) = ui.recv_from(&mut buf).unwrap();
+                let msg = decode::<FromAgent>(
+                    &secret
+                        .open(std::str::from_utf8(&buf[..n]).unwrap())
+                        .unwrap(),
+                )
+                .unwrap();
+                let ack = ToAgent::Ack {
+                    msg_id: msg.msg_id().unwrap(),
+                };
+                ui.send_to(
+                    secret.sign(&encode(&ToAgent::Ack { msg_id: 1 })).as_bytes(),
+                    from,
+                )
+                .unwrap();
+                ui.send_to(secret.sign(&encode(&ack)).as_bytes(), from)
+                    .unwrap();
+                ui
+            })
+        };
+        assert_eq!(send_acked(port, &secret, &show), Ok(()));
+        let silent_ui = fake_ui.join().unwrap();
+
+        let started = Instant::now();
+        assert_eq!(
+            send_acked(port, &secret, &show),
+            Err(format!("no ack after {} attempts", SEND_ATTEMPTS))
+        );
+        assert!(started.elapsed() >= ACK_TIMEOUT * SEND_ATTEMPTS);
+        drop(silent_ui);
+        assert!(send_acked(port, &secret, &show)
+            .unwrap_err()
+            .contains("nothing listens"));
+    }
+
+    #[test]
+    fn listener_acks_ui_messages_and_drops_resent_copies() {
+        let secret = Secret::generate().unwrap();
+        let port = free_port();
+        let (tx, rx) = mpsc::channel();
+        start_response_listener(port, secret.clone(), tx).unwrap();
+
+        let ui = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ui.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let chosen = secret.sign(&encode(&ToAgent::Chosen {
+            token: "t".into(),
+            slot: 2,
+            msg_id: Some(9),
+        }));
+        for _ in 0..2 {
+            assert_eq!(
+                exchange(&ui, &secret, port, &chosen),
+                FromAgent::Ack { msg_id: 9 }
+            );
+        }
+        assert!(matches!(
+            rx.recv_timeout(Duration::from_secs(5)),
+            Ok(Event::ChooserChosen { slot_num: 2, .. })
+        ));
+        assert!(
+            rx.recv_timeout(Duration::from_millis(100)).is_err(),
+            "the resent chosen is handled once"
+        );
+    }
+
+    #[test]
+    fn listener_acks_ui_messages_while_a_request_waits() {
+        let secret = Secret::generate().unwrap();
+        let ipc = IpcConfig {
+            agent_port: free_port(),
+            ..IpcConfig::default()
+        };
+        let (tx, rx) = mpsc::channel();
+        start_response_listener(ipc.agent_port, secret.clone(), tx).unwrap();
+
+        let client = {
+            let (ipc, secret) = (ipc.clone(), secret.clone());
+            thread::spawn(move || {
+                send_request(&ipc, &secret, &list_request())
+                    .unwrap()
+                    .unwrap()
+            })
+        };
+        let Ok(Event::Request(_, pending)) = rx.recv_timeout(Duration::from_secs(5)) else {
+            panic!("expected a request");
+        };
+        // The state machine has not answered yet; the UI's chosen is acked regardless.
+        let ui = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ui.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let chosen = secret.sign(&encode(&ToAgent::Chosen {
+            token: "t".into(),
+            slot: 1,
+            msg_id: Some(3),
+        }));
+        assert_eq!(
+            exchange(&ui, &secret, ipc.agent_port, &chosen),
+            FromAgent::Ack { msg_id: 3 }
+        );
+        assert!(matches!(
+            rx.recv_timeout(Duration::from_secs(5)),
+            Ok(Event::ChooserChosen { slot_num: 1, .. })
+        ));
+
+        pending.send(Ok(json!([]))).unwrap();
+        assert_eq!(client.join().unwrap().into_result(), Ok(json!([])));
+    }
+
+    #[test]
+    fn responses_too_big_for_a_datagram_become_errors() {
+        let secret = Secret::generate().unwrap();
+        let ipc = IpcConfig {
+            agent_port: free_port(),
+            ..IpcConfig::default()
+        };
+        let (tx, rx) = mpsc::channel();
+        start_response_listener(ipc.agent_port, secret.clone(), tx).unwrap();
+
+        let client = {
+            let (ipc, secret) = (ipc.clone(), secret.clone());
+            thread::spawn(move || {
+                send_request(&ipc, &secret, &list_request())
+                    .unwrap()
+                    .unwrap()
+            })
+        };
+        let Ok(Event::Request(_, reply)) = rx.recv_timeout(Duration::from_secs(5)) else {
+            panic!("expected a request");
+        };
+        reply.send(Ok(json!("x".repeat(MAX_DATAGRAM)))).unwrap();
+        let response = client.join().unwrap();
+        assert_eq!(response.id, json!("a"));
+        assert!(response
+            .into_result()
+            .unwrap_err()
+            .contains("more than a UDP datagram holds"));
+    }
+
+    #[test]
+    fn listener_answers_the_handshake() {
+        let secret = Secret::generate().unwrap();
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let (tx, _rx) = mpsc::channel();
+        start_response_listener(port, secret.clone(), tx).unwrap();
+
+        let hello = secret.sign(&encode(&ToAgent::Hello {
+            client: "test".into(),
+        }));
+        assert_eq!(
+            exchange(&sock, &secret, port, &hello),
+            FromAgent::Welcome {
+                agent: env!("CARGO_PKG_VERSION").to_string()
+            }
+        );
+        let future = secret.sign(r#"{"type":"hello","version":99,"client":"clip-ui 9"}"#);
+        assert_eq!(
+            exchange(&sock, &secret, port, &future),
+            FromAgent::Unsupported {
+                min_version: MIN_PROTOCOL_VERSION,
+                max_version: PROTOCOL_VERSION
+            }
+        );
+    }
+
+    #[test]
+    fn send_request_rejects_unsigned_responses_and_reports_no_agent() {
+        let secret = Secret::generate().unwrap();
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let ipc = IpcConfig {
+            agent_port: listener.local_addr().unwrap().port(),
+            ..IpcConfig::default()
+        };
+        let impostor = thread::spawn(move || {
+            let mut buf = [0u8; 1024];
+            let (_, from) = listener.recv_from(&mut buf).unwrap();
+            listener
+                .send_to(br#"{"type":"response","ok":true}"#, from)
+                .unwrap();
+        });
+        let response = send_request(&ipc, &secret, &list_request());
+        assert!(response.unwrap_err().contains("response rejected"));
+        impostor.join().unwrap();
+
+        // The impostor is gone, so the port is closed.
+        assert_eq!(send_request(&ipc, &secret, &list_request()), Ok(None));
+    }
 }
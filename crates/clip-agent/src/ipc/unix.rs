@@ -0,0 +1,402 @@
+//! Unix socket IPC (`ipc.transport = "unix"`): the messages `udp` carries, each in a
+//! length-prefixed frame (`clip_protocol::frame`) on a stream socket, `agent.sock` in
+//! `clip_config::runtime_dir()`. Frames are signed like datagrams.
+//!
+//! Scripts and `clip-agent trigger|request` connect, send a frame and read the answer. The UI
+//! says `hello` and stays connected; show and hide then go down its connection
+//! (`UiLink::send_acked`) and it acks them there. While no UI is connected the chooser falls
+//! back to UDP (`RemoteChooserUi::unix`).
+
+use super::inbound::{self, Inbound};
+use super::open_response;
+use super::request::REQUEST_TIMEOUT;
+use crate::state_machine::Event;
+use clip_config::auth::Secret;
+use clip_protocol::frame::{read_frame, write_frame};
+use clip_protocol::{
+    encode, Dedupe, FromAgent, Mode, SlotRequest, SlotResponse, ToAgent, ACK_TIMEOUT, SEND_ATTEMPTS,
+};
+use std::io::{BufReader, ErrorKind};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+type Writer = Arc<Mutex<UnixStream>>;
+
+/// The UI's connection, if it has one: where show and hide go and where its acks come back.
+#[derive(Clone)]
+pub struct UiLink(Arc<LinkState>);
+
+struct LinkState {
+    /// Connection number and writer of the current UI.
+    ui: Mutex<Option<(u64, Writer)>>,
+    acks_tx: Sender<u64>,
+    acks: Mutex<Receiver<u64>>,
+}
+
+impl UiLink {
+    pub fn new() -> Self {
+        let (acks_tx, acks) = mpsc::channel();
+        Self(Arc::new(LinkState {
+            ui: Mutex::new(None),
+            acks_tx,
+            acks: Mutex::new(acks),
+        }))
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.0
+            .ui
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .is_some()
+    }
+
+    /// Writes `msg` to the UI and waits for its ack. A stream does not lose frames, so unlike
+    /// `udp::send_acked` nothing is resent; the UI gets as long as UDP's attempts take.
+    pub fn send_acked(&self, secret: &Secret, msg: &FromAgent) -> Result<(), String> {
+        let msg_id = msg.msg_id().ok_or("message has no msg_id")?;
+        let writer = match &*self.0.ui.lock().unwrap_or_else(|p| p.into_inner()) {
+            Some((_, writer)) => writer.clone(),
+            None => return Err("no UI connected".into()),
+        };
+        let acks = self.0.acks.lock().unwrap_or_else(|p| p.into_inner());
+        // Late acks for messages that already timed out.
+        while acks.try_recv().is_ok() {}
+        write(&writer, secret, msg).map_err(|e| format!("write to UI: {}", e))?;
+        let deadline = Instant::now() + ACK_TIMEOUT * SEND_ATTEMPTS;
+        while let Some(wait) = deadline.checked_duration_since(Instant::now()) {
+            match acks.recv_timeout(wait) {
+                Ok(id) if id == msg_id => return Ok(()),
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+        Err(format!(
+            "no ack within {} ms",
+            (ACK_TIMEOUT * SEND_ATTEMPTS).as_millis()
+        ))
+    }
+
+    fn attach(&self, conn: u64, writer: Writer) {
+        *self.0.ui.lock().unwrap_or_else(|p| p.into_inner()) = Some((conn, writer));
+    }
+
+    /// Forgets connection `conn` if it is still the UI's; true if it was.
+    fn detach(&self, conn: u64) -> bool {
+        let mut ui = self.0.ui.lock().unwrap_or_else(|p| p.into_inner());
+        let current = matches!(&*ui, Some((c, _)) if *c == conn);
+        if current {
+            *ui = None;
+        }
+        current
+    }
+}
+
+/// Listens on `path` and serves each connection on its own thread, handing frames to
+/// `inbound::handle`. A socket left behind by an agent that is gone is replaced; one that still
+/// answers is an error.
+pub fn start_listener(
+    path: &Path,
+    secret: Secret,
+    tx: Sender<Event>,
+    link: UiLink,
+) -> Result<(), String> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(format!(
+                "{} is in use (is another agent running?)",
+                path.display()
+            ));
+        }
+        std::fs::remove_file(path)
+            .map_err(|e| format!("remove stale {}: {}", path.display(), e))?;
+    }
+    let listener =
+        UnixListener::bind(path).map_err(|e| format!("bind {}: {}", path.display(), e))?;
+    info!("ipc: listening on {}", path.display());
+    thread::spawn(move || {
+        let conns = AtomicU64::new(1);
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let conn = conns.fetch_add(1, Ordering::Relaxed);
+                    let (secret, tx, link) = (secret.clone(), tx.clone(), link.clone());
+                    thread::spawn(move || serve(stream, conn, secret, tx, link));
+                }
+                Err(e) => warn!("ipc: accept failed: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Handles frames from one connection until it closes.
+fn serve(stream: UnixStream, conn: u64, secret: Secret, tx: Sender<Event>, link: UiLink) {
+    let writer = match stream.try_clone() {
+        Ok(w) => Arc::new(Mutex::new(w)),
+        Err(e) => {
+            warn!("ipc: {}", e);
+            return;
+        }
+    };
+    let peer = format!("connection {}", conn);
+    let mut reader = BufReader::new(stream);
+    let mut seen = Dedupe::default();
+    let mut is_ui = false;
+    loop {
+        let frame = match read_frame(&mut reader) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break,
+            Err(e) => {
+                warn!("ipc: {}: {}", peer, e);
+                break;
+            }
+        };
+        let Ok(text) = String::from_utf8(frame) else {
+            continue;
+        };
+        match inbound::handle(&secret, &text, &tx, &mut seen, "unix", &peer) {
+            Inbound::Reply(answer) => {
+                if let Err(e) = write(&writer, &secret, &answer) {
+                    debug!("ipc: reply to {} failed: {}", peer, e);
+                    break;
+                }
+                if matches!(answer, FromAgent::Welcome { .. }) {
+                    info!("ipc: UI connected over the socket ({})", peer);
+                    link.attach(conn, writer.clone());
+                    is_ui = true;
+                }
+            }
+            Inbound::Request(request) => {
+                // Only this connection waits: each has its own thread.
+                let answer = FromAgent::Response(inbound::answer_request(request, &tx));
+                if let Err(e) = write(&writer, &secret, &answer) {
+                    debug!("ipc: reply to {} failed: {}", peer, e);
+                    break;
+                }
+            }
+            Inbound::Acked(msg_id) => {
+                let _ = link.0.acks_tx.send(msg_id);
+            }
+            Inbound::Done => {}
+            Inbound::Stopped => break,
+        }
+    }
+    // Unless a newer connection from the UI has taken over already.
+    if is_ui && link.detach(conn) {
+        info!("ipc: UI disconnected ({})", peer);
+        let _ = tx.send(Event::UiGone);
+    }
+}
+
+fn write(writer: &Mutex<UnixStream>, secret: &Secret, msg: &FromAgent) -> std::io::Result<()> {
+    let mut stream = writer.lock().unwrap_or_else(|p| p.into_inner());
+    write_frame(&mut *stream, secret.sign(&encode(msg)).as_bytes())
+}
+
+/// Connects to the agent's socket; None if no agent listens there.
+fn connect(path: &Path) -> Result<Option<UnixStream>, String> {
+    match UnixStream::connect(path) {
+        Ok(stream) => Ok(Some(stream)),
+        Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::ConnectionRefused) => {
+            Ok(None)
+        }
+        Err(e) => Err(format!("ipc: connect to {}: {}", path.display(), e)),
+    }
+}
+
+/// Sends a save or paste trigger to the agent listening on `path`. False if there is none.
+pub fn send_trigger(path: &Path, secret: &Secret, action: Mode) -> Result<bool, String> {
+    let Some(mut stream) = connect(path)? else {
+        return Ok(false);
+    };
+    write_frame(
+        &mut stream,
+        secret
+            .sign(&encode(&ToAgent::Trigger { action }))
+            .as_bytes(),
+    )
+    .map_err(|e| format!("ipc: send trigger: {}", e))?;
+    Ok(true)
+}
+
+/// Sends one request to the agent listening on `path` and returns its response. None if there
+/// is no agent there.
+pub fn send_request(
+    path: &Path,
+    secret: &Secret,
+    request: &SlotRequest,
+) -> Result<Option<SlotResponse>, String> {
+    let Some(mut stream) = connect(path)? else {
+        return Ok(None);
+    };
+    stream
+        .set_read_timeout(Some(REQUEST_TIMEOUT + Duration::from_secs(1)))
+        .map_err(|e| format!("ipc: {}", e))?;
+    write_frame(
+        &mut stream,
+        secret
+            .sign(&encode(&ToAgent::Request(request.clone())))
+            .as_bytes(),
+    )
+    .map_err(|e| format!("ipc: send request: {}", e))?;
+    match read_frame(&mut stream) {
+        Ok(Some(frame)) => open_response(secret, &String::from_utf8_lossy(&frame)).map(Some),
+        Ok(None) => Err("ipc: the agent closed the connection without answering".into()),
+        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+            Err(format!("ipc: no response on {}", path.display()))
+        }
+        Err(e) => Err(format!("ipc: receive response: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clip_protocol::{decode, Op};
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    fn listen(secret: &Secret) -> (tempfile::TempDir, PathBuf, mpsc::Receiver<Event>, UiLink) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("agent.sock");
+        let (tx, rx) = mpsc::channel();
+        let link = UiLink::new();
+        start_listener(&path, secret.clone(), tx, link.clone()).unwrap();
+        (dir, path, rx, link)
+    }
+
+    #[test]
+    fn answers_triggers_and_requests_over_the_socket() {
+        let secret = Secret::generate().unwrap();
+        let (_dir, path, rx, _link) = listen(&secret);
+
+        assert_eq!(send_trigger(&path, &secret, Mode::Paste), Ok(true));
+        assert!(matches!(
+            rx.recv_timeout(Duration::from_secs(5)),
+            Ok(Event::PasteTrigger)
+        ));
+
+        let client = {
+            let (path, secret) = (path.clone(), secret.clone());
+            let list = SlotRequest {
+                id: json!(7),
+                op: Op::List,
+                slot: None,
+                text: None,
+                entry: None,
+            };
+            thread::spawn(move || send_request(&path, &secret, &list))
+        };
+        match rx.recv_timeout(Duration::from_secs(5)) {
+            Ok(Event::Request(_, reply)) => reply.send(Ok(json!([]))).unwrap(),
+            other => panic!("expected a request, got {:?}", other),
+        }
+        let response = client.join().unwrap().unwrap().unwrap();
+        assert_eq!(response, SlotResponse::new(json!(7), Ok(json!([]))));
+
+        // Forged frames are dropped, and the connection stays usable.
+        let mut stream = UnixStream::connect(&path).unwrap();
+        let trigger = encode(&ToAgent::Trigger { action: Mode::Save });
+        write_frame(
+            &mut stream,
+            Secret::generate().unwrap().sign(&trigger).as_bytes(),
+        )
+        .unwrap();
+        write_frame(&mut stream, secret.sign(&trigger).as_bytes()).unwrap();
+        assert!(matches!(
+            rx.recv_timeout(Duration::from_secs(5)),
+            Ok(Event::SaveTrigger)
+        ));
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn reports_a_missing_agent_and_refuses_a_live_socket() {
+        let secret = Secret::generate().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("none.sock");
+        assert_eq!(send_trigger(&missing, &secret, Mode::Save), Ok(false));
+
+        let (_dir, path, _rx, link) = listen(&secret);
+        let (tx, _rx2) = mpsc::channel();
+        assert!(start_listener(&path, secret.clone(), tx, link)
+            .unwrap_err()
+            .contains("in use"));
+    }
+
+    #[test]
+    fn show_goes_to_the_connected_ui_and_waits_for_its_ack() {
+        let secret = Secret::generate().unwrap();
+        let (_dir, path, rx, link) = listen(&secret);
+        let hide = FromAgent::Hide {
+            token: "t".into(),
+            msg_id: Some(5),
+        };
+        assert_eq!(
+            link.send_acked(&secret, &hide),
+            Err("no UI connected".into())
+        );
+
+        let mut ui = UnixStream::connect(&path).unwrap();
+        let read = |ui: &mut UnixStream| -> FromAgent {
+            let frame = read_frame(ui).unwrap().unwrap();
+            decode(&secret.open(std::str::from_utf8(&frame).unwrap()).unwrap()).unwrap()
+        };
+        write_frame(
+            &mut ui,
+            secret
+                .sign(&encode(&ToAgent::Hello {
+                    client: "test".into(),
+                }))
+                .as_bytes(),
+        )
+        .unwrap();
+        assert!(matches!(read(&mut ui), FromAgent::Welcome { .. }));
+        assert!(link.is_connected());
+        assert!(matches!(
+            rx.recv_timeout(Duration::from_secs(5)),
+            Ok(Event::UiSeen {
+                transport: "unix",
+                ..
+            })
+        ));
+
+        let sender = {
+            let (link, secret, hide) = (link.clone(), secret.clone(), hide.clone());
+            thread::spawn(move || link.send_acked(&secret, &hide))
+        };
+        assert_eq!(read(&mut ui), hide);
+        write_frame(
+            &mut ui,
+            secret.sign(&encode(&ToAgent::Ack { msg_id: 5 })).as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(sender.join().unwrap(), Ok(()));
+
+        // A UI that does not ack, then one that hangs up.
+        assert!(link
+            .send_acked(&secret, &hide)
+            .unwrap_err()
+            .contains("no ack"));
+        drop(ui);
+        let started = Instant::now();
+        while link.is_connected() {
+            assert!(
+                started.elapsed() < Duration::from_secs(5),
+                "UI was not detached"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(matches!(
+            rx.recv_timeout(Duration::from_secs(5)),
+            Ok(Event::UiGone)
+        ));
+    }
+}
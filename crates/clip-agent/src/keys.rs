@@ -1,14 +1,13 @@
-//! Key and slot definitions.
+//! Key and slot definitions, and matching of configured hotkeys against raw key events.
 
-/// Logical key from a keyboard event.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Key {
-    Slot(SlotId),
-    Escape,
-    C,
-    V,
-    Other(u16),
-}
+use crate::state_machine::{MODE_PASTE_ACTIVE, MODE_SAVE_PENDING};
+use bitflags::bitflags;
+use clip_config::hotkey::{Hotkey, Modifier};
+use clip_config::{KeysConfig, SLOT_COUNT};
+
+/// Key from a keyboard event: the platform virtual keycode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key(pub i64);
 
 /// Slot identifiers: J, K, L, U, I, O.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -22,6 +21,16 @@ pub enum SlotId {
 }
 
 impl SlotId {
+    /// All slots in chooser order (1..6).
+    pub const ALL: [SlotId; 6] = [
+        SlotId::J,
+        SlotId::K,
+        SlotId::L,
+        SlotId::U,
+        SlotId::I,
+        SlotId::O,
+    ];
+
     /// Human-readable label for logs and DB key.
     pub fn label(self) -> &'static str {
         match self {
@@ -47,6 +56,15 @@ impl SlotId {
         }
     }
 
+    /// Chooser number 1..6.
+    pub fn slot_num(self) -> u8 {
+        SlotId::ALL
+            .iter()
+            .position(|&s| s == self)
+            .map(|i| i as u8 + 1)
+            .unwrap_or(0)
+    }
+
     /// Chooser slot number 1..6 -> SlotId. 1=J, 2=K, 3=L, 4=U, 5=I, 6=O.
     pub fn from_slot_num(n: u8) -> Option<SlotId> {
         match n {
@@ -61,29 +79,457 @@ impl SlotId {
     }
 }
 
-/// macOS virtual key codes (Carbon-style).
-const VK_ANSI_C: i64 = 8;
-const VK_ANSI_V: i64 = 9;
-const VK_ANSI_J: i64 = 38;
-const VK_ANSI_K: i64 = 40;
-const VK_ANSI_L: i64 = 37;
-const VK_ANSI_U: i64 = 32;
-const VK_ANSI_I: i64 = 34;
-const VK_ANSI_O: i64 = 31;
-const VK_ESCAPE: i64 = 53;
-
-/// Converts a raw keycode to a Key. Used by the event tap.
-pub fn keycode_to_key(keycode: i64) -> Key {
-    match keycode {
-        VK_ANSI_J => Key::Slot(SlotId::J),
-        VK_ANSI_K => Key::Slot(SlotId::K),
-        VK_ANSI_L => Key::Slot(SlotId::L),
-        VK_ANSI_U => Key::Slot(SlotId::U),
-        VK_ANSI_I => Key::Slot(SlotId::I),
-        VK_ANSI_O => Key::Slot(SlotId::O),
-        VK_ESCAPE => Key::Escape,
-        VK_ANSI_C => Key::C,
-        VK_ANSI_V => Key::V,
-        _ => Key::Other(keycode as u16),
+bitflags! {
+    /// Modifier keys held during a key event. The side-specific flags are set in addition to the
+    /// generic one (left Cmd gives `CMD | LEFT_CMD`); bindings only look at `CHORD`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct Modifiers: u16 {
+        const CMD = 1 << 0;
+        const OPTION = 1 << 1;
+        const CTRL = 1 << 2;
+        const SHIFT = 1 << 3;
+        const FN = 1 << 4;
+        const CAPS_LOCK = 1 << 5;
+        const LEFT_CMD = 1 << 6;
+        const RIGHT_CMD = 1 << 7;
+        const LEFT_OPTION = 1 << 8;
+        const RIGHT_OPTION = 1 << 9;
+        const LEFT_CTRL = 1 << 10;
+        const RIGHT_CTRL = 1 << 11;
+        const LEFT_SHIFT = 1 << 12;
+        const RIGHT_SHIFT = 1 << 13;
+
+        /// Modifiers that take part in hotkey matching.
+        const CHORD = Self::CMD.bits() | Self::OPTION.bits() | Self::CTRL.bits() | Self::SHIFT.bits();
+    }
+}
+
+/// macOS `CGEventFlags` bits: device-independent masks, then the device-dependent
+/// `NX_DEVICE*KEYMASK` bits that tell left from right.
+#[cfg(any(target_os = "macos", test))]
+const CG_FLAG_BITS: [(u64, Modifiers); 14] = [
+    (1 << 16, Modifiers::CAPS_LOCK),
+    (1 << 17, Modifiers::SHIFT),
+    (1 << 18, Modifiers::CTRL),
+    (1 << 19, Modifiers::OPTION),
+    (1 << 20, Modifiers::CMD),
+    (1 << 23, Modifiers::FN),
+    (0x0001, Modifiers::LEFT_CTRL),
+    (0x0002, Modifiers::LEFT_SHIFT),
+    (0x0004, Modifiers::RIGHT_SHIFT),
+    (0x0008, Modifiers::LEFT_CMD),
+    (0x0010, Modifiers::RIGHT_CMD),
+    (0x0020, Modifiers::LEFT_OPTION),
+    (0x0040, Modifiers::RIGHT_OPTION),
+    (0x2000, Modifiers::RIGHT_CTRL),
+];
+
+impl Modifiers {
+    /// Decodes a macOS `CGEventFlags` word. Bits that are not modifiers (numeric pad, help,
+    /// non-coalesced) are dropped.
+    #[cfg(any(target_os = "macos", test))]
+    pub fn from_cg_flags(flags: u64) -> Modifiers {
+        CG_FLAG_BITS
+            .iter()
+            .filter(|(bit, _)| flags & bit != 0)
+            .fold(Modifiers::empty(), |acc, (_, m)| acc | *m)
+    }
+}
+
+impl From<Modifier> for Modifiers {
+    fn from(m: Modifier) -> Modifiers {
+        match m {
+            Modifier::Cmd => Modifiers::CMD,
+            Modifier::Option => Modifiers::OPTION,
+            Modifier::Ctrl => Modifiers::CTRL,
+            Modifier::Shift => Modifiers::SHIFT,
+        }
+    }
+}
+
+/// macOS virtual keycode (Carbon `kVK_*`) for a canonical hotkey key name: letters, digits and
+/// every entry of `clip_config::hotkey::NAMED_KEYS`.
+pub fn keycode_for_name(name: &str) -> Option<i64> {
+    let code = match name {
+        "a" => 0,
+        "s" => 1,
+        "d" => 2,
+        "f" => 3,
+        "h" => 4,
+        "g" => 5,
+        "z" => 6,
+        "x" => 7,
+        "c" => 8,
+        "v" => 9,
+        "b" => 11,
+        "q" => 12,
+        "w" => 13,
+        "e" => 14,
+        "r" => 15,
+        "y" => 16,
+        "t" => 17,
+        "1" => 18,
+        "2" => 19,
+        "3" => 20,
+        "4" => 21,
+        "6" => 22,
+        "5" => 23,
+        "equal" => 24,
+        "9" => 25,
+        "7" => 26,
+        "minus" => 27,
+        "8" => 28,
+        "0" => 29,
+        "right_bracket" => 30,
+        "o" => 31,
+        "u" => 32,
+        "left_bracket" => 33,
+        "i" => 34,
+        "p" => 35,
+        "return" => 36,
+        "l" => 37,
+        "j" => 38,
+        "quote" => 39,
+        "k" => 40,
+        "semicolon" => 41,
+        "backslash" => 42,
+        "comma" => 43,
+        "slash" => 44,
+        "n" => 45,
+        "m" => 46,
+        "period" => 47,
+        "tab" => 48,
+        "space" => 49,
+        "grave" => 50,
+        "delete" => 51,
+        "escape" => 53,
+        "f17" => 64,
+        "f18" => 79,
+        "f19" => 80,
+        "f20" => 90,
+        "f5" => 96,
+        "f6" => 97,
+        "f7" => 98,
+        "f3" => 99,
+        "f8" => 100,
+        "f9" => 101,
+        "f11" => 103,
+        "f13" => 105,
+        "f16" => 106,
+        "f14" => 107,
+        "f10" => 109,
+        "f12" => 111,
+        "f15" => 113,
+        "home" => 115,
+        "page_up" => 116,
+        "forward_delete" => 117,
+        "f4" => 118,
+        "end" => 119,
+        "f2" => 120,
+        "page_down" => 121,
+        "f1" => 122,
+        "left" => 123,
+        "right" => 124,
+        "down" => 125,
+        "up" => 126,
+        _ => return None,
+    };
+    Some(code)
+}
+
+/// Hotkey key name for a macOS virtual keycode; inverse of `keycode_for_name`.
+pub fn name_for_keycode(keycode: i64) -> Option<&'static str> {
+    const LETTERS_AND_DIGITS: [&str; 36] = [
+        "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o", "p", "q", "r",
+        "s", "t", "u", "v", "w", "x", "y", "z", "0", "1", "2", "3", "4", "5", "6", "7", "8", "9",
+    ];
+    LETTERS_AND_DIGITS
+        .iter()
+        .chain(clip_config::hotkey::NAMED_KEYS)
+        .copied()
+        .find(|name| keycode_for_name(name) == Some(keycode))
+}
+
+/// A configured hotkey resolved to a keycode and the exact modifiers it requires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyBinding {
+    keycode: i64,
+    modifiers: Modifiers,
+}
+
+impl KeyBinding {
+    pub fn from_hotkey(hotkey: &Hotkey) -> Result<KeyBinding, String> {
+        let keycode = keycode_for_name(&hotkey.key)
+            .ok_or_else(|| format!("{}: key {:?} has no keycode", hotkey, hotkey.key))?;
+        let modifiers = hotkey
+            .modifiers
+            .iter()
+            .map(|&m| Modifiers::from(m))
+            .collect();
+        Ok(KeyBinding { keycode, modifiers })
+    }
+
+    pub fn key(&self) -> Key {
+        Key(self.keycode)
+    }
+
+    /// Required modifiers (a subset of `Modifiers::CHORD`).
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+
+    /// True if the key event is this binding with exactly its modifiers held. Fn, caps lock and
+    /// which side was pressed are ignored.
+    pub fn matches(&self, key: Key, modifiers: Modifiers) -> bool {
+        key.0 == self.keycode && modifiers.intersection(Modifiers::CHORD) == self.modifiers
+    }
+}
+
+/// What a key-down means to Slotpaste, given the chooser mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyAction {
+    SaveTrigger,
+    PasteTrigger,
+    /// Chooser slot 1..6.
+    Slot(u8),
+    Cancel,
+}
+
+impl KeyAction {
+    /// Whether the event tap hides the key from the focused app. The save trigger passes through
+    /// so the copy still happens.
+    pub fn swallows(self) -> bool {
+        !matches!(self, KeyAction::SaveTrigger)
+    }
+}
+
+/// All configured bindings (`[keys]`), resolved to keycodes. Shared by the event tap (to decide
+/// what to swallow) and the state machine (to act on key-downs).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keymap {
+    save_trigger: KeyBinding,
+    paste_trigger: KeyBinding,
+    save_slots: [KeyBinding; SLOT_COUNT],
+    paste_slots: [KeyBinding; SLOT_COUNT],
+    cancel: KeyBinding,
+}
+
+impl Keymap {
+    pub fn from_config(keys: &KeysConfig) -> Result<Keymap, String> {
+        let bind = |field: &str, hotkey: &Hotkey| {
+            KeyBinding::from_hotkey(hotkey).map_err(|e| format!("keys.{}: {}", field, e))
+        };
+        let slots = |field: &str, hotkeys: &[Hotkey]| -> Result<[KeyBinding; SLOT_COUNT], String> {
+            let bound = hotkeys
+                .iter()
+                .map(|h| bind(field, h))
+                .collect::<Result<Vec<_>, _>>()?;
+            bound.try_into().map_err(|v: Vec<_>| {
+                format!(
+                    "keys.{}: expected {} bindings, got {}",
+                    field,
+                    SLOT_COUNT,
+                    v.len()
+                )
+            })
+        };
+        Ok(Keymap {
+            save_trigger: bind("save_trigger", &keys.save_trigger)?,
+            paste_trigger: bind("paste_trigger", &keys.paste_trigger)?,
+            save_slots: slots("save_slots", &keys.save_slots)?,
+            paste_slots: slots("paste_slots", &keys.paste_slots)?,
+            cancel: bind("cancel", &keys.cancel)?,
+        })
+    }
+
+    /// Action for a key-down in chooser `mode` (`state_machine::MODE_*`). Slot and cancel keys
+    /// only act while the matching chooser is open; the triggers act in any mode.
+    pub fn action(&self, mode: u8, key: Key, modifiers: Modifiers) -> Option<KeyAction> {
+        let slots = match mode {
+            MODE_SAVE_PENDING => Some(&self.save_slots),
+            MODE_PASTE_ACTIVE => Some(&self.paste_slots),
+            _ => None,
+        };
+        if let Some(slots) = slots {
+            if let Some(i) = slots.iter().position(|b| b.matches(key, modifiers)) {
+                return Some(KeyAction::Slot(i as u8 + 1));
+            }
+            if self.cancel.matches(key, modifiers) {
+                return Some(KeyAction::Cancel);
+            }
+        }
+        if self.paste_trigger.matches(key, modifiers) {
+            Some(KeyAction::PasteTrigger)
+        } else if self.save_trigger.matches(key, modifiers) {
+            Some(KeyAction::SaveTrigger)
+        } else {
+            None
+        }
+    }
+}
+
+impl Keymap {
+    /// Bindings whose key-downs are swallowed in `mode`, for sources that must claim keys up
+    /// front (X11 grabs) instead of deciding per event.
+    pub fn swallowed_in(&self, mode: u8) -> Vec<KeyBinding> {
+        let mut out = vec![self.paste_trigger];
+        match mode {
+            MODE_SAVE_PENDING => out.extend(self.save_slots),
+            MODE_PASTE_ACTIVE => out.extend(self.paste_slots),
+            _ => return out,
+        }
+        out.push(self.cancel);
+        out
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::from_config(&KeysConfig::default()).expect("default keys have keycodes")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_machine::MODE_IDLE;
+
+    const V: Key = Key(9);
+    const C: Key = Key(8);
+    const CMD: Modifiers = Modifiers::CMD;
+    const OPTION: Modifiers = Modifiers::OPTION;
+    const NONE: Modifiers = Modifiers::empty();
+
+    #[test]
+    fn decodes_macos_flags() {
+        assert_eq!(Modifiers::from_cg_flags(0), NONE);
+        // Left Cmd + right Option, as reported by a real key-down (0x100 = non-coalesced).
+        assert_eq!(
+            Modifiers::from_cg_flags(0x0018_0148),
+            Modifiers::CMD | Modifiers::LEFT_CMD | Modifiers::OPTION | Modifiers::RIGHT_OPTION
+        );
+        assert_eq!(
+            Modifiers::from_cg_flags((1 << 18) | 0x2000 | (1 << 17) | 0x0002),
+            Modifiers::CTRL | Modifiers::RIGHT_CTRL | Modifiers::SHIFT | Modifiers::LEFT_SHIFT
+        );
+        assert_eq!(
+            Modifiers::from_cg_flags((1 << 16) | (1 << 23)),
+            Modifiers::CAPS_LOCK | Modifiers::FN
+        );
+        // Numeric pad (1 << 21) and help (1 << 22) are not modifiers.
+        assert_eq!(
+            Modifiers::from_cg_flags((1 << 21) | (1 << 22) | 0x100),
+            NONE
+        );
+    }
+
+    #[test]
+    fn binding_requires_exact_modifiers() {
+        let b = KeyBinding::from_hotkey(&Hotkey::parse("cmd+option+v").unwrap()).unwrap();
+        assert!(b.matches(V, CMD | OPTION));
+        // Caps lock, fn and sides are ignored.
+        assert!(b.matches(
+            V,
+            CMD | OPTION | Modifiers::CAPS_LOCK | Modifiers::FN | Modifiers::RIGHT_CMD
+        ));
+        assert!(!b.matches(V, CMD));
+        assert!(!b.matches(V, CMD | OPTION | Modifiers::SHIFT));
+        assert!(!b.matches(C, CMD | OPTION));
+    }
+
+    #[test]
+    fn every_key_name_has_a_distinct_keycode() {
+        let names = ('a'..='z').chain('0'..='9').map(|c| c.to_string()).chain(
+            clip_config::hotkey::NAMED_KEYS
+                .iter()
+                .map(|s| s.to_string()),
+        );
+        let mut seen = std::collections::HashMap::new();
+        for name in names {
+            let code = keycode_for_name(&name).unwrap_or_else(|| panic!("{} has no keycode", name));
+            if let Some(other) = seen.insert(code, name.clone()) {
+                panic!("{} and {} share keycode {}", name, other, code);
+            }
+        }
+    }
+
+    #[test]
+    fn keymap_resolves_by_mode() {
+        let keymap = Keymap::default();
+        let one = Key(keycode_for_name("1").unwrap());
+        let esc = Key(keycode_for_name("escape").unwrap());
+        assert_eq!(
+            keymap.action(MODE_IDLE, C, CMD),
+            Some(KeyAction::SaveTrigger)
+        );
+        assert_eq!(
+            keymap.action(MODE_IDLE, V, CMD | OPTION),
+            Some(KeyAction::PasteTrigger)
+        );
+        assert_eq!(keymap.action(MODE_IDLE, V, CMD), None);
+        assert_eq!(keymap.action(MODE_IDLE, one, NONE), None);
+        assert_eq!(keymap.action(MODE_IDLE, esc, NONE), None);
+        assert_eq!(
+            keymap.action(MODE_PASTE_ACTIVE, one, NONE),
+            Some(KeyAction::Slot(1))
+        );
+        assert_eq!(keymap.action(MODE_PASTE_ACTIVE, one, OPTION), None);
+        assert_eq!(
+            keymap.action(MODE_SAVE_PENDING, one, OPTION),
+            Some(KeyAction::Slot(1))
+        );
+        assert_eq!(keymap.action(MODE_SAVE_PENDING, one, NONE), None);
+        assert_eq!(
+            keymap.action(MODE_SAVE_PENDING, esc, NONE),
+            Some(KeyAction::Cancel)
+        );
+        assert_eq!(
+            keymap.action(MODE_SAVE_PENDING, C, CMD),
+            Some(KeyAction::SaveTrigger)
+        );
+        assert!(!KeyAction::SaveTrigger.swallows());
+        assert!(KeyAction::Slot(3).swallows());
+    }
+
+    #[test]
+    fn swallowed_bindings_agree_with_actions() {
+        let keymap = Keymap::default();
+        for mode in [MODE_IDLE, MODE_SAVE_PENDING, MODE_PASTE_ACTIVE] {
+            let swallowed = keymap.swallowed_in(mode);
+            for b in &swallowed {
+                let action = keymap.action(mode, b.key(), b.modifiers());
+                assert!(
+                    action.map(KeyAction::swallows).unwrap_or(false),
+                    "{:?} in {}",
+                    b,
+                    mode
+                );
+            }
+            assert_eq!(swallowed.len(), if mode == MODE_IDLE { 1 } else { 8 });
+        }
+        assert_eq!(name_for_keycode(9), Some("v"));
+        assert_eq!(name_for_keycode(122), Some("f1"));
+        assert_eq!(name_for_keycode(52), None);
+    }
+
+    #[test]
+    fn keymap_follows_config() {
+        let keys = clip_config::Config::from_toml_str(
+            "[keys]\npaste_trigger = \"ctrl+shift+f5\"\npaste_slots = [\"h\", \"j\", \"k\", \"l\", \";\", \"'\"]\n",
+        )
+        .unwrap()
+        .keys;
+        let keymap = Keymap::from_config(&keys).unwrap();
+        let f5 = Key(keycode_for_name("f5").unwrap());
+        let quote = Key(keycode_for_name("quote").unwrap());
+        assert_eq!(
+            keymap.action(MODE_IDLE, f5, Modifiers::CTRL | Modifiers::SHIFT),
+            Some(KeyAction::PasteTrigger)
+        );
+        assert_eq!(keymap.action(MODE_IDLE, V, CMD | OPTION), None);
+        assert_eq!(
+            keymap.action(MODE_PASTE_ACTIVE, quote, NONE),
+            Some(KeyAction::Slot(6))
+        );
     }
 }
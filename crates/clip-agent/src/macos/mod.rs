@@ -1,5 +0,0 @@
-//! macOS-specific functionality.
-
-pub mod clipboard;
-pub mod event_tap;
-pub mod paste;
@@ -1,101 +1,349 @@
-#[cfg(not(target_os = "macos"))]
-use std::time::Duration;
+use clip_config::Config;
 use tracing::info;
-#[cfg(not(target_os = "macos"))]
-use tracing::warn;
 
+mod config_watch;
+mod instance;
 mod ipc;
 mod keys;
+mod payload;
 mod persistence;
+mod platform;
 mod state_machine;
+mod timer;
 
 fn main() {
     tracing_subscriber::fmt()
         .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
         .init();
 
-    info!("agent running");
+    let config = match Config::load() {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    #[cfg(target_os = "macos")]
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let headless = match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
     {
-        if let Err(e) = run_macos() {
+        [] => false,
+        ["--headless"] => true,
+        ["trigger", action @ ("save" | "paste")] => {
+            let sent = clip_config::auth::Secret::load()
+                .and_then(|secret| ipc::send_trigger(&config.ipc, &secret, action.parse()?));
+            if let Err(e) = sent {
+                eprintln!("Error: {} (is the agent running?)", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        ["stop"] => {
+            match stop_running_agent(&config) {
+                Ok(message) => println!("{}", message),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        ["request"] => {
+            match answer_stdin_request(&config) {
+                Ok(response) => println!("{}", response),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        _ => {
+            eprintln!("Usage: clip-agent [--headless | trigger save|paste | request | stop]");
+            std::process::exit(2);
+        }
+    };
+
+    // Before the key capture, the slot DB and the sockets, so a second agent touches none of them.
+    let lock_path = match instance::lock_path() {
+        Ok(path) => path,
+        Err(e) => {
             eprintln!("Error: {}", e);
             std::process::exit(1);
         }
+    };
+    let lock = match instance::InstanceLock::acquire(&lock_path) {
+        Ok(lock) => lock,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            let held = instance::running_pid(&lock_path).is_some();
+            std::process::exit(if held { instance::ALREADY_RUNNING } else { 1 });
+        }
+    };
+    // An agent the lock missed (its pid file was removed) still answers with the secret on disk;
+    // the fresh secret `run_agent` writes would lock its UI out.
+    if clip_config::auth::Secret::load()
+        .is_ok_and(|secret| ipc::agent_answers(&config.ipc, &secret))
+    {
+        eprintln!("Error: another clip-agent answers on its socket or port; stop it first");
+        drop(lock);
+        std::process::exit(instance::ALREADY_RUNNING);
     }
 
-    #[cfg(not(target_os = "macos"))]
-    {
-        info!("Keyboard capture only supported on macOS");
-        ctrlc::set_handler(move || {
-            warn!("agent stopping");
-            std::process::exit(0);
-        })
-        .expect("Error setting Ctrl+C handler");
-
-        loop {
-            std::thread::sleep(Duration::from_secs(5));
-            info!("agent running");
-        }
+    info!("agent running");
+
+    let platform = if headless {
+        info!("headless: no key capture; drive the agent over IPC");
+        Ok(platform::headless::platform())
+    } else {
+        desktop_platform()
+    };
+    let result = platform.and_then(|p| run_agent(config, p));
+    drop(lock);
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
     }
 }
 
 #[cfg(target_os = "macos")]
-fn run_macos() -> Result<(), String> {
-    use crate::ipc::udp;
-    use crate::state_machine::{run, Event};
+fn desktop_platform() -> Result<platform::Platform, String> {
+    platform::macos::platform()
+}
+
+/// Wayland when `WAYLAND_DISPLAY` is set, else X11 when `DISPLAY` is, else headless.
+#[cfg(target_os = "linux")]
+fn desktop_platform() -> Result<platform::Platform, String> {
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        platform::wayland::platform()
+    } else if std::env::var_os("DISPLAY").is_some() {
+        platform::x11::platform()
+    } else {
+        info!("no Wayland or X11 session; running headless");
+        Ok(platform::headless::platform())
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+fn desktop_platform() -> Result<platform::Platform, String> {
+    info!("no desktop backend for this OS; running headless");
+    Ok(platform::headless::platform())
+}
+
+/// Reads one JSON request (`ipc::request`) from stdin and returns the response: from the running
+/// agent if there is one, otherwise answered from the slot DB directly.
+fn answer_stdin_request(config: &Config) -> Result<String, String> {
+    use crate::ipc::request::to_request;
+    use crate::state_machine::{answer_slots, SlotStorage};
+    use clip_protocol::{encode, FromAgent, SlotRequest, SlotResponse};
+    use std::io::Read;
+
+    let mut line = String::new();
+    std::io::stdin()
+        .read_to_string(&mut line)
+        .map_err(|e| format!("read stdin: {}", e))?;
+    let v: serde_json::Value =
+        serde_json::from_str(line.trim()).map_err(|e| format!("invalid request: {}", e))?;
+    let id = v.get("id").cloned().unwrap_or(serde_json::Value::Null);
+    let message = serde_json::from_value::<SlotRequest>(v).map_err(|e| e.to_string());
+    // Without a secret file no agent has run since boot.
+    if let (Ok(message), Ok(secret)) = (&message, clip_config::auth::Secret::load()) {
+        if let Some(response) = ipc::send_request(&config.ipc, &secret, message)? {
+            return Ok(encode(&FromAgent::Response(response)));
+        }
+    }
+    let result = message.and_then(|m| to_request(&m)).and_then(|request| {
+        let conn = persistence::sqlite::init_db()?;
+        let mut slots = SlotStorage::new(Box::new(persistence::sqlite::SqliteStore::new(conn)));
+        slots.set_trimmed_slots(&config.slots.trim);
+        answer_slots(&mut slots, config, request)
+    });
+    Ok(encode(&FromAgent::Response(SlotResponse::new(id, result))))
+}
+
+/// Asks the running agent to stop and waits for it to exit.
+fn stop_running_agent(config: &Config) -> Result<String, String> {
+    use clip_protocol::{Op, SlotRequest};
+    use std::time::Duration;
+
+    let path = instance::lock_path()?;
+    let Some(pid) = instance::running_pid(&path) else {
+        return Ok("clip-agent is not running".to_string());
+    };
+    let stop = SlotRequest {
+        id: serde_json::Value::Null,
+        op: Op::Stop,
+        slot: None,
+        text: None,
+        entry: None,
+    };
+    // The agent may exit before its reply gets out, so only its exit counts.
+    let asked = clip_config::auth::Secret::load()
+        .and_then(|secret| ipc::send_request(&config.ipc, &secret, &stop));
+    if instance::wait_for_exit(&path, Duration::from_secs(5)) {
+        return Ok(format!("clip-agent stopped (pid {})", pid));
+    }
+    Err(match asked {
+        Ok(Some(_)) => format!(
+            "clip-agent (pid {}) did not exit within 5 s; end it with `kill {}`",
+            pid, pid
+        ),
+        Ok(None) => format!(
+            "clip-agent (pid {}) did not answer; end it with `kill {}`",
+            pid, pid
+        ),
+        Err(e) => format!(
+            "clip-agent (pid {}) did not answer ({}); end it with `kill {}`",
+            pid, e, pid
+        ),
+    })
+}
+
+/// Runs the agent on `platform` until Ctrl+C or a stop request: state machine and IPC on
+/// background threads, key capture on the calling thread.
+fn run_agent(config: Config, platform: platform::Platform) -> Result<(), String> {
+    use crate::ipc::{udp, RemoteChooserUi};
+    use crate::keys::Keymap;
+    use crate::platform::KeySink;
+    use crate::state_machine::{run, Event, Services};
+    use crate::timer::SystemClock;
+    use clip_config::auth::Secret;
     use std::sync::atomic::AtomicU8;
     use std::sync::mpsc;
-    use std::sync::Arc;
+    use std::sync::{Arc, RwLock};
     use std::thread;
     use tracing::warn;
 
-    if !macos::event_tap::has_accessibility_permission() {
-        return Err(
-            "Accessibility permission not granted. Run `clip doctor` to open System Settings."
-                .to_string(),
-        );
-    }
+    let keymap = Arc::new(RwLock::new(Keymap::from_config(&config.keys)?));
 
-    let persistence = match persistence::sqlite::init_db() {
+    let store: Box<dyn persistence::SlotStore> = match persistence::sqlite::init_db() {
         Ok(conn) => {
             if let Ok(path) = persistence::sqlite::db_path() {
                 info!("persistence: {}", path.display());
             } else {
                 info!("persistence: enabled");
             }
-            Some(conn)
+            Box::new(persistence::sqlite::SqliteStore::new(conn))
         }
         Err(e) => {
             warn!("persistence init failed: {}, slots in-memory only", e);
-            None
+            Box::new(persistence::memory::MemoryStore::new())
         }
     };
 
     let (tx, rx) = mpsc::channel();
     let mode = Arc::new(AtomicU8::new(0));
 
-    udp::start_response_listener(tx.clone());
+    // A fresh secret per run; the UI and `clip-agent trigger|request` read it from the file.
+    let secret = Secret::generate()?;
+    let secret_path = Secret::path()?;
+    secret
+        .write_to(&secret_path)
+        .map_err(|e| format!("IPC secret: {}", e))?;
+    info!("ipc: secret in {}", secret_path.display());
+
+    // UDP always listens: it is the fallback for UIs and scripts that cannot use the socket.
+    if let Err(e) = udp::start_response_listener(config.ipc.agent_port, secret.clone(), tx.clone())
+    {
+        warn!("ipc: UDP listener unavailable: {}", e);
+    }
+
+    // The transport is read once; switching it takes a restart.
+    #[cfg(unix)]
+    let agent_socket = match config.ipc.transport {
+        clip_config::IpcTransport::Unix => {
+            let link = ipc::unix::UiLink::new();
+            match clip_config::agent_socket_path().and_then(|path| {
+                ipc::unix::start_listener(&path, secret.clone(), tx.clone(), link.clone())
+                    .map(|()| path)
+            }) {
+                Ok(path) => Some((path, link)),
+                Err(e) => {
+                    warn!("ipc: Unix socket unavailable, using UDP only: {}", e);
+                    None
+                }
+            }
+        }
+        clip_config::IpcTransport::Udp => None,
+    };
+
+    #[cfg(unix)]
+    let control_socket = match ipc::rpc::socket_path()
+        .and_then(|path| ipc::rpc::start_control_server(&path, tx.clone()).map(|()| path))
+    {
+        Ok(path) => Some(path),
+        Err(e) => {
+            warn!("control API unavailable: {}", e);
+            None
+        }
+    };
+
+    if let Ok(path) = clip_config::config_path() {
+        let tx = tx.clone();
+        let keymap = keymap.clone();
+        config_watch::spawn(path, config.clone(), move |new| {
+            // Resolve everything before touching any component so a bad edit changes nothing.
+            let new_keymap = Keymap::from_config(&new.keys)?;
+            tx.send(Event::ConfigReloaded(Box::new(new.clone())))
+                .map_err(|_| "state machine stopped".to_string())?;
+            match keymap.write() {
+                Ok(mut k) => *k = new_keymap,
+                Err(poisoned) => *poisoned.into_inner() = new_keymap,
+            }
+            Ok(())
+        });
+    }
+
+    let platform::Platform {
+        clipboard,
+        paste,
+        mut keys,
+    } = platform;
 
     ctrlc::set_handler({
         let tx = tx.clone();
+        let stop_keys = keys.stopper();
         move || {
             tracing::warn!("agent stopping");
             let _ = tx.send(Event::Quit);
-            core_foundation::runloop::CFRunLoop::get_main().stop();
+            stop_keys();
         }
     })
     .map_err(|e| format!("Failed to set Ctrl+C handler: {}", e))?;
 
-    let state_tx = tx.clone();
+    #[cfg(unix)]
+    let (ui, agent_socket) = match agent_socket {
+        Some((path, link)) => (RemoteChooserUi::unix(secret, tx.clone(), link), Some(path)),
+        None => (RemoteChooserUi::udp(secret, tx.clone()), None),
+    };
+    #[cfg(not(unix))]
+    let ui = RemoteChooserUi::udp(secret, tx.clone());
+    let ui = Box::new(ui);
+    let services = Services {
+        clock: Box::new(SystemClock),
+        ui,
+        clipboard,
+        paste,
+    };
     let mode_state = mode.clone();
-    let state_handle = thread::spawn(move || run(rx, state_tx, mode_state, persistence));
+    // However the state machine stops (Ctrl+C or a stop request), key capture stops with it.
+    let stop_keys = keys.stopper();
+    let state_handle = thread::spawn(move || {
+        run(rx, mode_state, store, config, services);
+        stop_keys();
+    });
 
-    macos::event_tap::run_event_tap_with_sender(tx)?;
+    keys.run(KeySink::new(tx, keymap, mode))?;
 
     let _ = state_handle.join();
+    let _ = std::fs::remove_file(&secret_path);
+    #[cfg(unix)]
+    for path in [control_socket, agent_socket].into_iter().flatten() {
+        let _ = std::fs::remove_file(path);
+    }
     Ok(())
 }
-
-#[cfg(target_os = "macos")]
-mod macos;
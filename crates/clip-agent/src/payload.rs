@@ -0,0 +1,147 @@
+//! Clipboard payloads: every representation (UTI / MIME type + bytes) of a copy.
+//!
+//! A copy can hold several pasteboard items (e.g. multiple files), each with several
+//! representations of the same content (e.g. HTML, RTF and plain text of one table).
+
+/// Plain UTF-8 text.
+pub const UTI_PLAIN_TEXT: &str = "public.utf8-plain-text";
+
+/// One representation of one pasteboard item (e.g. "public.html", "public.rtf",
+/// "public.png", "public.file-url").
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Representation {
+    /// Index of the pasteboard item this belongs to (0 for single-item copies).
+    pub item: u32,
+    /// Type identifier: UTI on macOS (e.g. "public.html"), MIME type elsewhere.
+    pub uti: String,
+    pub data: Vec<u8>,
+}
+
+/// All representations of a clipboard copy, in pasteboard order.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ClipPayload {
+    pub representations: Vec<Representation>,
+}
+
+impl ClipPayload {
+    /// Single-item payload holding only plain text.
+    pub fn from_text(text: &str) -> Self {
+        Self {
+            representations: vec![Representation {
+                item: 0,
+                uti: UTI_PLAIN_TEXT.to_string(),
+                data: text.as_bytes().to_vec(),
+            }],
+        }
+    }
+
+    /// Bytes of the first representation with type `uti`.
+    pub fn get(&self, uti: &str) -> Option<&[u8]> {
+        self.representations
+            .iter()
+            .find(|r| r.uti == uti)
+            .map(|r| r.data.as_slice())
+    }
+
+    /// Plain text representation, if present and valid UTF-8.
+    pub fn text(&self) -> Option<&str> {
+        self.get(UTI_PLAIN_TEXT)
+            .and_then(|b| std::str::from_utf8(b).ok())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.representations.iter().all(|r| r.data.is_empty())
+    }
+
+    /// Total size of all representations in bytes.
+    pub fn size(&self) -> usize {
+        self.representations.iter().map(|r| r.data.len()).sum()
+    }
+
+    /// Distinct type identifiers, in pasteboard order.
+    pub fn types(&self) -> Vec<String> {
+        let mut out: Vec<String> = Vec::new();
+        for r in &self.representations {
+            if !out.contains(&r.uti) {
+                out.push(r.uti.clone());
+            }
+        }
+        out
+    }
+}
+
+/// Optional per-slot normalization applied to plain text when saving.
+/// Slots store content byte-exact unless a transform is opted into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SlotTransform {
+    /// Store exactly what was copied.
+    #[default]
+    None,
+    /// Strip leading and trailing whitespace from the plain-text representation.
+    Trim,
+}
+
+impl SlotTransform {
+    /// Name reported by `list` requests.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SlotTransform::None => "none",
+            SlotTransform::Trim => "trim",
+        }
+    }
+
+    /// Applies the transform to the plain-text representations; others are left as-is.
+    pub fn apply(self, payload: &ClipPayload) -> ClipPayload {
+        let mut out = payload.clone();
+        if self == SlotTransform::None {
+            return out;
+        }
+        for rep in out
+            .representations
+            .iter_mut()
+            .filter(|r| r.uti == UTI_PLAIN_TEXT)
+        {
+            if let Ok(text) = std::str::from_utf8(&rep.data) {
+                rep.data = text.trim().as_bytes().to_vec();
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_is_byte_exact() {
+        for text in [
+            "  indented\n\tcode\n",
+            "echo hi\n",
+            "a\tb\tc",
+            "crlf\r\n",
+            " ",
+        ] {
+            assert_eq!(ClipPayload::from_text(text).text(), Some(text));
+        }
+    }
+
+    #[test]
+    fn none_transform_keeps_whitespace() {
+        let payload = ClipPayload::from_text("  x \n");
+        assert_eq!(SlotTransform::None.apply(&payload), payload);
+    }
+
+    #[test]
+    fn trim_transform_only_touches_plain_text() {
+        let mut payload = ClipPayload::from_text("\n  x \n");
+        payload.representations.push(Representation {
+            item: 0,
+            uti: "public.html".to_string(),
+            data: b" <b>x</b> ".to_vec(),
+        });
+        let trimmed = SlotTransform::Trim.apply(&payload);
+        assert_eq!(trimmed.text(), Some("x"));
+        assert_eq!(trimmed.get("public.html"), Some(&b" <b>x</b> "[..]));
+    }
+}
@@ -0,0 +1,80 @@
+//! In-memory slot store. Used when the DB cannot be opened, and in tests.
+
+use super::{unix_now, HistoryEntry, NewHistoryEntry, SlotMetadata, SlotStore};
+use crate::keys::SlotId;
+use crate::payload::ClipPayload;
+use std::collections::{HashMap, VecDeque};
+
+/// Slots and history held in memory only; lost when the agent exits.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    slots: HashMap<SlotId, (ClipPayload, i64)>,
+    /// Newest first.
+    history: VecDeque<HistoryEntry>,
+    /// Id of the last history entry pushed, pruned or not.
+    last_history_id: i64,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SlotStore for MemoryStore {
+    fn get(&self, slot: SlotId) -> Result<Option<ClipPayload>, String> {
+        Ok(self.slots.get(&slot).map(|(content, _)| content.clone()))
+    }
+
+    fn save(&mut self, slot: SlotId, content: &ClipPayload) -> Result<(), String> {
+        self.slots.insert(slot, (content.clone(), unix_now()));
+        Ok(())
+    }
+
+    fn clear(&mut self, slot: SlotId) -> Result<(), String> {
+        self.slots.remove(&slot);
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<SlotMetadata>, String> {
+        let mut out = Vec::new();
+        for slot in SlotId::ALL {
+            if let Some(meta) = self.metadata(slot)? {
+                out.push(meta);
+            }
+        }
+        Ok(out)
+    }
+
+    fn metadata(&self, slot: SlotId) -> Result<Option<SlotMetadata>, String> {
+        Ok(self
+            .slots
+            .get(&slot)
+            .map(|(content, updated_at)| SlotMetadata {
+                slot,
+                size: content.size(),
+                types: content.types(),
+                updated_at: *updated_at,
+            }))
+    }
+
+    fn push_history(&mut self, entry: &NewHistoryEntry) -> Result<i64, String> {
+        self.last_history_id += 1;
+        self.history
+            .push_front(entry.clone().with_id(self.last_history_id));
+        Ok(self.last_history_id)
+    }
+
+    fn history(&self, limit: usize) -> Result<Vec<HistoryEntry>, String> {
+        Ok(self.history.iter().take(limit).cloned().collect())
+    }
+
+    fn history_entry(&self, id: i64) -> Result<Option<HistoryEntry>, String> {
+        Ok(self.history.iter().find(|e| e.id == id).cloned())
+    }
+
+    fn prune_history(&mut self, keep: usize) -> Result<(), String> {
+        self.history.truncate(keep);
+        Ok(())
+    }
+}
@@ -0,0 +1,326 @@
+//! Versioned schema migrations for the SQLite store.
+//!
+//! The schema version is tracked with `PRAGMA user_version`. Each entry in `MIGRATIONS`
+//! upgrades the DB by one version; migrations are append-only and must never be edited
+//! once shipped. Databases written before versioning existed report version 0 and may
+//! already contain some tables, so early migrations use `IF NOT EXISTS`.
+
+use rusqlite::Connection;
+use tracing::info;
+
+struct Migration {
+    description: &'static str,
+    sql: &'static str,
+}
+
+/// Ordered up-migrations. Entry `i` takes the schema from version `i` to `i + 1`.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        description: "slots table",
+        sql: r#"
+CREATE TABLE IF NOT EXISTS slots (
+    slot_key TEXT PRIMARY KEY,
+    content TEXT NOT NULL,
+    updated_at INTEGER NOT NULL
+);"#,
+    },
+    Migration {
+        description: "clipboard history table",
+        sql: r#"
+CREATE TABLE IF NOT EXISTS history (
+    id INTEGER PRIMARY KEY,
+    content TEXT NOT NULL,
+    content_hash TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    source TEXT NOT NULL
+);"#,
+    },
+    Migration {
+        description: "slot representations as BLOBs",
+        sql: r#"
+CREATE TABLE slot_items (
+    slot_key TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    item INTEGER NOT NULL,
+    uti TEXT NOT NULL,
+    data BLOB NOT NULL,
+    PRIMARY KEY (slot_key, position)
+);
+INSERT INTO slot_items (slot_key, position, item, uti, data)
+    SELECT slot_key, 0, 0, 'public.utf8-plain-text', CAST(content AS BLOB) FROM slots;"#,
+    },
+    Migration {
+        description: "per-slot settings (opt-in whitespace transform)",
+        sql: r#"
+CREATE TABLE slot_settings (
+    slot_key TEXT PRIMARY KEY,
+    transform TEXT NOT NULL DEFAULT 'none'
+);"#,
+    },
+    Migration {
+        description: "drop per-slot settings ([slots] trim in the config decides)",
+        sql: "DROP TABLE slot_settings;",
+    },
+    Migration {
+        description: "history ids assigned by AUTOINCREMENT",
+        sql: r#"
+CREATE TABLE history_v6 (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    content TEXT NOT NULL,
+    content_hash TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    source TEXT NOT NULL
+);
+INSERT INTO history_v6 (id, content, content_hash, created_at, source)
+    SELECT id, content, content_hash, created_at, source FROM history;
+DROP TABLE history;
+ALTER TABLE history_v6 RENAME TO history;"#,
+    },
+];
+
+/// Schema version this build writes.
+pub const LATEST_VERSION: i64 = MIGRATIONS.len() as i64;
+
+/// Current schema version of the DB (`PRAGMA user_version`).
+pub fn schema_version(conn: &Connection) -> Result<i64, String> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+        .map_err(|e| format!("read schema version: {}", e))
+}
+
+/// Brings the DB up to `LATEST_VERSION`. Refuses DBs written by a newer build.
+/// Returns the version the DB was at before migrating.
+pub fn migrate(conn: &mut Connection) -> Result<i64, String> {
+    migrate_to(conn, LATEST_VERSION)
+}
+
+fn migrate_to(conn: &mut Connection, target: i64) -> Result<i64, String> {
+    let from = schema_version(conn)?;
+    if from > LATEST_VERSION {
+        return Err(format!(
+            "database schema version {} is newer than this build supports ({}); upgrade Slotpaste",
+            from, LATEST_VERSION
+        ));
+    }
+    for (i, m) in MIGRATIONS
+        .iter()
+        .enumerate()
+        .take(target as usize)
+        .skip(from as usize)
+    {
+        let version = i as i64 + 1;
+        let tx = conn
+            .transaction()
+            .map_err(|e| format!("begin migration {}: {}", version, e))?;
+        tx.execute_batch(m.sql)
+            .map_err(|e| format!("migration {} ({}): {}", version, m.description, e))?;
+        tx.pragma_update(None, "user_version", version)
+            .map_err(|e| format!("set schema version {}: {}", version, e))?;
+        tx.commit()
+            .map_err(|e| format!("commit migration {}: {}", version, e))?;
+        info!(
+            "persistence: migrated schema to v{} ({})",
+            version, m.description
+        );
+    }
+    Ok(from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::payload::ClipPayload;
+    use crate::persistence::sqlite;
+
+    /// Schema written by builds before history existed (unversioned).
+    const FIXTURE_V0_SLOTS_ONLY: &str = r#"
+CREATE TABLE slots (
+    slot_key TEXT PRIMARY KEY,
+    content TEXT NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+INSERT INTO slots VALUES ('J', 'hello', 1700000000);
+INSERT INTO slots VALUES ('O', 'world', 1700000001);
+"#;
+
+    /// Schema written by the first history builds (unversioned, both tables).
+    const FIXTURE_V0_WITH_HISTORY: &str = r#"
+CREATE TABLE slots (
+    slot_key TEXT PRIMARY KEY,
+    content TEXT NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+CREATE TABLE history (
+    id INTEGER PRIMARY KEY,
+    content TEXT NOT NULL,
+    content_hash TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    source TEXT NOT NULL
+);
+INSERT INTO slots VALUES ('K', 'kept', 1700000000);
+INSERT INTO history VALUES (7, 'copied', 'abc', 1700000002, 'cmd+c');
+"#;
+
+    /// Schema v2: text slots plus history.
+    const FIXTURE_V2: &str = r#"
+CREATE TABLE slots (
+    slot_key TEXT PRIMARY KEY,
+    content TEXT NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+CREATE TABLE history (
+    id INTEGER PRIMARY KEY,
+    content TEXT NOT NULL,
+    content_hash TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    source TEXT NOT NULL
+);
+INSERT INTO slots VALUES ('U', 'plain', 1700000000);
+PRAGMA user_version = 2;
+"#;
+
+    /// Schema v3: slot representations as BLOBs.
+    const FIXTURE_V3: &str = r#"
+CREATE TABLE slots (
+    slot_key TEXT PRIMARY KEY,
+    content TEXT NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+CREATE TABLE history (
+    id INTEGER PRIMARY KEY,
+    content TEXT NOT NULL,
+    content_hash TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    source TEXT NOT NULL
+);
+CREATE TABLE slot_items (
+    slot_key TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    item INTEGER NOT NULL,
+    uti TEXT NOT NULL,
+    data BLOB NOT NULL,
+    PRIMARY KEY (slot_key, position)
+);
+INSERT INTO slots VALUES ('I', '  x
+', 1700000000);
+INSERT INTO slot_items VALUES ('I', 0, 0, 'public.utf8-plain-text', CAST('  x
+' AS BLOB));
+PRAGMA user_version = 3;
+"#;
+
+    fn fixture(sql: &str) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(sql).unwrap();
+        conn
+    }
+
+    #[test]
+    fn fresh_db_migrates_to_latest() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert_eq!(migrate(&mut conn).unwrap(), 0);
+        assert_eq!(schema_version(&conn).unwrap(), LATEST_VERSION);
+        assert_eq!(sqlite::get_slot(&conn, "J").unwrap(), None);
+        assert!(sqlite::load_history(&conn, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn unversioned_slots_only_db_keeps_slots() {
+        let mut conn = fixture(FIXTURE_V0_SLOTS_ONLY);
+        migrate(&mut conn).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), LATEST_VERSION);
+        assert_eq!(
+            sqlite::get_slot(&conn, "J").unwrap(),
+            Some(ClipPayload::from_text("hello"))
+        );
+        assert_eq!(
+            sqlite::get_slot(&conn, "O").unwrap(),
+            Some(ClipPayload::from_text("world"))
+        );
+        assert!(sqlite::load_history(&conn, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn unversioned_history_db_keeps_history() {
+        let mut conn = fixture(FIXTURE_V0_WITH_HISTORY);
+        migrate(&mut conn).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), LATEST_VERSION);
+        assert_eq!(
+            sqlite::get_slot(&conn, "K").unwrap(),
+            Some(ClipPayload::from_text("kept"))
+        );
+        let history = sqlite::load_history(&conn, 10).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].id, 7);
+        assert_eq!(history[0].content, "copied");
+
+        // Ids carry on from the kept entries, and past them once they are pruned.
+        sqlite::prune_history(&conn, 0).unwrap();
+        let entry = crate::persistence::NewHistoryEntry {
+            content: "next".into(),
+            content_hash: "def".into(),
+            created_at: 1700000003,
+            source: "cmd+c".into(),
+        };
+        assert_eq!(sqlite::insert_history(&conn, &entry).unwrap(), 8);
+    }
+
+    #[test]
+    fn v2_text_slots_become_plain_text_representations() {
+        let mut conn = fixture(FIXTURE_V2);
+        assert_eq!(migrate(&mut conn).unwrap(), 2);
+        assert_eq!(
+            sqlite::get_slot(&conn, "U").unwrap(),
+            Some(ClipPayload::from_text("plain"))
+        );
+    }
+
+    #[test]
+    fn v3_slots_keep_their_whitespace_and_no_settings_table() {
+        let mut conn = fixture(FIXTURE_V3);
+        assert_eq!(migrate(&mut conn).unwrap(), 3);
+        assert_eq!(
+            sqlite::get_slot(&conn, "I").unwrap(),
+            Some(ClipPayload::from_text("  x\n"))
+        );
+        let settings: i64 = conn
+            .query_row(
+                "SELECT count(*) FROM sqlite_master WHERE name = 'slot_settings'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(settings, 0);
+    }
+
+    #[test]
+    fn every_version_migrates_to_latest() {
+        for version in 0..=LATEST_VERSION {
+            let mut conn = Connection::open_in_memory().unwrap();
+            migrate_to(&mut conn, version).unwrap();
+            assert_eq!(schema_version(&conn).unwrap(), version);
+            assert_eq!(migrate(&mut conn).unwrap(), version);
+            assert_eq!(schema_version(&conn).unwrap(), LATEST_VERSION);
+            sqlite::upsert_slot(&conn, "J", &ClipPayload::from_text("x")).unwrap();
+        }
+    }
+
+    #[test]
+    fn migrate_is_idempotent() {
+        let mut conn = fixture(FIXTURE_V0_SLOTS_ONLY);
+        migrate(&mut conn).unwrap();
+        assert_eq!(migrate(&mut conn).unwrap(), LATEST_VERSION);
+        assert_eq!(
+            sqlite::get_slot(&conn, "O").unwrap(),
+            Some(ClipPayload::from_text("world"))
+        );
+    }
+
+    #[test]
+    fn newer_db_is_refused() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", LATEST_VERSION + 1)
+            .unwrap();
+        let err = migrate(&mut conn).unwrap_err();
+        assert!(err.contains("newer"), "{}", err);
+        assert_eq!(schema_version(&conn).unwrap(), LATEST_VERSION + 1);
+    }
+}
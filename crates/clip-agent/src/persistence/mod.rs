@@ -1,3 +1,209 @@
-//! Persistence for slot content (SQLite).
+//! Persistence for slot content and clipboard history.
+//!
+//! `SlotStore` is the backend interface; `sqlite::SqliteStore` is used in production and
+//! `memory::MemoryStore` when no DB is available (and in tests).
 
+pub mod memory;
+pub mod migrations;
 pub mod sqlite;
+
+use crate::keys::SlotId;
+use crate::payload::ClipPayload;
+use sha2::{Digest, Sha256};
+
+/// Storage backend for slots and clipboard history.
+pub trait SlotStore: Send {
+    /// Content of a slot, or None if never saved / cleared.
+    fn get(&self, slot: SlotId) -> Result<Option<ClipPayload>, String>;
+    /// Replaces a slot's content (all representations).
+    fn save(&mut self, slot: SlotId, content: &ClipPayload) -> Result<(), String>;
+    /// Empties a slot. Clearing an empty slot is not an error.
+    fn clear(&mut self, slot: SlotId) -> Result<(), String>;
+    /// Metadata for every filled slot, in chooser order.
+    fn list(&self) -> Result<Vec<SlotMetadata>, String>;
+    /// Metadata for one slot, or None if empty.
+    fn metadata(&self, slot: SlotId) -> Result<Option<SlotMetadata>, String>;
+
+    /// Appends a history entry and returns the id the store gave it. Ids are never reused, even
+    /// after every entry was pruned.
+    fn push_history(&mut self, entry: &NewHistoryEntry) -> Result<i64, String>;
+    /// Up to `limit` history entries, newest first.
+    fn history(&self, limit: usize) -> Result<Vec<HistoryEntry>, String>;
+    /// One history entry by id.
+    fn history_entry(&self, id: i64) -> Result<Option<HistoryEntry>, String>;
+    /// Drops all but the newest `keep` history entries.
+    fn prune_history(&mut self, keep: usize) -> Result<(), String>;
+}
+
+/// Slot metadata (without content).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlotMetadata {
+    pub slot: SlotId,
+    /// Total size of all representations in bytes.
+    pub size: usize,
+    /// Representation types, e.g. ["public.html", "public.utf8-plain-text"].
+    pub types: Vec<String>,
+    /// Unix epoch seconds of the last save.
+    pub updated_at: i64,
+}
+
+/// One clipboard history row: content captured on Cmd+C.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub id: i64,
+    pub content: String,
+    /// Hex SHA-256 of `content`; used to skip consecutive duplicates.
+    pub content_hash: String,
+    /// Unix epoch seconds.
+    pub created_at: i64,
+    /// What produced the entry (e.g. "cmd+c").
+    pub source: String,
+}
+
+/// A history entry before the store gives it an id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewHistoryEntry {
+    pub content: String,
+    pub content_hash: String,
+    pub created_at: i64,
+    pub source: String,
+}
+
+impl NewHistoryEntry {
+    /// The stored entry, under `id`.
+    pub fn with_id(self, id: i64) -> HistoryEntry {
+        HistoryEntry {
+            id,
+            content: self.content,
+            content_hash: self.content_hash,
+            created_at: self.created_at,
+            source: self.source,
+        }
+    }
+}
+
+/// Hex SHA-256 of clipboard content.
+pub fn content_hash(content: &str) -> String {
+    Sha256::digest(content.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Current time as unix epoch seconds.
+pub fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::payload::Representation;
+
+    fn entry(n: i64, content: &str) -> NewHistoryEntry {
+        NewHistoryEntry {
+            content: content.to_string(),
+            content_hash: content_hash(content),
+            created_at: 1_700_000_000 + n,
+            source: "cmd+c".to_string(),
+        }
+    }
+
+    /// Table copied from a browser plus a second pasteboard item holding an image.
+    fn rich_payload() -> ClipPayload {
+        let rep = |item, uti: &str, data: &[u8]| Representation {
+            item,
+            uti: uti.to_string(),
+            data: data.to_vec(),
+        };
+        ClipPayload {
+            representations: vec![
+                rep(0, "public.html", b"<table><tr><td>a</td></tr></table>"),
+                rep(0, "public.utf8-plain-text", b"a"),
+                rep(1, "public.png", &[0x89, b'P', b'N', b'G', 0, 0xff]),
+            ],
+        }
+    }
+
+    /// Contract shared by every backend.
+    fn exercise_store(store: &mut dyn SlotStore) {
+        assert_eq!(store.get(SlotId::J).unwrap(), None);
+        assert!(store.list().unwrap().is_empty());
+
+        store
+            .save(SlotId::L, &ClipPayload::from_text("third"))
+            .unwrap();
+        store
+            .save(SlotId::J, &ClipPayload::from_text("first"))
+            .unwrap();
+        store.save(SlotId::J, &rich_payload()).unwrap();
+        assert_eq!(store.get(SlotId::J).unwrap(), Some(rich_payload()));
+        let meta = store.metadata(SlotId::J).unwrap().unwrap();
+        assert_eq!(meta.slot, SlotId::J);
+        assert_eq!(meta.size, rich_payload().size());
+        assert_eq!(
+            meta.types,
+            vec!["public.html", "public.utf8-plain-text", "public.png"]
+        );
+        assert!(meta.updated_at > 0);
+        let listed: Vec<SlotId> = store.list().unwrap().into_iter().map(|m| m.slot).collect();
+        assert_eq!(listed, vec![SlotId::J, SlotId::L]);
+
+        store.clear(SlotId::J).unwrap();
+        store.clear(SlotId::K).unwrap();
+        assert_eq!(store.get(SlotId::J).unwrap(), None);
+        assert_eq!(store.metadata(SlotId::J).unwrap(), None);
+        assert_eq!(store.list().unwrap().len(), 1);
+
+        for n in 1..=5 {
+            assert_eq!(
+                store
+                    .push_history(&entry(n, &format!("copy {}", n)))
+                    .unwrap(),
+                n
+            );
+        }
+        let ids: Vec<i64> = store
+            .history(3)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.id)
+            .collect();
+        assert_eq!(ids, vec![5, 4, 3]);
+        assert_eq!(
+            store.history_entry(2).unwrap(),
+            Some(entry(2, "copy 2").with_id(2))
+        );
+        store.prune_history(2).unwrap();
+        let ids: Vec<i64> = store
+            .history(10)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.id)
+            .collect();
+        assert_eq!(ids, vec![5, 4]);
+        assert_eq!(store.history_entry(2).unwrap(), None);
+
+        store.prune_history(0).unwrap();
+        assert_eq!(
+            store.push_history(&entry(6, "copy 6")).unwrap(),
+            6,
+            "ids go on after a full prune"
+        );
+    }
+
+    #[test]
+    fn memory_store_contract() {
+        exercise_store(&mut memory::MemoryStore::new());
+    }
+
+    #[test]
+    fn sqlite_store_contract() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        migrations::migrate(&mut conn).unwrap();
+        exercise_store(&mut sqlite::SqliteStore::new(conn));
+    }
+}
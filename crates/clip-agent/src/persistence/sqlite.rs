@@ -1,16 +1,12 @@
-//! SQLite persistence for slots. DB path: macOS ~/Library/Application Support/Slotpaste/slotpaste.db,
-//! other ~/.slotpaste/slotpaste.db.
+//! SQLite persistence for slots (all clipboard representations, as BLOBs) and clipboard
+//! history. DB path: macOS ~/Library/Application Support/Slotpaste/slotpaste.db, other
+//! ~/.slotpaste/slotpaste.db.
 
-use rusqlite::Connection;
-use std::collections::HashMap;
-use std::path::PathBuf;
-
-const CREATE_TABLE: &str = r#"
-CREATE TABLE IF NOT EXISTS slots (
-    slot_key TEXT PRIMARY KEY,
-    content TEXT NOT NULL,
-    updated_at INTEGER NOT NULL
-)"#;
+use super::{HistoryEntry, NewHistoryEntry, SlotMetadata, SlotStore};
+use crate::keys::SlotId;
+use crate::payload::{ClipPayload, Representation};
+use rusqlite::{Connection, OptionalExtension};
+use std::path::{Path, PathBuf};
 
 /// Returns DB path and creates parent dirs. macOS: ~/Library/Application Support/Slotpaste/slotpaste.db;
 /// other: ~/.slotpaste/slotpaste.db.
@@ -28,44 +24,242 @@ pub fn db_path() -> Result<PathBuf, String> {
     Ok(PathBuf::from(dir).join(file))
 }
 
-/// Open DB and create table if not exists.
+/// Open DB at the default path and migrate it to the latest schema.
 pub fn init_db() -> Result<Connection, String> {
-    let path = db_path()?;
-    let conn = Connection::open(&path).map_err(|e| format!("open db: {}", e))?;
-    conn.execute(CREATE_TABLE, [])
-        .map_err(|e| format!("create table: {}", e))?;
+    open_db(&db_path()?)
+}
+
+/// Open DB at `path` and migrate it to the latest schema.
+/// Fails if the DB was written by a newer schema version.
+pub fn open_db(path: &Path) -> Result<Connection, String> {
+    let mut conn = Connection::open(path).map_err(|e| format!("open db: {}", e))?;
+    super::migrations::migrate(&mut conn)?;
     Ok(conn)
 }
 
-/// Load all slots: slot_key -> content.
-pub fn load_all(conn: &Connection) -> Result<HashMap<String, String>, String> {
-    let mut stmt = conn
-        .prepare("SELECT slot_key, content FROM slots")
-        .map_err(|e| format!("prepare load: {}", e))?;
-    let rows = stmt
-        .query_map([], |row| {
-            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
-        })
-        .map_err(|e| format!("query: {}", e))?;
-    let mut out = HashMap::new();
-    for row in rows {
-        let (k, v) = row.map_err(|e| format!("row: {}", e))?;
-        out.insert(k, v);
+/// `SlotStore` backed by a migrated SQLite connection.
+pub struct SqliteStore {
+    conn: Connection,
+}
+
+impl SqliteStore {
+    /// Wraps a connection already migrated with `open_db` / `migrations::migrate`.
+    pub fn new(conn: Connection) -> Self {
+        Self { conn }
+    }
+}
+
+impl SlotStore for SqliteStore {
+    fn get(&self, slot: SlotId) -> Result<Option<ClipPayload>, String> {
+        get_slot(&self.conn, slot.label())
+    }
+
+    fn save(&mut self, slot: SlotId, content: &ClipPayload) -> Result<(), String> {
+        upsert_slot(&self.conn, slot.label(), content)
+    }
+
+    fn clear(&mut self, slot: SlotId) -> Result<(), String> {
+        delete_slot(&self.conn, slot.label())
+    }
+
+    fn list(&self) -> Result<Vec<SlotMetadata>, String> {
+        let mut out = Vec::new();
+        for slot in SlotId::ALL {
+            if let Some(meta) = self.metadata(slot)? {
+                out.push(meta);
+            }
+        }
+        Ok(out)
+    }
+
+    fn metadata(&self, slot: SlotId) -> Result<Option<SlotMetadata>, String> {
+        let updated_at: Option<i64> = self
+            .conn
+            .query_row(
+                "SELECT updated_at FROM slots WHERE slot_key = ?1",
+                [slot.label()],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| format!("slot metadata: {}", e))?;
+        let Some(updated_at) = updated_at else {
+            return Ok(None);
+        };
+        // Sizes come from SQL so listing slots does not load their payloads.
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT uti, length(data) FROM slot_items WHERE slot_key = ?1 ORDER BY position",
+            )
+            .map_err(|e| format!("prepare slot metadata: {}", e))?;
+        let rows = stmt
+            .query_map([slot.label()], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })
+            .map_err(|e| format!("slot metadata: {}", e))?;
+        let (mut size, mut types) = (0, Vec::new());
+        for row in rows {
+            let (uti, len) = row.map_err(|e| format!("row: {}", e))?;
+            size += len as usize;
+            if !types.contains(&uti) {
+                types.push(uti);
+            }
+        }
+        Ok(Some(SlotMetadata {
+            slot,
+            size,
+            types,
+            updated_at,
+        }))
+    }
+
+    fn push_history(&mut self, entry: &NewHistoryEntry) -> Result<i64, String> {
+        insert_history(&self.conn, entry)
+    }
+
+    fn history(&self, limit: usize) -> Result<Vec<HistoryEntry>, String> {
+        load_history(&self.conn, limit)
+    }
+
+    fn history_entry(&self, id: i64) -> Result<Option<HistoryEntry>, String> {
+        get_history(&self.conn, id)
+    }
+
+    fn prune_history(&mut self, keep: usize) -> Result<(), String> {
+        prune_history(&self.conn, keep)
     }
-    Ok(out)
 }
 
-/// Upsert slot by key. updated_at = unix epoch seconds.
-pub fn upsert_slot(conn: &Connection, slot_key: &str, content: &str) -> Result<(), String> {
+/// Upsert slot by key, replacing all its representations. updated_at = unix epoch seconds.
+/// `slots.content` keeps the plain-text representation (or "") for older builds and debugging.
+pub fn upsert_slot(conn: &Connection, slot_key: &str, payload: &ClipPayload) -> Result<(), String> {
     let now = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .map_err(|e| format!("time: {}", e))?
         .as_secs() as i64;
-    conn.execute(
+    let tx = conn
+        .unchecked_transaction()
+        .map_err(|e| format!("begin upsert: {}", e))?;
+    tx.execute(
         "INSERT INTO slots (slot_key, content, updated_at) VALUES (?1, ?2, ?3)
          ON CONFLICT(slot_key) DO UPDATE SET content = ?2, updated_at = ?3",
-        rusqlite::params![slot_key, content, now],
+        rusqlite::params![slot_key, payload.text().unwrap_or(""), now],
     )
     .map_err(|e| format!("upsert: {}", e))?;
+    tx.execute("DELETE FROM slot_items WHERE slot_key = ?1", [slot_key])
+        .map_err(|e| format!("upsert items: {}", e))?;
+    for (position, rep) in payload.representations.iter().enumerate() {
+        tx.execute(
+            "INSERT INTO slot_items (slot_key, position, item, uti, data) VALUES (?1, ?2, ?3, ?4, ?5)",
+            rusqlite::params![slot_key, position as i64, rep.item, rep.uti, rep.data],
+        )
+        .map_err(|e| format!("upsert items: {}", e))?;
+    }
+    tx.commit().map_err(|e| format!("commit upsert: {}", e))?;
     Ok(())
 }
+
+/// All representations of one slot by key, or None if the slot was never saved.
+pub fn get_slot(conn: &Connection, slot_key: &str) -> Result<Option<ClipPayload>, String> {
+    let exists: Option<i64> = conn
+        .query_row(
+            "SELECT 1 FROM slots WHERE slot_key = ?1",
+            [slot_key],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| format!("get slot: {}", e))?;
+    if exists.is_none() {
+        return Ok(None);
+    }
+    let mut stmt = conn
+        .prepare("SELECT item, uti, data FROM slot_items WHERE slot_key = ?1 ORDER BY position")
+        .map_err(|e| format!("prepare get slot: {}", e))?;
+    let rows = stmt
+        .query_map([slot_key], |row| {
+            Ok(Representation {
+                item: row.get(0)?,
+                uti: row.get(1)?,
+                data: row.get(2)?,
+            })
+        })
+        .map_err(|e| format!("query slot items: {}", e))?;
+    let representations = rows
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("row: {}", e))?;
+    Ok(Some(ClipPayload { representations }))
+}
+
+/// Delete slot by key. No-op if absent.
+pub fn delete_slot(conn: &Connection, slot_key: &str) -> Result<(), String> {
+    let tx = conn
+        .unchecked_transaction()
+        .map_err(|e| format!("begin delete: {}", e))?;
+    tx.execute("DELETE FROM slot_items WHERE slot_key = ?1", [slot_key])
+        .map_err(|e| format!("delete slot items: {}", e))?;
+    tx.execute("DELETE FROM slots WHERE slot_key = ?1", [slot_key])
+        .map_err(|e| format!("delete slot: {}", e))?;
+    tx.commit().map_err(|e| format!("commit delete: {}", e))?;
+    Ok(())
+}
+
+/// Append a history entry and return the id SQLite gave it (AUTOINCREMENT, so never reused).
+pub fn insert_history(conn: &Connection, entry: &NewHistoryEntry) -> Result<i64, String> {
+    conn.execute(
+        "INSERT INTO history (content, content_hash, created_at, source) VALUES (?1, ?2, ?3, ?4)",
+        rusqlite::params![
+            entry.content,
+            entry.content_hash,
+            entry.created_at,
+            entry.source
+        ],
+    )
+    .map_err(|e| format!("insert history: {}", e))?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Delete all but the newest `keep` history entries.
+pub fn prune_history(conn: &Connection, keep: usize) -> Result<(), String> {
+    conn.execute(
+        "DELETE FROM history WHERE id <= (SELECT id FROM history ORDER BY id DESC LIMIT 1 OFFSET ?1)",
+        [keep as i64],
+    )
+    .map_err(|e| format!("prune history: {}", e))?;
+    Ok(())
+}
+
+/// Load up to `limit` history entries, newest first.
+pub fn load_history(conn: &Connection, limit: usize) -> Result<Vec<HistoryEntry>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, content, content_hash, created_at, source FROM history
+             ORDER BY id DESC LIMIT ?1",
+        )
+        .map_err(|e| format!("prepare load history: {}", e))?;
+    let rows = stmt
+        .query_map([limit as i64], history_from_row)
+        .map_err(|e| format!("query history: {}", e))?;
+    rows.collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("row: {}", e))
+}
+
+/// Fetch one history entry by id.
+pub fn get_history(conn: &Connection, id: i64) -> Result<Option<HistoryEntry>, String> {
+    conn.query_row(
+        "SELECT id, content, content_hash, created_at, source FROM history WHERE id = ?1",
+        [id],
+        history_from_row,
+    )
+    .optional()
+    .map_err(|e| format!("get history: {}", e))
+}
+
+fn history_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<HistoryEntry> {
+    Ok(HistoryEntry {
+        id: row.get(0)?,
+        content: row.get(1)?,
+        content_hash: row.get(2)?,
+        created_at: row.get(3)?,
+        source: row.get(4)?,
+    })
+}
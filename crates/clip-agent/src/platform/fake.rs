@@ -0,0 +1,165 @@
+//! In-memory stand-ins for the clipboard, key capture, paste injection and chooser UI. Clones
+//! share state, so a test keeps one handle and gives the other to the agent.
+
+use super::{ClipboardBackend, KeySink, KeySource, PasteInjector, Stopper};
+use crate::ipc::ChooserUi;
+use crate::keys::{Key, Modifiers};
+use crate::payload::ClipPayload;
+use clip_config::IpcConfig;
+use clip_protocol::ChooserKeys;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Clipboard whose content the test sets directly.
+#[derive(Clone, Default)]
+pub struct FakeClipboard {
+    content: Arc<Mutex<Option<ClipPayload>>>,
+}
+
+impl FakeClipboard {
+    pub fn set_text(&self, text: &str) {
+        *self.content.lock().unwrap() = Some(ClipPayload::from_text(text));
+    }
+
+    pub fn clear(&self) {
+        *self.content.lock().unwrap() = None;
+    }
+}
+
+impl ClipboardBackend for FakeClipboard {
+    fn read(&self) -> Option<ClipPayload> {
+        self.content.lock().unwrap().clone()
+    }
+}
+
+/// Key source that replays a fixed list of key-downs, recording which ones were swallowed.
+#[derive(Clone, Default)]
+pub struct ScriptedKeys {
+    script: Vec<(Key, Modifiers)>,
+    swallowed: Arc<Mutex<Vec<bool>>>,
+}
+
+impl ScriptedKeys {
+    pub fn new(script: Vec<(Key, Modifiers)>) -> Self {
+        Self {
+            script,
+            swallowed: Default::default(),
+        }
+    }
+
+    /// For each scripted key-down, whether the sink asked to swallow it.
+    pub fn swallowed(&self) -> Vec<bool> {
+        self.swallowed.lock().unwrap().clone()
+    }
+}
+
+impl KeySource for ScriptedKeys {
+    fn run(&mut self, sink: KeySink) -> Result<(), String> {
+        for &(key, modifiers) in &self.script {
+            let swallow = sink.key_down(key, modifiers);
+            self.swallowed.lock().unwrap().push(swallow);
+            sink.key_up(key, modifiers);
+        }
+        Ok(())
+    }
+
+    fn stopper(&self) -> Stopper {
+        Box::new(|| {})
+    }
+}
+
+/// Records every paste and copy instead of performing it.
+#[derive(Clone, Default)]
+pub struct RecordingPaste {
+    pasted: Arc<Mutex<Vec<ClipPayload>>>,
+    copied: Arc<Mutex<Vec<ClipPayload>>>,
+}
+
+impl RecordingPaste {
+    /// Pasted contents in order, as text.
+    pub fn texts(&self) -> Vec<String> {
+        self.pasted
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|p| p.text().unwrap_or("").to_string())
+            .collect()
+    }
+
+    /// Copied contents in order, as text.
+    pub fn copied_texts(&self) -> Vec<String> {
+        self.copied
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|p| p.text().unwrap_or("").to_string())
+            .collect()
+    }
+}
+
+impl PasteInjector for RecordingPaste {
+    fn paste(&self, content: ClipPayload, _restore_delay: Duration) {
+        self.pasted.lock().unwrap().push(content);
+    }
+
+    fn copy(&self, content: ClipPayload) -> Result<(), String> {
+        self.copied.lock().unwrap().push(content);
+        Ok(())
+    }
+}
+
+/// A message the agent sent to the chooser UI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UiCall {
+    Show {
+        mode: String,
+        token: String,
+        timeout_ms: u64,
+        keys: ChooserKeys,
+    },
+    Hide {
+        token: String,
+    },
+}
+
+/// Records show/hide instead of sending them.
+#[derive(Clone, Default)]
+pub struct RecordingUi {
+    calls: Arc<Mutex<Vec<UiCall>>>,
+}
+
+impl RecordingUi {
+    /// Calls since the last `take`, without clearing them.
+    pub fn calls(&self) -> Vec<UiCall> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Calls since the last `take`.
+    pub fn take(&self) -> Vec<UiCall> {
+        std::mem::take(&mut *self.calls.lock().unwrap())
+    }
+}
+
+impl ChooserUi for RecordingUi {
+    fn show(
+        &mut self,
+        _ipc: &IpcConfig,
+        mode: &str,
+        token: &str,
+        timeout_ms: u64,
+        keys: ChooserKeys,
+    ) {
+        self.calls.lock().unwrap().push(UiCall::Show {
+            mode: mode.to_string(),
+            token: token.to_string(),
+            timeout_ms,
+            keys,
+        });
+    }
+
+    fn hide(&mut self, _ipc: &IpcConfig, token: &str) {
+        self.calls.lock().unwrap().push(UiCall::Hide {
+            token: token.to_string(),
+        });
+    }
+}
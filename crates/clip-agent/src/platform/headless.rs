@@ -0,0 +1,44 @@
+//! Headless platform for servers, CI and remote sessions: no key capture, and a clipboard that
+//! lives in the agent. Pastes put the slot on it and clipboard saves read from it; scripts drive
+//! everything over IPC.
+
+use super::{ClipboardBackend, NoKeyCapture, PasteInjector, Platform};
+use crate::payload::ClipPayload;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The agent's own clipboard. Clones share it.
+#[derive(Clone, Default)]
+pub struct MemoryClipboard {
+    content: Arc<Mutex<Option<ClipPayload>>>,
+}
+
+impl ClipboardBackend for MemoryClipboard {
+    fn read(&self) -> Option<ClipPayload> {
+        self.content
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .clone()
+    }
+}
+
+impl PasteInjector for MemoryClipboard {
+    /// There is no app to paste into, so the content stays on the clipboard.
+    fn paste(&self, content: ClipPayload, _restore_delay: Duration) {
+        let _ = self.copy(content);
+    }
+
+    fn copy(&self, content: ClipPayload) -> Result<(), String> {
+        *self.content.lock().unwrap_or_else(|p| p.into_inner()) = Some(content);
+        Ok(())
+    }
+}
+
+pub fn platform() -> Platform {
+    let clipboard = MemoryClipboard::default();
+    Platform {
+        clipboard: Box::new(clipboard.clone()),
+        paste: Box::new(clipboard),
+        keys: Box::new(NoKeyCapture::new()),
+    }
+}
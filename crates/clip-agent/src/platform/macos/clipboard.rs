@@ -0,0 +1,216 @@
+//! macOS clipboard access via NSPasteboard.
+//!
+//! Reads and writes every representation of every pasteboard item, so HTML, RTF, images
+//! and file URLs survive a save/paste round trip. Clipboard read with retry for "settle"
+//! delay after Cmd+C.
+
+use crate::payload::{ClipPayload, Representation};
+use crate::platform::{ClipboardBackend, CLIPBOARD_READ_WAIT};
+use objc::rc::autoreleasepool;
+use objc::runtime::{Object, BOOL, NO};
+use objc::{class, msg_send, sel, sel_impl};
+use std::ffi::{c_void, CStr};
+use std::os::raw::c_char;
+use std::time::Duration;
+use tracing::debug;
+
+#[link(name = "AppKit", kind = "framework")]
+extern "C" {}
+
+type Id = *mut Object;
+
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+const MAX_TRIES: u32 = 6;
+const NS_UTF8_STRING_ENCODING: usize = 4;
+
+/// The general pasteboard as a `ClipboardBackend`.
+pub struct Pasteboard;
+
+impl ClipboardBackend for Pasteboard {
+    fn read(&self) -> Option<ClipPayload> {
+        read_payload_with_retry(CLIPBOARD_READ_WAIT)
+    }
+
+    fn read_text(&self) -> Option<String> {
+        read_text_with_retry(CLIPBOARD_READ_WAIT)
+    }
+}
+
+/// Reads all pasteboard representations with retry.
+/// Handles timing: clipboard may update slightly after Cmd+C.
+/// Retries up to ~300ms (6 tries * 50ms) before giving up.
+///
+/// Returns None if the clipboard is empty or all retries fail.
+pub fn read_payload_with_retry(max_wait: Duration) -> Option<ClipPayload> {
+    let total_tries = (max_wait.as_millis() / RETRY_INTERVAL.as_millis()).max(1) as u32;
+    let tries = total_tries.min(MAX_TRIES);
+
+    for attempt in 0..tries {
+        if attempt > 0 {
+            std::thread::sleep(RETRY_INTERVAL);
+        }
+
+        if let Some(payload) = read_payload() {
+            debug!("Clipboard read succeeded on attempt {}", attempt + 1);
+            return Some(payload);
+        }
+    }
+
+    None
+}
+
+/// Reads plain text from the clipboard with retry, byte-exact (whitespace is kept).
+/// Returns None if there is no text.
+pub fn read_text_with_retry(max_wait: Duration) -> Option<String> {
+    let payload = read_payload_with_retry(max_wait)?;
+    let text = payload.text()?;
+    if text.is_empty() {
+        return None;
+    }
+    Some(text.to_string())
+}
+
+/// Reads every representation of every pasteboard item. Returns None if empty.
+pub fn read_payload() -> Option<ClipPayload> {
+    read_items(false).ok().flatten()
+}
+
+/// Like `read_payload` for a paste to restore afterwards, but an error when the pasteboard or
+/// any type it offers cannot be read, so a failed read is not taken for an empty clipboard.
+pub fn snapshot() -> Result<Option<ClipPayload>, String> {
+    read_items(true)
+}
+
+/// Every representation of every item. A type whose data does not arrive is skipped, or with
+/// `strict` is an error.
+fn read_items(strict: bool) -> Result<Option<ClipPayload>, String> {
+    let representations = autoreleasepool(|| unsafe {
+        let pb = general_pasteboard().ok_or("pasteboard unavailable")?;
+        let items: Id = msg_send![pb, pasteboardItems];
+        if items.is_null() {
+            return Err("pasteboard items unavailable".to_string());
+        }
+        let mut out = Vec::new();
+        let count: usize = msg_send![items, count];
+        for i in 0..count {
+            let item: Id = msg_send![items, objectAtIndex: i];
+            let types: Id = msg_send![item, types];
+            if types.is_null() {
+                continue;
+            }
+            let type_count: usize = msg_send![types, count];
+            for j in 0..type_count {
+                let uti: Id = msg_send![types, objectAtIndex: j];
+                let data: Id = msg_send![item, dataForType: uti];
+                let Some(uti) = string_from_ns(uti) else {
+                    continue;
+                };
+                if data.is_null() {
+                    if strict {
+                        return Err(format!("no data for {}", uti));
+                    }
+                    continue;
+                }
+                out.push(Representation {
+                    item: i as u32,
+                    uti,
+                    data: bytes_from_nsdata(data),
+                });
+            }
+        }
+        Ok(out)
+    })?;
+    let payload = ClipPayload { representations };
+    Ok(if payload.is_empty() {
+        None
+    } else {
+        Some(payload)
+    })
+}
+
+/// Replaces the clipboard with every representation in `payload`.
+/// Returns Ok(()) on success, Err on failure.
+pub fn write_payload(payload: &ClipPayload) -> Result<(), String> {
+    autoreleasepool(|| unsafe {
+        let pb = general_pasteboard().ok_or("NSPasteboard unavailable")?;
+        let mut items: Vec<Id> = Vec::new();
+        for rep in &payload.representations {
+            let idx = rep.item as usize;
+            while items.len() <= idx {
+                let item: Id = msg_send![class!(NSPasteboardItem), new];
+                let item: Id = msg_send![item, autorelease];
+                items.push(item);
+            }
+            let data: Id = msg_send![class!(NSData), dataWithBytes: rep.data.as_ptr() as *const c_void
+                                                           length: rep.data.len()];
+            let ok: BOOL = msg_send![items[idx], setData: data forType: ns_string(&rep.uti)];
+            if ok == NO {
+                debug!("NSPasteboardItem rejected type {}", rep.uti);
+            }
+        }
+        let array: Id =
+            msg_send![class!(NSArray), arrayWithObjects: items.as_ptr() count: items.len()];
+        let _: isize = msg_send![pb, clearContents];
+        let ok: BOOL = msg_send![pb, writeObjects: array];
+        if ok == NO {
+            return Err("NSPasteboard writeObjects failed".to_string());
+        }
+        Ok(())
+    })
+}
+
+/// Empties the clipboard.
+pub fn clear() -> Result<(), String> {
+    autoreleasepool(|| unsafe {
+        let pb = general_pasteboard().ok_or("NSPasteboard unavailable")?;
+        let _: isize = msg_send![pb, clearContents];
+        Ok(())
+    })
+}
+
+/// NSPasteboard change count; increases on every write by any app. None if unavailable.
+pub fn change_count() -> Option<isize> {
+    autoreleasepool(|| unsafe {
+        let pb = general_pasteboard()?;
+        let count: isize = msg_send![pb, changeCount];
+        Some(count)
+    })
+}
+
+unsafe fn general_pasteboard() -> Option<Id> {
+    let pb: Id = msg_send![class!(NSPasteboard), generalPasteboard];
+    if pb.is_null() {
+        None
+    } else {
+        Some(pb)
+    }
+}
+
+/// Autoreleased NSString from a Rust string.
+unsafe fn ns_string(s: &str) -> Id {
+    let ns: Id = msg_send![class!(NSString), alloc];
+    let ns: Id = msg_send![ns, initWithBytes: s.as_ptr() as *const c_void
+                                      length: s.len()
+                                    encoding: NS_UTF8_STRING_ENCODING];
+    msg_send![ns, autorelease]
+}
+
+unsafe fn string_from_ns(ns: Id) -> Option<String> {
+    if ns.is_null() {
+        return None;
+    }
+    let ptr: *const c_char = msg_send![ns, UTF8String];
+    if ptr.is_null() {
+        return None;
+    }
+    Some(CStr::from_ptr(ptr).to_string_lossy().into_owned())
+}
+
+unsafe fn bytes_from_nsdata(data: Id) -> Vec<u8> {
+    let len: usize = msg_send![data, length];
+    let ptr: *const u8 = msg_send![data, bytes];
+    if ptr.is_null() || len == 0 {
+        return Vec::new();
+    }
+    std::slice::from_raw_parts(ptr, len).to_vec()
+}
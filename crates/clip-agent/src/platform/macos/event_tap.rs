@@ -0,0 +1,122 @@
+//! Global keyboard event capture via CGEventTap.
+//!
+//! Keys are resolved by the `KeySink` through the shared `Keymap`: the paste trigger (default
+//! Cmd+Option+V) is swallowed, and so are the slot and cancel keys while a chooser is open. The
+//! save trigger (default Cmd+C), Cmd+V and everything else pass through normally.
+//! Requires Accessibility permission.
+
+use crate::keys::{Key, Modifiers};
+use crate::platform::{KeySink, KeySource, Stopper};
+use core_foundation::runloop::CFRunLoop;
+use core_graphics::event::{
+    CGEvent, CGEventTap, CGEventTapLocation, CGEventTapOptions, CGEventTapPlacement, CGEventType,
+    CallbackResult,
+};
+use foreign_types::ForeignType;
+use macos_accessibility_client::accessibility::application_is_trusted;
+use tracing::{debug, error, info, warn};
+
+#[link(name = "CoreGraphics", kind = "framework")]
+extern "C" {
+    fn CGEventGetIntegerValueField(event: *const std::ffi::c_void, field: u32) -> i64;
+    fn CGEventGetFlags(event: *const std::ffi::c_void) -> u64;
+}
+
+const K_CG_KEYBOARD_EVENT_KEYCODE: u32 = 9;
+
+pub fn has_accessibility_permission() -> bool {
+    application_is_trusted()
+}
+
+/// `KeySource` backed by a CGEventTap on the main thread's run loop.
+pub struct EventTap;
+
+impl KeySource for EventTap {
+    fn run(&mut self, sink: KeySink) -> Result<(), String> {
+        run_event_tap(sink)
+    }
+
+    fn stopper(&self) -> Stopper {
+        Box::new(|| CFRunLoop::get_main().stop())
+    }
+}
+
+/// Runs the event tap and CFRunLoop, delivering events to `sink`. Blocks until the run loop
+/// stops. The sink reads the keymap per event, so a config reload takes effect on the next
+/// keystroke.
+fn run_event_tap(sink: KeySink) -> Result<(), String> {
+    if !has_accessibility_permission() {
+        error!("Accessibility permission required for keyboard capture. Run `clip doctor` to fix.");
+        return Err(
+            "Accessibility permission not granted. Run `clip doctor` to open System Settings."
+                .to_string(),
+        );
+    }
+
+    let events_of_interest = vec![
+        CGEventType::KeyDown,
+        CGEventType::KeyUp,
+        CGEventType::FlagsChanged,
+    ];
+
+    info!("Keyboard event tap active (paste trigger and open-chooser keys swallowed, rest normal)");
+
+    CGEventTap::with_enabled(
+        CGEventTapLocation::HID,
+        CGEventTapPlacement::HeadInsertEventTap,
+        CGEventTapOptions::Default,
+        events_of_interest,
+        move |_proxy, event_type, event| {
+            if deliver(event_type, event, &sink) {
+                CallbackResult::Drop
+            } else {
+                CallbackResult::Keep
+            }
+        },
+        || CFRunLoop::run_current(),
+    )
+    .map_err(|_| {
+        error!(
+            "Failed to create event tap. Is Accessibility permission granted? Run `clip doctor`."
+        );
+        "Failed to create CGEventTap".to_string()
+    })
+}
+
+/// Decodes one tap event and hands it to `sink`. Returns true if it should be swallowed.
+fn deliver(event_type: CGEventType, event: &CGEvent, sink: &KeySink) -> bool {
+    if matches!(event_type, CGEventType::TapDisabledByTimeout) {
+        warn!("Event tap disabled by timeout; re-enabling");
+        return false;
+    }
+    if matches!(event_type, CGEventType::TapDisabledByUserInput) {
+        warn!("Event tap disabled by user input");
+        return false;
+    }
+
+    let keycode = unsafe {
+        CGEventGetIntegerValueField(event.as_ptr() as *const _, K_CG_KEYBOARD_EVENT_KEYCODE)
+    };
+    let flags = unsafe { CGEventGetFlags(event.as_ptr() as *const _) };
+    // The only place raw flag words are looked at; everything downstream sees `Modifiers`.
+    let key = Key(keycode);
+    let modifiers = Modifiers::from_cg_flags(flags);
+
+    debug!(
+        "event_type={:?} keycode={} flags=0x{:x} modifiers={:?}",
+        event_type, keycode, flags, modifiers
+    );
+
+    match event_type {
+        CGEventType::KeyDown => sink.key_down(key, modifiers),
+        CGEventType::KeyUp => {
+            sink.key_up(key, modifiers);
+            false
+        }
+        CGEventType::FlagsChanged => {
+            sink.flags_changed(modifiers);
+            false
+        }
+        _ => false,
+    }
+}
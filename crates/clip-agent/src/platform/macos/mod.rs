@@ -0,0 +1,22 @@
+//! macOS-specific functionality.
+
+pub mod clipboard;
+pub mod event_tap;
+pub mod paste;
+
+use super::Platform;
+
+/// NSPasteboard, CGEventTap and synthesized Cmd+V. Fails without Accessibility permission.
+pub fn platform() -> Result<Platform, String> {
+    if !event_tap::has_accessibility_permission() {
+        return Err(
+            "Accessibility permission not granted. Run `clip doctor` to open System Settings."
+                .to_string(),
+        );
+    }
+    Ok(Platform {
+        clipboard: Box::new(clipboard::Pasteboard),
+        paste: Box::new(paste::CmdVPaste),
+        keys: Box::new(event_tap::EventTap),
+    })
+}
@@ -0,0 +1,121 @@
+//! Paste from slot: set clipboard to slot content (all representations), synthesize Cmd+V
+//! (realistic 4-event sequence), restore clipboard.
+
+use core_graphics::event::{CGEvent, CGEventTapLocation};
+use core_graphics::event_source::{CGEventSource, CGEventSourceStateID};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+use super::clipboard;
+use crate::payload::ClipPayload;
+use crate::platform::PasteInjector;
+
+const CMD_KEYCODE: u16 = 55;
+const V_KEYCODE: u16 = 9; // ANSI_V
+
+/// `paste_from_slot` on its own thread, as a `PasteInjector`.
+pub struct CmdVPaste;
+
+impl PasteInjector for CmdVPaste {
+    fn paste(&self, content: ClipPayload, restore_delay: Duration) {
+        thread::spawn(move || paste_from_slot(&content, restore_delay));
+    }
+
+    fn copy(&self, content: ClipPayload) -> Result<(), String> {
+        clipboard::write_payload(&content)
+    }
+}
+
+/// Pastes slot content: snapshot clipboard, set to slot content, post CmdDown/VDown/VUp/CmdUp,
+/// restore the snapshot after `restore_delay` (`paste.restore_delay_ms`).
+///
+/// The snapshot holds every representation of every pasteboard item, so images, rich text
+/// and file references the user had copied come back byte-for-byte. The restore is skipped
+/// if anything else wrote the clipboard during the delay (NSPasteboard change count moved), or
+/// if the snapshot could not be read: restoring nothing would clear the user's clipboard.
+/// Timing logged at debug: backup_ms, write_ms, restore_ms.
+pub fn paste_from_slot(slot_content: &ClipPayload, restore_delay: Duration) {
+    let t0 = Instant::now();
+    let backup = match clipboard::snapshot() {
+        Ok(backup) => Some(backup),
+        Err(e) => {
+            warn!(
+                "paste_from_slot: could not back up the clipboard, it will keep the slot: {}",
+                e
+            );
+            None
+        }
+    };
+    let backup_ms = t0.elapsed().as_millis();
+    debug!("paste_from_slot backup_ms={}", backup_ms);
+
+    let t1 = Instant::now();
+    if let Err(e) = clipboard::write_payload(slot_content) {
+        warn!("paste_from_slot: failed to set clipboard: {}", e);
+        return;
+    }
+    let written_change_count = clipboard::change_count();
+    let write_ms = t1.elapsed().as_millis();
+    debug!("paste_from_slot write_ms={}", write_ms);
+
+    thread::yield_now();
+
+    if let Err(()) = post_cmd_v_realistic() {
+        warn!("paste_from_slot: post_cmd_v_realistic failed");
+        if let Some(backup) = &backup {
+            restore_clipboard(backup.as_ref());
+        }
+        return;
+    }
+
+    let Some(backup) = backup else { return };
+    thread::spawn(move || {
+        thread::sleep(restore_delay);
+        let tr0 = Instant::now();
+        if clipboard::change_count() != written_change_count {
+            debug!("paste_from_slot: clipboard changed during paste, not restoring");
+            return;
+        }
+        restore_clipboard(backup.as_ref());
+        let restore_ms = tr0.elapsed().as_millis();
+        debug!("paste_from_slot restore_ms={}", restore_ms);
+    });
+}
+
+/// Puts the snapshot back; an empty snapshot clears the clipboard.
+fn restore_clipboard(backup: Option<&ClipPayload>) {
+    let result = match backup {
+        Some(prev) => clipboard::write_payload(prev),
+        None => clipboard::clear(),
+    };
+    if let Err(e) = result {
+        warn!("paste_from_slot: clipboard restore failed: {}", e);
+    }
+}
+
+/// Post 4 events: CmdDown, VDown, VUp, CmdUp. Keycodes: Cmd=55, V=9. Targets active session.
+fn post_cmd_v_realistic() -> Result<(), ()> {
+    let source = CGEventSource::new(CGEventSourceStateID::CombinedSessionState).map_err(|_| ())?;
+    let cmd_flag = core_graphics::event::CGEventFlags::CGEventFlagCommand;
+    let loc = CGEventTapLocation::HID;
+
+    let cmd_down =
+        CGEvent::new_keyboard_event(source.clone(), CMD_KEYCODE, true).map_err(|_| ())?;
+    cmd_down.set_flags(cmd_flag);
+    cmd_down.post(loc);
+
+    let v_down = CGEvent::new_keyboard_event(source.clone(), V_KEYCODE, true).map_err(|_| ())?;
+    v_down.set_flags(cmd_flag);
+    v_down.post(loc);
+
+    let v_up = CGEvent::new_keyboard_event(source.clone(), V_KEYCODE, false).map_err(|_| ())?;
+    v_up.set_flags(cmd_flag);
+    v_up.post(loc);
+
+    let cmd_up = CGEvent::new_keyboard_event(source, CMD_KEYCODE, false).map_err(|_| ())?;
+    cmd_up.set_flags(core_graphics::event::CGEventFlags::CGEventFlagNull);
+    cmd_up.post(loc);
+
+    Ok(())
+}
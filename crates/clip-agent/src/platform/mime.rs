@@ -0,0 +1,83 @@
+//! Clipboard type names on Linux. X11 targets and Wayland MIME types are mapped to the UTIs
+//! macOS uses for the common types, so slots saved on either OS paste the same way; other MIME
+//! types are kept under their own names.
+
+use crate::payload::UTI_PLAIN_TEXT;
+
+/// Targets (X11 selection targets and Wayland MIME types) and the payload type each one is
+/// stored as.
+const TARGET_UTIS: &[(&str, &str)] = &[
+    ("UTF8_STRING", UTI_PLAIN_TEXT),
+    ("text/plain;charset=utf-8", UTI_PLAIN_TEXT),
+    ("text/html", "public.html"),
+    ("text/rtf", "public.rtf"),
+    ("image/png", "public.png"),
+];
+
+/// Targets that describe the selection rather than hold content, and legacy text encodings
+/// that duplicate UTF8_STRING / text/plain;charset=utf-8.
+const SKIPPED_TARGETS: &[&str] = &[
+    "TARGETS",
+    "MULTIPLE",
+    "TIMESTAMP",
+    "SAVE_TARGETS",
+    "DELETE",
+    "STRING",
+    "TEXT",
+    "COMPOUND_TEXT",
+    "text/plain",
+];
+
+/// Payload type for a target offered by the selection owner, or None if it is not content.
+pub fn uti_for_target(target: &str) -> Option<&str> {
+    if SKIPPED_TARGETS.contains(&target) {
+        return None;
+    }
+    match TARGET_UTIS.iter().find(|(t, _)| *t == target) {
+        Some((_, uti)) => Some(uti),
+        None if target.contains('/') => Some(target),
+        None => None,
+    }
+}
+
+/// Targets a representation is offered under when the agent owns the clipboard.
+pub fn targets_for_uti(uti: &str) -> Vec<&str> {
+    let mut out: Vec<&str> = TARGET_UTIS
+        .iter()
+        .filter(|(_, u)| *u == uti)
+        .map(|(t, _)| *t)
+        .collect();
+    if uti == UTI_PLAIN_TEXT {
+        out.extend(["STRING", "TEXT", "text/plain"]);
+    }
+    if out.is_empty() {
+        out.push(uti);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_map_to_payload_types_and_back() {
+        assert_eq!(uti_for_target("UTF8_STRING"), Some(UTI_PLAIN_TEXT));
+        assert_eq!(uti_for_target("text/html"), Some("public.html"));
+        assert_eq!(
+            uti_for_target("application/x-foo"),
+            Some("application/x-foo")
+        );
+        assert_eq!(uti_for_target("TARGETS"), None);
+        assert_eq!(uti_for_target("STRING"), None);
+        assert_eq!(uti_for_target("_GTK_HINTS"), None);
+
+        let text = targets_for_uti(UTI_PLAIN_TEXT);
+        assert!(text.contains(&"UTF8_STRING") && text.contains(&"STRING"));
+        assert_eq!(targets_for_uti("public.png"), vec!["image/png"]);
+        assert_eq!(
+            targets_for_uti("application/x-foo"),
+            vec!["application/x-foo"]
+        );
+    }
+}
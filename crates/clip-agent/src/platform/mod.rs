@@ -0,0 +1,178 @@
+//! Platform seams the agent depends on: clipboard access, global key capture and paste
+//! injection. Each OS module provides a `Platform`, as does `headless` for machines without a
+//! desktop; `fake` has in-memory ones for tests.
+
+use crate::keys::{Key, Keymap, Modifiers};
+use crate::payload::ClipPayload;
+use crate::state_machine::Event;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+#[cfg(test)]
+pub mod fake;
+pub mod headless;
+#[cfg(target_os = "macos")]
+pub mod macos;
+#[cfg(target_os = "linux")]
+pub mod mime;
+#[cfg(target_os = "linux")]
+pub mod wayland;
+#[cfg(target_os = "linux")]
+pub mod x11;
+
+/// One OS's implementations of every seam.
+pub struct Platform {
+    pub clipboard: Box<dyn ClipboardBackend>,
+    pub paste: Box<dyn PasteInjector>,
+    pub keys: Box<dyn KeySource>,
+}
+
+/// How long a clipboard read waits for a copy that is still being written.
+pub const CLIPBOARD_READ_WAIT: Duration = Duration::from_millis(300);
+
+/// Reads the system clipboard.
+pub trait ClipboardBackend: Send {
+    /// Every representation on the clipboard, or None if it is empty.
+    fn read(&self) -> Option<ClipPayload>;
+
+    /// Plain-text representation only, for history.
+    fn read_text(&self) -> Option<String> {
+        self.read().and_then(|p| p.text().map(str::to_string))
+    }
+}
+
+/// Pastes slot content into the focused app, or just copies it.
+pub trait PasteInjector: Send {
+    /// Puts `content` on the clipboard, synthesizes the paste shortcut and restores the user's
+    /// clipboard after `restore_delay`. Must not block the caller for the delay.
+    fn paste(&self, content: ClipPayload, restore_delay: Duration);
+
+    /// Puts `content` on the clipboard and leaves it there.
+    fn copy(&self, content: ClipPayload) -> Result<(), String>;
+}
+
+/// Stops a running `KeySource` from another thread.
+pub type Stopper = Box<dyn Fn() + Send + Sync>;
+
+/// Captures global key events.
+pub trait KeySource: Send {
+    /// Delivers key events to `sink` until stopped. Blocks; on macOS it must run on the main
+    /// thread.
+    fn run(&mut self, sink: KeySink) -> Result<(), String>;
+
+    /// Makes `run` return.
+    fn stopper(&self) -> Stopper;
+}
+
+/// For platforms without global key capture: delivers nothing and only waits to be stopped.
+/// Triggers arrive over IPC instead (`clip-agent trigger`).
+pub struct NoKeyCapture {
+    stop_tx: Sender<()>,
+    stop_rx: std::sync::mpsc::Receiver<()>,
+}
+
+impl NoKeyCapture {
+    pub fn new() -> Self {
+        let (stop_tx, stop_rx) = std::sync::mpsc::channel();
+        Self { stop_tx, stop_rx }
+    }
+}
+
+impl KeySource for NoKeyCapture {
+    fn run(&mut self, _sink: KeySink) -> Result<(), String> {
+        let _ = self.stop_rx.recv();
+        Ok(())
+    }
+
+    fn stopper(&self) -> Stopper {
+        let tx = self.stop_tx.clone();
+        Box::new(move || {
+            let _ = tx.send(());
+        })
+    }
+}
+
+/// Where a `KeySource` delivers events. Resolves key-downs through the shared keymap and the
+/// state machine's chooser mode to decide what is swallowed from the focused app.
+#[derive(Clone)]
+pub struct KeySink {
+    tx: Sender<Event>,
+    keymap: Arc<RwLock<Keymap>>,
+    mode: Arc<AtomicU8>,
+}
+
+impl KeySink {
+    pub fn new(tx: Sender<Event>, keymap: Arc<RwLock<Keymap>>, mode: Arc<AtomicU8>) -> Self {
+        Self { tx, keymap, mode }
+    }
+
+    /// Current keymap; it changes on config reload.
+    pub fn keymap(&self) -> Keymap {
+        match self.keymap.read() {
+            Ok(k) => *k,
+            Err(poisoned) => *poisoned.into_inner(),
+        }
+    }
+
+    /// The state machine's chooser mode (`MODE_*`).
+    pub fn mode(&self) -> u8 {
+        self.mode.load(Ordering::Acquire)
+    }
+
+    /// Forwards a key-down. Returns true if the source should swallow it.
+    pub fn key_down(&self, key: Key, modifiers: Modifiers) -> bool {
+        let swallow = self
+            .keymap()
+            .action(self.mode(), key, modifiers)
+            .map(|a| a.swallows())
+            .unwrap_or(false);
+        let _ = self.tx.send(Event::KeyDown(key, modifiers));
+        swallow
+    }
+
+    pub fn key_up(&self, key: Key, modifiers: Modifiers) {
+        let _ = self.tx.send(Event::KeyUp(key, modifiers));
+    }
+
+    pub fn flags_changed(&self, modifiers: Modifiers) {
+        let _ = self.tx.send(Event::FlagsChanged(modifiers));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fake::ScriptedKeys;
+    use super::*;
+    use crate::keys::keycode_for_name;
+    use crate::state_machine::{MODE_IDLE, MODE_PASTE_ACTIVE};
+
+    #[test]
+    fn sink_swallows_by_keymap_and_mode_and_forwards_everything() {
+        let key = |name| Key(keycode_for_name(name).unwrap());
+        let script = vec![
+            (key("v"), Modifiers::CMD | Modifiers::OPTION),
+            (key("c"), Modifiers::CMD),
+            (key("1"), Modifiers::empty()),
+        ];
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mode = Arc::new(AtomicU8::new(MODE_IDLE));
+        let sink = KeySink::new(tx, Arc::new(RwLock::new(Keymap::default())), mode.clone());
+
+        let mut idle = ScriptedKeys::new(script.clone());
+        idle.run(sink.clone()).unwrap();
+        assert_eq!(idle.swallowed(), vec![true, false, false]);
+
+        mode.store(MODE_PASTE_ACTIVE, Ordering::Release);
+        let mut active = ScriptedKeys::new(script);
+        active.run(sink).unwrap();
+        assert_eq!(active.swallowed(), vec![true, false, true]);
+
+        let downs = rx
+            .try_iter()
+            .filter(|e| matches!(e, Event::KeyDown(..)))
+            .count();
+        assert_eq!(downs, 6, "swallowed keys still reach the state machine");
+    }
+}
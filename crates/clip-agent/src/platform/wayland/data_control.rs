@@ -0,0 +1,551 @@
+//! Wayland clipboard worker. One thread owns the connection: it monitors the selection offers
+//! the compositor announces, reads and owns the clipboard, and drives the virtual keyboard.
+//! `DataControl` is the handle the rest of the agent talks to it through.
+
+use crate::payload::{ClipPayload, Representation};
+use crate::platform::mime::{targets_for_uti, uti_for_target};
+use crate::platform::{ClipboardBackend, CLIPBOARD_READ_WAIT};
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::os::fd::AsFd;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+use wayland_client::backend::{ObjectId, WaylandError};
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::wl_registry::WlRegistry;
+use wayland_client::protocol::wl_seat::WlSeat;
+use wayland_client::{event_created_child, Connection, Dispatch, EventQueue, Proxy, QueueHandle};
+use wayland_protocols_misc::zwp_virtual_keyboard_v1::client::zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1;
+use wayland_protocols_misc::zwp_virtual_keyboard_v1::client::zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1;
+use wayland_protocols_wlr::data_control::v1::client::zwlr_data_control_device_v1::{
+    self, ZwlrDataControlDeviceV1,
+};
+use wayland_protocols_wlr::data_control::v1::client::zwlr_data_control_manager_v1::ZwlrDataControlManagerV1;
+use wayland_protocols_wlr::data_control::v1::client::zwlr_data_control_offer_v1::{
+    self, ZwlrDataControlOfferV1,
+};
+use wayland_protocols_wlr::data_control::v1::client::zwlr_data_control_source_v1::{
+    self, ZwlrDataControlSourceV1,
+};
+
+/// How long the worker waits for a command before checking the connection again.
+const WORKER_POLL: Duration = Duration::from_millis(5);
+
+/// Keymap uploaded to the virtual keyboard: just Control_L and v, on their evdev keycodes.
+const KEYMAP: &str = r#"xkb_keymap {
+    xkb_keycodes "slotpaste" {
+        minimum = 8;
+        maximum = 255;
+        <LCTL> = 37;
+        <AB04> = 55;
+    };
+    xkb_types "slotpaste" { include "complete" };
+    xkb_compatibility "slotpaste" { include "complete" };
+    xkb_symbols "slotpaste" {
+        key <LCTL> { [ Control_L ] };
+        key <AB04> { [ v, V ] };
+        modifier_map Control { <LCTL> };
+    };
+};
+"#;
+const KEYMAP_FORMAT_XKB_V1: u32 = 1;
+const KEY_LEFTCTRL: u32 = 29;
+const KEY_V: u32 = 47;
+const KEY_RELEASED: u32 = 0;
+const KEY_PRESSED: u32 = 1;
+const MOD_CONTROL: u32 = 1 << 2;
+
+/// Identifies one `own` call, so a later restore only applies if nobody copied since.
+pub type OwnToken = u64;
+
+enum Command {
+    Read {
+        reply: Sender<Option<ClipPayload>>,
+    },
+    Backup {
+        reply: Sender<Result<Option<ClipPayload>, String>>,
+    },
+    Own {
+        payload: ClipPayload,
+        reply: Sender<OwnToken>,
+    },
+    Restore {
+        token: OwnToken,
+        previous: Option<ClipPayload>,
+    },
+    CtrlV {
+        reply: Sender<Result<(), String>>,
+    },
+}
+
+/// Handle to the clipboard worker thread.
+#[derive(Clone)]
+pub struct DataControl {
+    tx: Sender<Command>,
+}
+
+impl DataControl {
+    /// Connects to `$WAYLAND_DISPLAY` and starts the worker; it lives as long as the process.
+    /// Fails if the compositor lacks wlr data-control.
+    pub fn connect() -> Result<Self, String> {
+        let conn = Connection::connect_to_env().map_err(|e| format!("wayland: connect: {}", e))?;
+        let (globals, mut queue) =
+            registry_queue_init::<State>(&conn).map_err(|e| format!("wayland: registry: {}", e))?;
+        let qh = queue.handle();
+        let seat: WlSeat = globals
+            .bind(&qh, 1..=1, ())
+            .map_err(|e| format!("wayland: seat: {}", e))?;
+        let manager: ZwlrDataControlManagerV1 = globals
+            .bind(&qh, 1..=2, ())
+            .map_err(|_| "wayland: compositor does not support wlr data-control".to_string())?;
+        let keyboards: Option<ZwpVirtualKeyboardManagerV1> = globals.bind(&qh, 1..=1, ()).ok();
+        let device = manager.get_data_device(&seat, &qh, ());
+        let mut state = State {
+            manager,
+            device,
+            seat,
+            keyboards,
+            keyboard: None,
+            offers: HashMap::new(),
+            selection: None,
+            primary: None,
+            owned: None,
+            next_token: 0,
+            started: Instant::now(),
+        };
+        // Learn the current selection before the first read.
+        queue
+            .roundtrip(&mut state)
+            .map_err(|e| format!("wayland: roundtrip: {}", e))?;
+
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || run(conn, queue, state, rx));
+        Ok(Self { tx })
+    }
+
+    fn call<T>(&self, command: impl FnOnce(Sender<T>) -> Command) -> Option<T> {
+        let (reply, rx) = mpsc::channel();
+        self.tx.send(command(reply)).ok()?;
+        rx.recv().ok()
+    }
+
+    /// The clipboard alone (no primary fallback) for a paste to restore afterwards. An error
+    /// if any of its types could not be read, so a failed read is not taken for an empty clipboard.
+    pub fn backup(&self) -> Result<Option<ClipPayload>, String> {
+        self.call(|reply| Command::Backup { reply })
+            .unwrap_or_else(|| Err(worker_stopped()))
+    }
+
+    /// Takes the clipboard and serves `payload` from it until another client copies.
+    pub fn own(&self, payload: ClipPayload) -> Result<OwnToken, String> {
+        self.call(|reply| Command::Own { payload, reply })
+            .ok_or_else(worker_stopped)
+    }
+
+    /// If the clipboard still holds what `token` put there, serves `previous` instead (or
+    /// clears it when `previous` is None).
+    pub fn restore(&self, token: OwnToken, previous: Option<ClipPayload>) {
+        let _ = self.tx.send(Command::Restore { token, previous });
+    }
+
+    /// Types Ctrl+V on the virtual keyboard.
+    pub fn press_ctrl_v(&self) -> Result<(), String> {
+        self.call(|reply| Command::CtrlV { reply })
+            .unwrap_or_else(|| Err(worker_stopped()))
+    }
+}
+
+impl ClipboardBackend for DataControl {
+    fn read(&self) -> Option<ClipPayload> {
+        self.call(|reply| Command::Read { reply }).flatten()
+    }
+}
+
+fn worker_stopped() -> String {
+    "wayland: clipboard worker stopped".to_string()
+}
+
+fn run(conn: Connection, mut queue: EventQueue<State>, mut state: State, rx: Receiver<Command>) {
+    let qh = queue.handle();
+    loop {
+        match rx.recv_timeout(WORKER_POLL) {
+            Ok(Command::Read { reply }) => {
+                if let Err(e) = queue.roundtrip(&mut state) {
+                    warn!("wayland: connection lost: {}", e);
+                    return;
+                }
+                let _ = reply.send(state.read(&conn));
+            }
+            Ok(Command::Backup { reply }) => {
+                if let Err(e) = queue.roundtrip(&mut state) {
+                    warn!("wayland: connection lost: {}", e);
+                    return;
+                }
+                let _ = reply.send(state.backup(&conn));
+            }
+            Ok(Command::Own { payload, reply }) => {
+                let _ = reply.send(state.own(payload, &qh));
+            }
+            Ok(Command::Restore { token, previous }) => state.restore(token, previous, &qh),
+            Ok(Command::CtrlV { reply }) => {
+                let _ = reply.send(state.press_ctrl_v(&qh));
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        if let Some(guard) = queue.prepare_read() {
+            match guard.read() {
+                Ok(_) => {}
+                Err(WaylandError::Io(e)) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => {
+                    warn!("wayland: connection lost: {}", e);
+                    return;
+                }
+            }
+        }
+        if let Err(e) = queue.dispatch_pending(&mut state) {
+            warn!("wayland: dispatch failed: {}", e);
+            return;
+        }
+        let _ = conn.flush();
+    }
+}
+
+struct State {
+    manager: ZwlrDataControlManagerV1,
+    device: ZwlrDataControlDeviceV1,
+    seat: WlSeat,
+    keyboards: Option<ZwpVirtualKeyboardManagerV1>,
+    keyboard: Option<ZwpVirtualKeyboardV1>,
+    /// MIME types of every live offer.
+    offers: HashMap<ObjectId, Vec<String>>,
+    selection: Option<ZwlrDataControlOfferV1>,
+    primary: Option<ZwlrDataControlOfferV1>,
+    /// Our source while it is the selection, with the token and payload it serves.
+    owned: Option<(ZwlrDataControlSourceV1, OwnToken, ClipPayload)>,
+    next_token: OwnToken,
+    started: Instant,
+}
+
+impl State {
+    /// Every content type of the clipboard, or of the primary selection when the clipboard
+    /// is empty.
+    fn read(&self, conn: &Connection) -> Option<ClipPayload> {
+        if let Some((_, _, payload)) = &self.owned {
+            return Some(payload.clone());
+        }
+        let offer = self.selection.as_ref().or(self.primary.as_ref())?;
+        self.receive_all(conn, offer, false).ok().flatten()
+    }
+
+    /// The clipboard only; any type that fails to arrive fails the whole read.
+    fn backup(&self, conn: &Connection) -> Result<Option<ClipPayload>, String> {
+        if let Some((_, _, payload)) = &self.owned {
+            return Ok(Some(payload.clone()));
+        }
+        match &self.selection {
+            Some(offer) => self.receive_all(conn, offer, true),
+            None => Ok(None),
+        }
+    }
+
+    /// Every content type `offer` has. A type that fails to arrive is skipped, or with `strict`
+    /// is an error.
+    fn receive_all(
+        &self,
+        conn: &Connection,
+        offer: &ZwlrDataControlOfferV1,
+        strict: bool,
+    ) -> Result<Option<ClipPayload>, String> {
+        let Some(mimes) = self.offers.get(&offer.id()) else {
+            return Ok(None);
+        };
+        let mut payload = ClipPayload::default();
+        for mime in mimes {
+            let Some(uti) = uti_for_target(mime) else {
+                continue;
+            };
+            if payload.get(uti).is_some() {
+                continue;
+            }
+            match receive(conn, offer, mime) {
+                Ok(data) => payload.representations.push(Representation {
+                    item: 0,
+                    uti: uti.to_string(),
+                    data,
+                }),
+                Err(e) if strict => return Err(format!("wayland: reading {} failed: {}", mime, e)),
+                Err(e) => debug!("wayland: reading {} failed: {}", mime, e),
+            }
+        }
+        Ok(if payload.is_empty() {
+            None
+        } else {
+            Some(payload)
+        })
+    }
+
+    fn own(&mut self, payload: ClipPayload, qh: &QueueHandle<State>) -> OwnToken {
+        let source = self.manager.create_data_source(qh, ());
+        for uti in payload.types() {
+            for target in targets_for_uti(&uti) {
+                source.offer(target.to_string());
+            }
+        }
+        self.device.set_selection(Some(&source));
+        if let Some((old, _, _)) = self.owned.take() {
+            old.destroy();
+        }
+        self.next_token += 1;
+        self.owned = Some((source, self.next_token, payload));
+        self.next_token
+    }
+
+    fn restore(&mut self, token: OwnToken, previous: Option<ClipPayload>, qh: &QueueHandle<State>) {
+        if !matches!(&self.owned, Some((_, t, _)) if *t == token) {
+            debug!("wayland: clipboard changed during paste, not restoring");
+            return;
+        }
+        match previous {
+            Some(prev) => {
+                self.own(prev, qh);
+            }
+            None => {
+                self.device.set_selection(None);
+                if let Some((source, _, _)) = self.owned.take() {
+                    source.destroy();
+                }
+            }
+        }
+    }
+
+    fn press_ctrl_v(&mut self, qh: &QueueHandle<State>) -> Result<(), String> {
+        let keyboard = match &self.keyboard {
+            Some(k) => k.clone(),
+            None => {
+                let manager = self
+                    .keyboards
+                    .as_ref()
+                    .ok_or("wayland: compositor does not support virtual keyboards")?;
+                let k = manager.create_virtual_keyboard(&self.seat, qh, ());
+                upload_keymap(&k)?;
+                self.keyboard = Some(k.clone());
+                k
+            }
+        };
+        let time = self.started.elapsed().as_millis() as u32;
+        keyboard.key(time, KEY_LEFTCTRL, KEY_PRESSED);
+        keyboard.modifiers(MOD_CONTROL, 0, 0, 0);
+        keyboard.key(time, KEY_V, KEY_PRESSED);
+        keyboard.key(time, KEY_V, KEY_RELEASED);
+        keyboard.key(time, KEY_LEFTCTRL, KEY_RELEASED);
+        keyboard.modifiers(0, 0, 0, 0);
+        Ok(())
+    }
+
+    /// Forgets an offer that is no longer the selection or the primary selection.
+    fn drop_offer(&mut self, offer: ZwlrDataControlOfferV1) {
+        let in_use = |o: &Option<ZwlrDataControlOfferV1>| o.as_ref() == Some(&offer);
+        if !in_use(&self.selection) && !in_use(&self.primary) {
+            self.offers.remove(&offer.id());
+            offer.destroy();
+        }
+    }
+}
+
+/// Reads one MIME type of `offer` through a pipe, giving up after `CLIPBOARD_READ_WAIT`.
+fn receive(
+    conn: &Connection,
+    offer: &ZwlrDataControlOfferV1,
+    mime: &str,
+) -> Result<Vec<u8>, String> {
+    let (mut reader, writer) = std::io::pipe().map_err(|e| format!("pipe: {}", e))?;
+    offer.receive(mime.to_string(), writer.as_fd());
+    conn.flush().map_err(|e| format!("flush: {}", e))?;
+    drop(writer);
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut data = Vec::new();
+        let _ = tx.send(reader.read_to_end(&mut data).map(|_| data));
+    });
+    match rx.recv_timeout(CLIPBOARD_READ_WAIT) {
+        Ok(Ok(data)) => Ok(data),
+        Ok(Err(e)) => Err(format!("read: {}", e)),
+        Err(_) => Err("clipboard owner did not respond".to_string()),
+    }
+}
+
+fn upload_keymap(keyboard: &ZwpVirtualKeyboardV1) -> Result<(), String> {
+    let mut file = tempfile::tempfile().map_err(|e| format!("wayland: keymap file: {}", e))?;
+    file.write_all(KEYMAP.as_bytes())
+        .and_then(|()| file.write_all(&[0]))
+        .map_err(|e| format!("wayland: keymap file: {}", e))?;
+    keyboard.keymap(KEYMAP_FORMAT_XKB_V1, file.as_fd(), KEYMAP.len() as u32 + 1);
+    Ok(())
+}
+
+impl Dispatch<ZwlrDataControlDeviceV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        _: &ZwlrDataControlDeviceV1,
+        event: zwlr_data_control_device_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_data_control_device_v1::Event::DataOffer { id } => {
+                state.offers.insert(id.id(), Vec::new());
+            }
+            zwlr_data_control_device_v1::Event::Selection { id } => {
+                if let Some(old) = std::mem::replace(&mut state.selection, id) {
+                    state.drop_offer(old);
+                }
+            }
+            zwlr_data_control_device_v1::Event::PrimarySelection { id } => {
+                if let Some(old) = std::mem::replace(&mut state.primary, id) {
+                    state.drop_offer(old);
+                }
+            }
+            zwlr_data_control_device_v1::Event::Finished => {
+                warn!("wayland: data-control device finished; clipboard unavailable");
+            }
+            _ => {}
+        }
+    }
+
+    event_created_child!(State, ZwlrDataControlDeviceV1, [
+        zwlr_data_control_device_v1::EVT_DATA_OFFER_OPCODE => (ZwlrDataControlOfferV1, ()),
+    ]);
+}
+
+impl Dispatch<ZwlrDataControlOfferV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        offer: &ZwlrDataControlOfferV1,
+        event: zwlr_data_control_offer_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let zwlr_data_control_offer_v1::Event::Offer { mime_type } = event {
+            state.offers.entry(offer.id()).or_default().push(mime_type);
+        }
+    }
+}
+
+impl Dispatch<ZwlrDataControlSourceV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        source: &ZwlrDataControlSourceV1,
+        event: zwlr_data_control_source_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let ours = matches!(&state.owned, Some((s, _, _)) if s == source);
+        match event {
+            zwlr_data_control_source_v1::Event::Send { mime_type, fd } => {
+                let data = state
+                    .owned
+                    .as_ref()
+                    .filter(|_| ours)
+                    .and_then(|(_, _, payload)| {
+                        payload
+                            .representations
+                            .iter()
+                            .find(|r| targets_for_uti(&r.uti).contains(&mime_type.as_str()))
+                            .map(|r| r.data.clone())
+                    });
+                // Write off-thread: the reader may be slow, and a large payload fills the pipe.
+                if let Some(data) = data {
+                    thread::spawn(move || {
+                        let _ = std::fs::File::from(fd).write_all(&data);
+                    });
+                }
+            }
+            zwlr_data_control_source_v1::Event::Cancelled => {
+                if ours {
+                    state.owned = None;
+                }
+                source.destroy();
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for State {
+    fn event(
+        _: &mut Self,
+        _: &WlRegistry,
+        _: <WlRegistry as Proxy>::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+/// Globals whose events the worker ignores.
+macro_rules! ignore_events {
+    ($($proxy:ty),*) => {$(
+        impl Dispatch<$proxy, ()> for State {
+            fn event(
+                _: &mut Self,
+                _: &$proxy,
+                _: <$proxy as Proxy>::Event,
+                _: &(),
+                _: &Connection,
+                _: &QueueHandle<Self>,
+            ) {
+            }
+        }
+    )*};
+}
+
+ignore_events!(
+    WlSeat,
+    ZwlrDataControlManagerV1,
+    ZwpVirtualKeyboardManagerV1,
+    ZwpVirtualKeyboardV1
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_until(control: &DataControl, want: Option<&ClipPayload>) -> Option<ClipPayload> {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        loop {
+            let got = control.read();
+            if got.as_ref() == want || Instant::now() >= deadline {
+                return got;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a wlroots compositor (WLR_BACKENDS=headless sway)"]
+    fn wayland_clipboard_round_trip_between_clients() {
+        let owner = DataControl::connect().unwrap();
+        let reader = DataControl::connect().unwrap();
+
+        let mut payload = ClipPayload::from_text("hello");
+        payload.representations.push(Representation {
+            item: 0,
+            uti: "public.html".to_string(),
+            data: b"<b>hello</b>".to_vec(),
+        });
+        let token = owner.own(payload.clone()).unwrap();
+        assert_eq!(read_until(&reader, Some(&payload)), Some(payload));
+
+        owner.restore(token, None);
+        assert_eq!(read_until(&reader, None), None);
+    }
+}
@@ -0,0 +1,23 @@
+//! Wayland backend for wlroots-class compositors (sway, Hyprland, …): the clipboard through
+//! `zwlr_data_control_manager_v1` and paste through `zwp_virtual_keyboard_v1`.
+//!
+//! Wayland gives clients no global key capture, so the triggers come from compositor key
+//! bindings that run `clip-agent trigger save|paste`; slots are picked in the chooser UI.
+//!
+//! Tests that need a compositor are ignored by default; run them under a headless one with
+//! `WLR_BACKENDS=headless sway -c /dev/null & cargo test -p clip-agent -- --ignored wayland`.
+
+pub mod data_control;
+pub mod paste;
+
+use super::{NoKeyCapture, Platform};
+
+/// Clipboard and paste on the compositor named by `$WAYLAND_DISPLAY`.
+pub fn platform() -> Result<Platform, String> {
+    let control = data_control::DataControl::connect()?;
+    Ok(Platform {
+        clipboard: Box::new(control.clone()),
+        paste: Box::new(paste::VirtualKeyboardPaste::new(control)),
+        keys: Box::new(NoKeyCapture::new()),
+    })
+}
@@ -0,0 +1,76 @@
+//! Paste from slot on Wayland: snapshot the clipboard, own it with the slot content, type
+//! Ctrl+V on a virtual keyboard, then serve the snapshot again after the restore delay.
+
+use super::data_control::DataControl;
+use crate::payload::ClipPayload;
+use crate::platform::PasteInjector;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// Virtual-keyboard paste as a `PasteInjector`. Pastes run on their own threads, one at a time.
+pub struct VirtualKeyboardPaste {
+    control: DataControl,
+    busy: Arc<Mutex<()>>,
+}
+
+impl VirtualKeyboardPaste {
+    pub fn new(control: DataControl) -> Self {
+        Self {
+            control,
+            busy: Arc::new(Mutex::new(())),
+        }
+    }
+}
+
+impl PasteInjector for VirtualKeyboardPaste {
+    fn paste(&self, content: ClipPayload, restore_delay: Duration) {
+        let (control, busy) = (self.control.clone(), self.busy.clone());
+        thread::spawn(move || {
+            let _guard = busy.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            paste_from_slot(&control, content, restore_delay);
+        });
+    }
+
+    fn copy(&self, content: ClipPayload) -> Result<(), String> {
+        self.control.own(content).map(|_| ())
+    }
+}
+
+/// Same sequence as the macOS paste. Without a virtual keyboard the slot content is left on
+/// the clipboard for the user to paste by hand. If the clipboard could not be backed up, it
+/// keeps the slot content rather than being cleared. Timing logged at debug: backup_ms, write_ms.
+fn paste_from_slot(control: &DataControl, content: ClipPayload, restore_delay: Duration) {
+    let t0 = Instant::now();
+    let backup = match control.backup() {
+        Ok(backup) => Some(backup),
+        Err(e) => {
+            warn!(
+                "paste_from_slot: could not back up the clipboard, it will keep the slot: {}",
+                e
+            );
+            None
+        }
+    };
+    debug!("paste_from_slot backup_ms={}", t0.elapsed().as_millis());
+
+    let t1 = Instant::now();
+    let token = match control.own(content) {
+        Ok(t) => t,
+        Err(e) => {
+            warn!("paste_from_slot: failed to set clipboard: {}", e);
+            return;
+        }
+    };
+    debug!("paste_from_slot write_ms={}", t1.elapsed().as_millis());
+
+    if let Err(e) = control.press_ctrl_v() {
+        warn!("paste_from_slot: {}; slot content left on the clipboard", e);
+        return;
+    }
+    thread::sleep(restore_delay);
+    if let Some(backup) = backup {
+        control.restore(token, backup);
+    }
+}
@@ -0,0 +1,611 @@
+//! X11 selections. `X11Clipboard` reads CLIPBOARD (falling back to PRIMARY) by converting
+//! every offered target; `SelectionOwner` owns a selection on its own thread and serves
+//! conversion requests until another client takes it. Both sides speak INCR for content too big
+//! for one request.
+//!
+//! Targets are stored under the payload types from `platform::mime`.
+
+use super::{err, XDisplay};
+use crate::payload::{ClipPayload, Representation};
+use crate::platform::mime::{targets_for_uti, uti_for_target};
+use crate::platform::{ClipboardBackend, CLIPBOARD_READ_WAIT};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+use x11rb::connection::{Connection, RequestConnection as _};
+use x11rb::protocol::xproto::{
+    Atom, AtomEnum, ChangeWindowAttributesAux, ConnectionExt as _, EventMask, PropMode, Property,
+    SelectionNotifyEvent, SelectionRequestEvent, Window, SELECTION_NOTIFY_EVENT,
+};
+use x11rb::protocol::Event as XEvent;
+use x11rb::wrapper::ConnectionExt as _;
+use x11rb::CURRENT_TIME;
+use x11rb::NONE;
+
+/// Largest property the owner writes in one request; bigger content goes out over INCR in
+/// chunks of this size, so one slot does not hold up the server either.
+const MAX_CHUNK: usize = 1 << 20;
+/// Bytes of a ChangeProperty request before its data.
+const CHANGE_PROPERTY_HEADER: usize = 24;
+/// An INCR transfer whose requestor stops deleting the property for this long is dropped.
+const INCR_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Reads CLIPBOARD, or PRIMARY when nothing owns CLIPBOARD.
+pub struct X11Clipboard {
+    display: XDisplay,
+    window: Window,
+}
+
+impl X11Clipboard {
+    pub fn connect() -> Result<Self, String> {
+        let display = XDisplay::connect()?;
+        let window = display.create_window()?;
+        Ok(Self { display, window })
+    }
+
+    /// Every content target of `selection`, or None if it has no owner or no content.
+    pub fn read_selection(&self, selection: Atom) -> Result<Option<ClipPayload>, String> {
+        let d = &self.display;
+        let owner = d
+            .conn
+            .get_selection_owner(selection)
+            .map_err(err("get selection owner"))?
+            .reply()
+            .map_err(err("get selection owner"))?
+            .owner;
+        if owner == NONE {
+            return Ok(None);
+        }
+        let targets = match self.convert(selection, d.atoms.TARGETS)? {
+            Some(bytes) => bytes
+                .chunks_exact(4)
+                .map(|c| u32::from_ne_bytes([c[0], c[1], c[2], c[3]]))
+                .collect::<Vec<_>>(),
+            None => return Ok(None),
+        };
+        let mut payload = ClipPayload::default();
+        for target in targets {
+            let Some(name) = d.atom_name(target) else {
+                continue;
+            };
+            let Some(uti) = uti_for_target(&name) else {
+                continue;
+            };
+            if payload.get(uti).is_some() {
+                continue;
+            }
+            match self.convert(selection, target)? {
+                Some(data) => payload.representations.push(Representation {
+                    item: 0,
+                    uti: uti.to_string(),
+                    data,
+                }),
+                None => debug!("x11 clipboard: owner refused target {}", name),
+            }
+        }
+        Ok(if payload.representations.is_empty() {
+            None
+        } else {
+            Some(payload)
+        })
+    }
+
+    /// CLIPBOARD only, with read errors kept apart from an empty clipboard.
+    pub fn read_clipboard(&self) -> Result<Option<ClipPayload>, String> {
+        self.read_selection(self.display.atoms.CLIPBOARD)
+    }
+
+    /// Asks the owner to convert `selection` to `target` and collects the result, following
+    /// INCR transfers. None if the owner refused; an error if it stays silent for
+    /// `CLIPBOARD_READ_WAIT`.
+    fn convert(&self, selection: Atom, target: Atom) -> Result<Option<Vec<u8>>, String> {
+        let d = &self.display;
+        let property = d.atoms.SLOTPASTE_SELECTION;
+        d.conn
+            .convert_selection(self.window, selection, target, property, CURRENT_TIME)
+            .map_err(err("convert selection"))?;
+        d.conn.flush().map_err(err("flush"))?;
+
+        let deadline = Instant::now() + CLIPBOARD_READ_WAIT;
+        let notify = self.wait_for(deadline, |e| match e {
+            XEvent::SelectionNotify(n)
+                if n.requestor == self.window && n.selection == selection =>
+            {
+                Some(n.property)
+            }
+            _ => None,
+        })?;
+        if notify == NONE {
+            return Ok(None);
+        }
+
+        let reply = self.take_property(property)?;
+        if reply.type_ != d.atoms.INCR {
+            return Ok(Some(reply.value));
+        }
+        // INCR: the owner writes chunks one at a time, each after we delete the previous one;
+        // a zero-length chunk ends the transfer.
+        let mut data = Vec::new();
+        loop {
+            let deadline = Instant::now() + CLIPBOARD_READ_WAIT;
+            self.wait_for(deadline, |e| match e {
+                XEvent::PropertyNotify(p)
+                    if p.window == self.window
+                        && p.atom == property
+                        && p.state == Property::NEW_VALUE =>
+                {
+                    Some(())
+                }
+                _ => None,
+            })?;
+            let chunk = self.take_property(property)?.value;
+            if chunk.is_empty() {
+                return Ok(Some(data));
+            }
+            data.extend_from_slice(&chunk);
+        }
+    }
+
+    fn take_property(
+        &self,
+        property: Atom,
+    ) -> Result<x11rb::protocol::xproto::GetPropertyReply, String> {
+        let d = &self.display;
+        let reply = d
+            .conn
+            .get_property(true, self.window, property, AtomEnum::ANY, 0, u32::MAX / 4)
+            .map_err(err("get property"))?
+            .reply()
+            .map_err(err("get property"))?;
+        d.conn.flush().map_err(err("flush"))?;
+        Ok(reply)
+    }
+
+    /// Polls events until `pick` returns Some or `deadline` passes.
+    fn wait_for<T>(
+        &self,
+        deadline: Instant,
+        mut pick: impl FnMut(&XEvent) -> Option<T>,
+    ) -> Result<T, String> {
+        loop {
+            while let Some(event) = self
+                .display
+                .conn
+                .poll_for_event()
+                .map_err(err("read event"))?
+            {
+                if let Some(v) = pick(&event) {
+                    return Ok(v);
+                }
+            }
+            if Instant::now() >= deadline {
+                return Err("x11: selection owner did not respond".to_string());
+            }
+            thread::sleep(Duration::from_millis(2));
+        }
+    }
+}
+
+impl ClipboardBackend for X11Clipboard {
+    fn read(&self) -> Option<ClipPayload> {
+        let atoms = self.display.atoms;
+        for selection in [atoms.CLIPBOARD, atoms.PRIMARY] {
+            match self.read_selection(selection) {
+                Ok(Some(payload)) if !payload.is_empty() => return Some(payload),
+                Ok(_) => {}
+                Err(e) => warn!("x11 clipboard: read failed: {}", e),
+            }
+        }
+        None
+    }
+}
+
+/// Which selection `SelectionOwner` should own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selection {
+    Clipboard,
+    /// Only the tests own PRIMARY, to exercise the reader's fallback.
+    #[cfg(test)]
+    Primary,
+}
+
+/// Identifies one `own` call, so a later restore only applies if nobody copied since.
+pub type OwnToken = u64;
+
+enum Command {
+    Own {
+        selection: Selection,
+        payload: ClipPayload,
+        reply: Sender<Result<OwnToken, String>>,
+    },
+    Restore {
+        token: OwnToken,
+        previous: Option<ClipPayload>,
+    },
+}
+
+/// Handle to the selection-owner thread.
+#[derive(Clone)]
+pub struct SelectionOwner {
+    tx: Sender<Command>,
+}
+
+impl SelectionOwner {
+    /// Connects and starts the owner thread; it lives as long as the process.
+    pub fn spawn() -> Result<Self, String> {
+        let display = XDisplay::connect()?;
+        let window = display.create_window()?;
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            OwnerThread {
+                display,
+                window,
+                owned: Vec::new(),
+                next_token: 0,
+                transfers: Vec::new(),
+            }
+            .run(rx)
+        });
+        Ok(Self { tx })
+    }
+
+    /// Takes `selection` and serves `payload` from it until another client copies.
+    pub fn own(&self, selection: Selection, payload: ClipPayload) -> Result<OwnToken, String> {
+        let (reply, rx) = mpsc::channel();
+        self.tx
+            .send(Command::Own {
+                selection,
+                payload,
+                reply,
+            })
+            .map_err(|_| "x11: selection owner stopped".to_string())?;
+        rx.recv()
+            .map_err(|_| "x11: selection owner stopped".to_string())?
+    }
+
+    /// If CLIPBOARD still holds what `token` put there, serves `previous` instead (or gives up
+    /// the selection when `previous` is None).
+    pub fn restore(&self, token: OwnToken, previous: Option<ClipPayload>) {
+        let _ = self.tx.send(Command::Restore { token, previous });
+    }
+}
+
+struct OwnerThread {
+    display: XDisplay,
+    window: Window,
+    /// (selection atom, token, payload) for each selection we currently own.
+    owned: Vec<(Atom, OwnToken, ClipPayload)>,
+    next_token: OwnToken,
+    transfers: Vec<Incr>,
+}
+
+/// An INCR transfer in progress: the requestor deletes `property` to ask for the next chunk of
+/// `data`, and an empty chunk ends it.
+struct Incr {
+    requestor: Window,
+    property: Atom,
+    target: Atom,
+    data: Vec<u8>,
+    sent: usize,
+    last_activity: Instant,
+}
+
+impl OwnerThread {
+    fn run(mut self, rx: Receiver<Command>) {
+        loop {
+            match rx.recv_timeout(Duration::from_millis(5)) {
+                Ok(Command::Own {
+                    selection,
+                    payload,
+                    reply,
+                }) => {
+                    let _ = reply.send(self.own(self.atom(selection), payload));
+                }
+                Ok(Command::Restore { token, previous }) => self.restore(token, previous),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+            self.transfers.retain(|t| {
+                let alive = t.last_activity.elapsed() < INCR_TIMEOUT;
+                if !alive {
+                    debug!(
+                        "x11 clipboard: requestor abandoned INCR transfer after {} bytes",
+                        t.sent
+                    );
+                }
+                alive
+            });
+            loop {
+                match self.display.conn.poll_for_event() {
+                    Ok(Some(event)) => self.handle(event),
+                    Ok(None) => break,
+                    Err(e) => {
+                        warn!("x11 clipboard: owner connection lost: {}", e);
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    fn atom(&self, selection: Selection) -> Atom {
+        match selection {
+            Selection::Clipboard => self.display.atoms.CLIPBOARD,
+            #[cfg(test)]
+            Selection::Primary => self.display.atoms.PRIMARY,
+        }
+    }
+
+    fn own(&mut self, selection: Atom, payload: ClipPayload) -> Result<OwnToken, String> {
+        let conn = &self.display.conn;
+        conn.set_selection_owner(self.window, selection, CURRENT_TIME)
+            .map_err(err("set selection owner"))?;
+        let owner = conn
+            .get_selection_owner(selection)
+            .map_err(err("get selection owner"))?
+            .reply()
+            .map_err(err("get selection owner"))?
+            .owner;
+        if owner != self.window {
+            return Err("x11: could not take selection ownership".to_string());
+        }
+        self.next_token += 1;
+        self.owned.retain(|(s, _, _)| *s != selection);
+        self.owned.push((selection, self.next_token, payload));
+        Ok(self.next_token)
+    }
+
+    fn restore(&mut self, token: OwnToken, previous: Option<ClipPayload>) {
+        let clipboard = self.display.atoms.CLIPBOARD;
+        if !self
+            .owned
+            .iter()
+            .any(|(s, t, _)| *s == clipboard && *t == token)
+        {
+            debug!("x11 clipboard: clipboard changed during paste, not restoring");
+            return;
+        }
+        let result = match previous {
+            Some(prev) => self.own(clipboard, prev).map(|_| ()),
+            None => {
+                self.owned.retain(|(s, _, _)| *s != clipboard);
+                self.display
+                    .conn
+                    .set_selection_owner(NONE, clipboard, CURRENT_TIME)
+                    .map(|_| ())
+                    .map_err(err("release selection"))
+            }
+        };
+        if let Err(e) = result.and_then(|()| self.display.conn.flush().map_err(err("flush"))) {
+            warn!("x11 clipboard: restore failed: {}", e);
+        }
+    }
+
+    fn handle(&mut self, event: XEvent) {
+        match event {
+            XEvent::SelectionClear(e) => self.owned.retain(|(s, _, _)| *s != e.selection),
+            XEvent::PropertyNotify(p) if p.state == Property::DELETE => {
+                if let Err(e) = self.send_chunk(p.window, p.atom) {
+                    warn!("x11 clipboard: INCR transfer failed: {}", e);
+                    self.transfers
+                        .retain(|t| !(t.requestor == p.window && t.property == p.atom));
+                }
+            }
+            XEvent::SelectionRequest(req) => {
+                let property = self.serve(&req).unwrap_or_else(|e| {
+                    debug!("x11 clipboard: refusing request: {}", e);
+                    NONE
+                });
+                let notify = SelectionNotifyEvent {
+                    response_type: SELECTION_NOTIFY_EVENT,
+                    sequence: 0,
+                    time: req.time,
+                    requestor: req.requestor,
+                    selection: req.selection,
+                    target: req.target,
+                    property,
+                };
+                let conn = &self.display.conn;
+                let sent = conn.send_event(false, req.requestor, EventMask::NO_EVENT, notify);
+                if let Err(e) = sent.map(|_| ()).and_then(|()| conn.flush()) {
+                    warn!("x11 clipboard: failed to answer request: {}", e);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Writes the requested conversion to the requestor's property, or starts an INCR transfer
+    /// when it is bigger than one chunk. Returns the property, or NONE if the target is not
+    /// offered.
+    fn serve(&mut self, req: &SelectionRequestEvent) -> Result<Atom, String> {
+        let Some((_, _, payload)) = self.owned.iter().find(|(s, _, _)| *s == req.selection) else {
+            return Ok(NONE);
+        };
+        let d = &self.display;
+        // Obsolete clients pass property None and expect the target as the property.
+        let property = if req.property == NONE {
+            req.target
+        } else {
+            req.property
+        };
+
+        if req.target == d.atoms.TARGETS {
+            let mut atoms = vec![d.atoms.TARGETS];
+            for uti in payload.types() {
+                for target in targets_for_uti(&uti) {
+                    atoms.push(d.atom(target)?);
+                }
+            }
+            d.conn
+                .change_property32(
+                    PropMode::REPLACE,
+                    req.requestor,
+                    property,
+                    d.atoms.ATOM,
+                    &atoms,
+                )
+                .map_err(err("change property"))?;
+            return Ok(property);
+        }
+
+        let Some(target) = d.atom_name(req.target) else {
+            return Ok(NONE);
+        };
+        let rep = payload
+            .representations
+            .iter()
+            .find(|r| targets_for_uti(&r.uti).contains(&target.as_str()));
+        let Some(rep) = rep else { return Ok(NONE) };
+        if rep.data.len() <= self.chunk_size() {
+            d.conn
+                .change_property8(
+                    PropMode::REPLACE,
+                    req.requestor,
+                    property,
+                    req.target,
+                    &rep.data,
+                )
+                .map_err(err("change property"))?;
+            return Ok(property);
+        }
+
+        // INCR: announce the size, then send a chunk each time the requestor deletes the property.
+        let data = rep.data.clone();
+        let events = ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE);
+        d.conn
+            .change_window_attributes(req.requestor, &events)
+            .map_err(err("watch requestor"))?;
+        let size = u32::try_from(data.len()).unwrap_or(u32::MAX);
+        d.conn
+            .change_property32(
+                PropMode::REPLACE,
+                req.requestor,
+                property,
+                d.atoms.INCR,
+                &[size],
+            )
+            .map_err(err("change property"))?;
+        debug!("x11 clipboard: serving {} bytes over INCR", data.len());
+        self.transfers
+            .retain(|t| !(t.requestor == req.requestor && t.property == property));
+        self.transfers.push(Incr {
+            requestor: req.requestor,
+            property,
+            target: req.target,
+            data,
+            sent: 0,
+            last_activity: Instant::now(),
+        });
+        Ok(property)
+    }
+
+    /// The most data one ChangeProperty may carry here.
+    fn chunk_size(&self) -> usize {
+        let max_request = self.display.conn.maximum_request_bytes();
+        max_request
+            .saturating_sub(CHANGE_PROPERTY_HEADER)
+            .min(MAX_CHUNK)
+    }
+
+    /// Writes the next chunk of the INCR transfer to `requestor`'s `property`, if one is waiting
+    /// on it. The empty chunk after the last one ends the transfer.
+    fn send_chunk(&mut self, requestor: Window, property: Atom) -> Result<(), String> {
+        let chunk_size = self.chunk_size();
+        let Some(i) = self
+            .transfers
+            .iter()
+            .position(|t| t.requestor == requestor && t.property == property)
+        else {
+            return Ok(());
+        };
+        let transfer = &mut self.transfers[i];
+        let end = (transfer.sent + chunk_size).min(transfer.data.len());
+        let chunk = &transfer.data[transfer.sent..end];
+        let conn = &self.display.conn;
+        conn.change_property8(
+            PropMode::REPLACE,
+            requestor,
+            property,
+            transfer.target,
+            chunk,
+        )
+        .map_err(err("change property"))?;
+        conn.flush().map_err(err("flush"))?;
+        if chunk.is_empty() {
+            self.transfers.remove(i);
+            // Unless another transfer still goes to this window, stop watching it.
+            if !self.transfers.iter().any(|t| t.requestor == requestor) && requestor != self.window
+            {
+                let events = ChangeWindowAttributesAux::new().event_mask(EventMask::NO_EVENT);
+                let _ = conn
+                    .change_window_attributes(requestor, &events)
+                    .map(|_| ())
+                    .and_then(|()| conn.flush());
+            }
+        } else {
+            transfer.sent = end;
+            transfer.last_activity = Instant::now();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "needs an X server (run under xvfb-run)"]
+    fn x11_selection_round_trip_and_primary_fallback() {
+        let owner = SelectionOwner::spawn().unwrap();
+        let reader = X11Clipboard::connect().unwrap();
+        let primary = reader.display.atoms.PRIMARY;
+
+        // Take CLIPBOARD and give it up, so nothing owns it.
+        let token = owner
+            .own(Selection::Clipboard, ClipPayload::from_text("x"))
+            .unwrap();
+        owner.restore(token, None);
+        owner
+            .own(Selection::Primary, ClipPayload::from_text("from primary"))
+            .unwrap();
+        assert_eq!(
+            reader
+                .read_selection(reader.display.atoms.CLIPBOARD)
+                .unwrap(),
+            None
+        );
+        assert_eq!(reader.read_text().as_deref(), Some("from primary"));
+        assert_eq!(
+            reader.read_selection(primary).unwrap().unwrap().text(),
+            Some("from primary")
+        );
+
+        let mut payload = ClipPayload::from_text("hello");
+        payload.representations.push(Representation {
+            item: 0,
+            uti: "public.html".to_string(),
+            data: b"<b>hello</b>".to_vec(),
+        });
+        owner.own(Selection::Clipboard, payload.clone()).unwrap();
+        assert_eq!(reader.read(), Some(payload));
+    }
+
+    #[test]
+    #[ignore = "needs an X server (run under xvfb-run)"]
+    fn x11_serves_multi_megabyte_content_over_incr() {
+        let owner = SelectionOwner::spawn().unwrap();
+        let reader = X11Clipboard::connect().unwrap();
+        let text: String = (0..5 * 1024 * 1024)
+            .map(|i| char::from(b'a' + (i % 26) as u8))
+            .collect();
+        owner
+            .own(Selection::Clipboard, ClipPayload::from_text(&text))
+            .unwrap();
+        let read = reader.read_clipboard().unwrap().unwrap();
+        assert_eq!(read.text().map(str::len), Some(text.len()));
+        assert!(read.text() == Some(text.as_str()));
+    }
+}
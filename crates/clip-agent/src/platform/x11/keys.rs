@@ -0,0 +1,457 @@
+//! X11 key capture. XRecord reports every key event without taking it from the focused app;
+//! XGrabKey claims the bindings that must be swallowed (the paste trigger, plus slot and cancel
+//! keys while a chooser is open), re-grabbed whenever the mode or keymap changes.
+//!
+//! Keys are translated to macOS keycodes via their keysym and hotkey name. Ctrl plays the role
+//! of Cmd (so the default `cmd+c` is Ctrl+C), Alt is Option and Super is Ctrl.
+
+use super::{err, KeyboardMap, XDisplay};
+use crate::keys::{keycode_for_name, name_for_keycode, Key, KeyBinding, Modifiers};
+use crate::platform::{KeySink, KeySource, Stopper};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, warn};
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::protocol::record::{self, ConnectionExt as _};
+use x11rb::protocol::xproto::{
+    self, ConnectionExt as _, GrabMode, KeyPressEvent, Keycode, ModMask,
+};
+use x11rb::x11_utils::TryParse;
+
+/// How often the grab thread checks the chooser mode.
+const GRAB_POLL: Duration = Duration::from_millis(10);
+
+/// Keysyms of named keys (X11 keysymdef.h).
+const NAMED_KEYSYMS: &[(&str, u32)] = &[
+    ("escape", 0xff1b),
+    ("return", 0xff0d),
+    ("tab", 0xff09),
+    ("space", 0x20),
+    ("delete", 0xff08),
+    ("forward_delete", 0xffff),
+    ("home", 0xff50),
+    ("left", 0xff51),
+    ("up", 0xff52),
+    ("right", 0xff53),
+    ("down", 0xff54),
+    ("page_up", 0xff55),
+    ("page_down", 0xff56),
+    ("end", 0xff57),
+    ("minus", 0x2d),
+    ("equal", 0x3d),
+    ("left_bracket", 0x5b),
+    ("right_bracket", 0x5d),
+    ("backslash", 0x5c),
+    ("semicolon", 0x3b),
+    ("quote", 0x27),
+    ("comma", 0x2c),
+    ("period", 0x2e),
+    ("slash", 0x2f),
+    ("grave", 0x60),
+];
+
+/// XK_F1; F2..F20 follow it.
+const KEYSYM_F1: u32 = 0xffbe;
+
+/// Keysym for a hotkey key name.
+pub fn keysym_for_name(name: &str) -> Option<u32> {
+    if let [c] = name.as_bytes() {
+        if c.is_ascii_lowercase() || c.is_ascii_digit() {
+            return Some(*c as u32);
+        }
+    }
+    if let Some(n) = name.strip_prefix('f').and_then(|n| n.parse::<u32>().ok()) {
+        return (1..=20).contains(&n).then(|| KEYSYM_F1 + n - 1);
+    }
+    NAMED_KEYSYMS
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, sym)| *sym)
+}
+
+/// The macOS keycode the state machine knows `keysym` by. Uppercase letters count as their
+/// lowercase key.
+pub fn key_for_keysym(keysym: u32) -> Option<Key> {
+    let name = match char::from_u32(keysym) {
+        Some(c) if c.is_ascii_alphanumeric() => c.to_ascii_lowercase().to_string(),
+        _ => {
+            let named = NAMED_KEYSYMS
+                .iter()
+                .find(|(_, sym)| *sym == keysym)
+                .map(|(n, _)| *n);
+            match named {
+                Some(n) => n.to_string(),
+                None if (KEYSYM_F1..KEYSYM_F1 + 20).contains(&keysym) => {
+                    format!("f{}", keysym - KEYSYM_F1 + 1)
+                }
+                None => return None,
+            }
+        }
+    };
+    keycode_for_name(&name).map(Key)
+}
+
+/// Modifier flags for a modifier keysym.
+pub fn modifier_for_keysym(keysym: u32) -> Option<Modifiers> {
+    Some(match keysym {
+        0xffe1 => Modifiers::SHIFT | Modifiers::LEFT_SHIFT,
+        0xffe2 => Modifiers::SHIFT | Modifiers::RIGHT_SHIFT,
+        0xffe3 => Modifiers::CMD | Modifiers::LEFT_CMD,
+        0xffe4 => Modifiers::CMD | Modifiers::RIGHT_CMD,
+        0xffe7 | 0xffe9 => Modifiers::OPTION | Modifiers::LEFT_OPTION,
+        0xffe8 | 0xffea | 0xfe03 => Modifiers::OPTION | Modifiers::RIGHT_OPTION,
+        0xffeb => Modifiers::CTRL | Modifiers::LEFT_CTRL,
+        0xffec => Modifiers::CTRL | Modifiers::RIGHT_CTRL,
+        0xffe5 => Modifiers::CAPS_LOCK,
+        _ => return None,
+    })
+}
+
+/// Core modifier mask a binding's chord corresponds to on X11.
+fn grab_mask(modifiers: Modifiers) -> ModMask {
+    let mut mask = ModMask::from(0u16);
+    for (flag, bit) in [
+        (Modifiers::CMD, ModMask::CONTROL),
+        (Modifiers::OPTION, ModMask::M1),
+        (Modifiers::CTRL, ModMask::M4),
+        (Modifiers::SHIFT, ModMask::SHIFT),
+    ] {
+        if modifiers.contains(flag) {
+            mask |= bit;
+        }
+    }
+    mask
+}
+
+/// Held modifiers, tracked from key events because recorded events carry no reliable state.
+#[derive(Default)]
+struct ModifierState {
+    held: Vec<(Keycode, Modifiers)>,
+    caps_lock: bool,
+}
+
+impl ModifierState {
+    /// Updates from a modifier key event; returns false if `keysym` is not a modifier.
+    fn update(&mut self, keycode: Keycode, keysym: u32, press: bool) -> bool {
+        let Some(flags) = modifier_for_keysym(keysym) else {
+            return false;
+        };
+        if flags == Modifiers::CAPS_LOCK {
+            self.caps_lock ^= press;
+        } else if press {
+            if !self.held.iter().any(|(k, _)| *k == keycode) {
+                self.held.push((keycode, flags));
+            }
+        } else {
+            self.held.retain(|(k, _)| *k != keycode);
+        }
+        true
+    }
+
+    fn current(&self) -> Modifiers {
+        let caps = if self.caps_lock {
+            Modifiers::CAPS_LOCK
+        } else {
+            Modifiers::empty()
+        };
+        self.held.iter().fold(caps, |m, (_, f)| m | *f)
+    }
+}
+
+/// XRecord capture plus XGrabKey swallowing, as a `KeySource`.
+pub struct X11Keys {
+    control: Arc<XDisplay>,
+    data: XDisplay,
+    context: record::Context,
+    stopped: Arc<AtomicBool>,
+}
+
+impl X11Keys {
+    /// Connects and sets up a RECORD context for key events from all clients.
+    pub fn connect() -> Result<Self, String> {
+        let control = XDisplay::connect()?;
+        let data = XDisplay::connect()?;
+        if control
+            .conn
+            .extension_information(record::X11_EXTENSION_NAME)
+            .map_err(err("query RECORD"))?
+            .is_none()
+        {
+            return Err("x11: the X server does not support the RECORD extension".to_string());
+        }
+        let context = control
+            .conn
+            .generate_id()
+            .map_err(err("allocate record context"))?;
+        let empty = record::Range8 { first: 0, last: 0 };
+        let empty_ext = record::ExtRange {
+            major: empty,
+            minor: record::Range16 { first: 0, last: 0 },
+        };
+        let range = record::Range {
+            core_requests: empty,
+            core_replies: empty,
+            ext_requests: empty_ext,
+            ext_replies: empty_ext,
+            delivered_events: empty,
+            device_events: record::Range8 {
+                first: xproto::KEY_PRESS_EVENT,
+                last: xproto::KEY_RELEASE_EVENT,
+            },
+            errors: empty,
+            client_started: false,
+            client_died: false,
+        };
+        control
+            .conn
+            .record_create_context(context, 0, &[record::CS::ALL_CLIENTS.into()], &[range])
+            .map_err(err("create record context"))?
+            .check()
+            .map_err(err("create record context"))?;
+        Ok(Self {
+            control: Arc::new(control),
+            data,
+            context,
+            stopped: Arc::new(AtomicBool::new(false)),
+        })
+    }
+}
+
+impl KeySource for X11Keys {
+    fn run(&mut self, sink: KeySink) -> Result<(), String> {
+        let keyboard = self.data.keyboard_map()?;
+        let grabs = {
+            let grab = XDisplay::connect()?;
+            let keyboard = grab.keyboard_map()?;
+            let (sink, stopped) = (sink.clone(), self.stopped.clone());
+            thread::spawn(move || run_grabs(grab, keyboard, sink, stopped))
+        };
+
+        let mut modifiers = ModifierState::default();
+        const RECORD_FROM_SERVER: u8 = 0;
+        let replies = self
+            .data
+            .conn
+            .record_enable_context(self.context)
+            .map_err(err("enable record context"))?;
+        for reply in replies {
+            let reply = reply.map_err(err("record"))?;
+            if reply.category != RECORD_FROM_SERVER || reply.client_swapped {
+                continue;
+            }
+            // Device events are 32 bytes each; a reply may carry several.
+            let mut data = &reply.data[..];
+            while data.len() >= 32 {
+                let kind = data[0] & 0x7f;
+                if kind == xproto::KEY_PRESS_EVENT || kind == xproto::KEY_RELEASE_EVENT {
+                    if let Ok((event, _)) = KeyPressEvent::try_parse(data) {
+                        deliver(
+                            &keyboard,
+                            &mut modifiers,
+                            &sink,
+                            event.detail,
+                            kind == xproto::KEY_PRESS_EVENT,
+                        );
+                    }
+                }
+                data = &data[32..];
+            }
+        }
+
+        self.stopped.store(true, Ordering::Release);
+        let _ = grabs.join();
+        let _ = self.control.conn.record_free_context(self.context);
+        let _ = self.control.conn.flush();
+        Ok(())
+    }
+
+    fn stopper(&self) -> Stopper {
+        let (control, context, stopped) =
+            (self.control.clone(), self.context, self.stopped.clone());
+        Box::new(move || {
+            stopped.store(true, Ordering::Release);
+            let _ = control.conn.record_disable_context(context);
+            let _ = control.conn.flush();
+        })
+    }
+}
+
+/// Decodes one recorded key event and forwards it to `sink`.
+fn deliver(
+    keyboard: &KeyboardMap,
+    modifiers: &mut ModifierState,
+    sink: &KeySink,
+    keycode: Keycode,
+    press: bool,
+) {
+    let keysym = keyboard.keysym(keycode);
+    if modifiers.update(keycode, keysym, press) {
+        sink.flags_changed(modifiers.current());
+        return;
+    }
+    let Some(key) = key_for_keysym(keysym) else {
+        return;
+    };
+    if press {
+        // Swallowing already happened through the grabs.
+        sink.key_down(key, modifiers.current());
+    } else {
+        sink.key_up(key, modifiers.current());
+    }
+}
+
+/// Keeps the passive grabs equal to the bindings swallowed in the current mode.
+fn run_grabs(display: XDisplay, keyboard: KeyboardMap, sink: KeySink, stopped: Arc<AtomicBool>) {
+    let mut grabbed: Vec<(Keycode, ModMask)> = Vec::new();
+    while !stopped.load(Ordering::Acquire) {
+        let wanted: Vec<(Keycode, ModMask)> = sink
+            .keymap()
+            .swallowed_in(sink.mode())
+            .iter()
+            .filter_map(|b| grab_for(&keyboard, b))
+            .collect();
+        if wanted != grabbed {
+            for (keycode, mask) in grabbed.drain(..) {
+                for lock in lock_variants() {
+                    let _ = display.conn.ungrab_key(keycode, display.root, mask | lock);
+                }
+            }
+            for (keycode, mask) in &wanted {
+                for lock in lock_variants() {
+                    let result = display
+                        .conn
+                        .grab_key(
+                            false,
+                            display.root,
+                            *mask | lock,
+                            *keycode,
+                            GrabMode::ASYNC,
+                            GrabMode::ASYNC,
+                        )
+                        .map_err(err("grab key"))
+                        .and_then(|c| c.check().map_err(err("grab key")));
+                    if let Err(e) = result {
+                        warn!(
+                            "x11 keys: keycode {} is grabbed by another client: {}",
+                            keycode, e
+                        );
+                        break;
+                    }
+                }
+            }
+            debug!("x11 keys: grabbed {} binding(s)", wanted.len());
+            grabbed = wanted;
+        }
+        // Grabbed key events also arrive here; the recorded copies are the ones delivered.
+        while let Ok(Some(_)) = display.conn.poll_for_event() {}
+        let _ = display.conn.flush();
+        thread::sleep(GRAB_POLL);
+    }
+    for (keycode, mask) in grabbed {
+        for lock in lock_variants() {
+            let _ = display.conn.ungrab_key(keycode, display.root, mask | lock);
+        }
+    }
+    let _ = display.conn.flush();
+}
+
+fn grab_for(keyboard: &KeyboardMap, binding: &KeyBinding) -> Option<(Keycode, ModMask)> {
+    let keysym = name_for_keycode(binding.key().0).and_then(keysym_for_name)?;
+    Some((keyboard.keycode(keysym)?, grab_mask(binding.modifiers())))
+}
+
+/// Caps Lock and Num Lock (Mod2) must not stop a grab from matching.
+fn lock_variants() -> [ModMask; 4] {
+    [
+        ModMask::from(0u16),
+        ModMask::LOCK,
+        ModMask::M2,
+        ModMask::LOCK | ModMask::M2,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::{KeyAction, Keymap};
+    use crate::state_machine::{Event, MODE_IDLE};
+    use std::sync::atomic::AtomicU8;
+    use std::sync::RwLock;
+
+    #[test]
+    fn keysyms_translate_to_hotkey_keys() {
+        for name in clip_config::hotkey::NAMED_KEYS
+            .iter()
+            .chain(&["a", "z", "0", "9"])
+        {
+            let sym = keysym_for_name(name).unwrap_or_else(|| panic!("no keysym for {}", name));
+            assert_eq!(
+                key_for_keysym(sym),
+                keycode_for_name(name).map(Key),
+                "{}",
+                name
+            );
+        }
+        assert_eq!(key_for_keysym('V' as u32), keycode_for_name("v").map(Key));
+        assert_eq!(key_for_keysym(0xffe3), None, "modifiers are not keys");
+    }
+
+    #[test]
+    fn modifier_state_tracks_held_keys() {
+        let mut m = ModifierState::default();
+        assert!(!m.update(55, 'v' as u32, true));
+        assert!(m.update(37, 0xffe3, true));
+        assert!(m.update(64, 0xffe9, true));
+        assert_eq!(
+            m.current() & Modifiers::CHORD,
+            Modifiers::CMD | Modifiers::OPTION
+        );
+        assert!(m.update(37, 0xffe3, false));
+        assert_eq!(m.current(), Modifiers::OPTION | Modifiers::LEFT_OPTION);
+        m.update(66, 0xffe5, true);
+        m.update(66, 0xffe5, false);
+        assert!(m.current().contains(Modifiers::CAPS_LOCK));
+        assert_eq!(
+            grab_mask(Modifiers::CMD | Modifiers::OPTION),
+            ModMask::CONTROL | ModMask::M1
+        );
+    }
+
+    #[test]
+    #[ignore = "needs an X server (run under xvfb-run)"]
+    fn x11_records_the_paste_trigger() {
+        let mut keys = X11Keys::connect().unwrap();
+        let stop = keys.stopper();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let keymap = Arc::new(RwLock::new(Keymap::default()));
+        let sink = KeySink::new(tx, keymap, Arc::new(AtomicU8::new(MODE_IDLE)));
+        let runner = thread::spawn(move || keys.run(sink));
+
+        let display = XDisplay::connect().unwrap();
+        let keyboard = display.keyboard_map().unwrap();
+        thread::sleep(Duration::from_millis(200));
+        for (sym, press) in [
+            (0xffe3, true),
+            (0xffe9, true),
+            ('v' as u32, true),
+            ('v' as u32, false),
+            (0xffe9, false),
+            (0xffe3, false),
+        ] {
+            super::super::paste::fake_key(&display, keyboard.keycode(sym).unwrap(), press).unwrap();
+        }
+
+        let mut found = None;
+        while let Ok(e) = rx.recv_timeout(Duration::from_secs(2)) {
+            if let Event::KeyDown(key, mods) = e {
+                found = Keymap::default().action(MODE_IDLE, key, mods);
+                break;
+            }
+        }
+        assert_eq!(found, Some(KeyAction::PasteTrigger));
+        stop();
+        runner.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,151 @@
+//! X11 backend: CLIPBOARD/PRIMARY selections, XRecord key capture with XGrabKey for the keys
+//! that must be swallowed, and XTest Ctrl+V.
+//!
+//! Tests that need a display are ignored by default; run them under a headless server with
+//! `xvfb-run cargo test -p clip-agent -- --ignored --test-threads=1 x11`.
+
+pub mod clipboard;
+pub mod keys;
+pub mod paste;
+
+use super::Platform;
+use std::fmt::Display;
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{
+    ConnectionExt as _, CreateWindowAux, EventMask, Keycode, Window, WindowClass,
+};
+use x11rb::rust_connection::RustConnection;
+
+x11rb::atom_manager! {
+    /// Atoms the backend interns on every connection.
+    pub Atoms: AtomsCookie {
+        CLIPBOARD,
+        PRIMARY,
+        TARGETS,
+        MULTIPLE,
+        TIMESTAMP,
+        SAVE_TARGETS,
+        INCR,
+        ATOM,
+        UTF8_STRING,
+        SLOTPASTE_SELECTION,
+    }
+}
+
+/// Connection to `$DISPLAY`, plus its root window.
+pub struct XDisplay {
+    pub conn: RustConnection,
+    pub root: Window,
+    pub atoms: Atoms,
+}
+
+impl XDisplay {
+    pub fn connect() -> Result<Self, String> {
+        let (conn, screen) = x11rb::connect(None).map_err(err("connect to X display"))?;
+        let root = conn.setup().roots[screen].root;
+        let atoms = Atoms::new(&conn)
+            .map_err(err("intern atoms"))?
+            .reply()
+            .map_err(err("intern atoms"))?;
+        Ok(Self { conn, root, atoms })
+    }
+
+    /// Unmapped window used as selection owner / requestor, listening for property changes.
+    pub fn create_window(&self) -> Result<Window, String> {
+        let win = self.conn.generate_id().map_err(err("allocate window id"))?;
+        self.conn
+            .create_window(
+                0,
+                win,
+                self.root,
+                0,
+                0,
+                1,
+                1,
+                0,
+                WindowClass::INPUT_ONLY,
+                0,
+                &CreateWindowAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+            )
+            .map_err(err("create window"))?;
+        self.conn.flush().map_err(err("flush"))?;
+        Ok(win)
+    }
+
+    /// Interns an atom by name (selection targets are dynamic).
+    pub fn atom(&self, name: &str) -> Result<u32, String> {
+        Ok(self
+            .conn
+            .intern_atom(false, name.as_bytes())
+            .map_err(err("intern atom"))?
+            .reply()
+            .map_err(err("intern atom"))?
+            .atom)
+    }
+
+    pub fn atom_name(&self, atom: u32) -> Option<String> {
+        let reply = self.conn.get_atom_name(atom).ok()?.reply().ok()?;
+        String::from_utf8(reply.name).ok()
+    }
+
+    /// Keysym table for every keycode.
+    pub fn keyboard_map(&self) -> Result<KeyboardMap, String> {
+        let setup = self.conn.setup();
+        let (min, max) = (setup.min_keycode, setup.max_keycode);
+        let reply = self
+            .conn
+            .get_keyboard_mapping(min, max - min + 1)
+            .map_err(err("get keyboard mapping"))?
+            .reply()
+            .map_err(err("get keyboard mapping"))?;
+        Ok(KeyboardMap {
+            min_keycode: min,
+            per_keycode: reply.keysyms_per_keycode as usize,
+            keysyms: reply.keysyms,
+        })
+    }
+}
+
+/// Keycode ↔ keysym lookup from the server's keyboard mapping.
+pub struct KeyboardMap {
+    min_keycode: Keycode,
+    per_keycode: usize,
+    keysyms: Vec<u32>,
+}
+
+impl KeyboardMap {
+    /// Unshifted keysym of `keycode` (0 if unmapped).
+    pub fn keysym(&self, keycode: Keycode) -> u32 {
+        if keycode < self.min_keycode || self.per_keycode == 0 {
+            return 0;
+        }
+        let i = (keycode - self.min_keycode) as usize * self.per_keycode;
+        self.keysyms.get(i).copied().unwrap_or(0)
+    }
+
+    /// First keycode whose unshifted keysym is `keysym`.
+    pub fn keycode(&self, keysym: u32) -> Option<Keycode> {
+        if self.per_keycode == 0 {
+            return None;
+        }
+        self.keysyms
+            .chunks(self.per_keycode)
+            .position(|syms| syms[0] == keysym)
+            .map(|i| self.min_keycode + i as Keycode)
+    }
+}
+
+/// Formats an X11 error with context, for `map_err`.
+pub fn err<E: Display>(ctx: &'static str) -> impl Fn(E) -> String {
+    move |e| format!("x11: {}: {}", ctx, e)
+}
+
+/// Selections, key capture and paste on the X server named by `$DISPLAY`.
+pub fn platform() -> Result<Platform, String> {
+    let owner = clipboard::SelectionOwner::spawn()?;
+    Ok(Platform {
+        clipboard: Box::new(clipboard::X11Clipboard::connect()?),
+        paste: Box::new(paste::CtrlVPaste::new(owner)?),
+        keys: Box::new(keys::X11Keys::connect()?),
+    })
+}
@@ -0,0 +1,914 @@
+//! Slotpaste state machine: chooser overlay (save after the save trigger, Cmd+C by default; paste
+//! after the paste trigger, Cmd+Option+V by default). Key-downs are resolved through the `Keymap`
+//! built from `[keys]`.
+//!
+//! The machine tracks whether a chooser UI is running from its hellos and heartbeats
+//! (`Event::UiSeen`). Without one a chooser opens keyboard-only: nothing is shown, and the slot
+//! and cancel keys still pick or close it until its deadline.
+
+use crate::ipc::request::{Reply, Request};
+use crate::ipc::ChooserUi;
+use crate::keys::{Key, KeyAction, Keymap, Modifiers, SlotId};
+use crate::payload::{ClipPayload, SlotTransform};
+use crate::persistence::memory::MemoryStore;
+use crate::persistence::{HistoryEntry, NewHistoryEntry, SlotMetadata, SlotStore};
+use crate::platform::{ClipboardBackend, PasteInjector};
+use crate::timer::{Clock, TimerId, Timers};
+use clip_config::{Config, KeysConfig};
+use clip_protocol::{ChooserKeys, PRESENCE_TIMEOUT};
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info};
+
+/// Mode for event tap: 0=Idle, 1=SaveChooserPending, 2=PasteChooserActive.
+pub const MODE_IDLE: u8 = 0;
+pub const MODE_SAVE_PENDING: u8 = 1;
+pub const MODE_PASTE_ACTIVE: u8 = 2;
+
+/// Internal events from event tap, IPC, or timer.
+#[derive(Debug, Clone)]
+pub enum Event {
+    KeyDown(Key, Modifiers),
+    // Forwarded by every key source; the state machine acts on key-downs only.
+    #[allow(dead_code)]
+    KeyUp(Key, Modifiers),
+    #[allow(dead_code)]
+    FlagsChanged(Modifiers),
+    /// Save trigger (`keys.save_trigger`) -> show save chooser.
+    SaveTrigger,
+    /// Paste trigger (`keys.paste_trigger`) -> show paste chooser.
+    PasteTrigger,
+    /// UI or slot key chose slot 1..6.
+    ChooserChosen {
+        token: String,
+        slot_num: u8,
+    },
+    /// UI cancel, cancel key, or timeout.
+    ChooserCancel {
+        token: String,
+        reason: String,
+    },
+    /// The UI never acknowledged the show for chooser `token`.
+    UiUnreachable {
+        token: String,
+        error: String,
+    },
+    /// A UI said hello or sent a heartbeat, over `transport` ("udp" or "unix").
+    UiSeen {
+        client: String,
+        transport: &'static str,
+    },
+    /// The UI's socket connection closed.
+    UiGone,
+    /// Validated config from a live reload; replaces the running config and keymap between events.
+    ConfigReloaded(Box<Config>),
+    /// Slot request from IPC; the result goes to the reply channel.
+    Request(Request, Reply),
+    /// Sends every later slot and chooser event (`publish`) to this channel until it closes.
+    Subscribe(Sender<Value>),
+    Quit,
+}
+
+/// Delay after the save trigger before reading the clipboard for history (lets the copy land).
+const HISTORY_CAPTURE_DELAY_MS: u64 = 150;
+
+/// State machine state. `deadline` is the chooser's timeout timer, cancelled when it closes.
+#[derive(Debug)]
+enum State {
+    Idle,
+    SaveChooserPending { token: String, deadline: TimerId },
+    PasteChooserActive { token: String, deadline: TimerId },
+}
+
+/// Slot storage and clipboard history on top of a `SlotStore` backend.
+pub struct SlotStorage {
+    store: Box<dyn SlotStore>,
+    history_retention: usize,
+    /// Slots that save trimmed text, from `slots.trim`.
+    trimmed: Vec<SlotId>,
+}
+
+impl SlotStorage {
+    pub fn new(store: Box<dyn SlotStore>) -> Self {
+        Self {
+            store,
+            history_retention: clip_config::HistoryConfig::default().retention,
+            trimmed: Vec::new(),
+        }
+    }
+
+    /// Storage that is lost when the agent exits.
+    pub fn in_memory() -> Self {
+        Self::new(Box::new(MemoryStore::new()))
+    }
+
+    /// Sets how many history entries are kept; older ones are pruned.
+    pub fn set_history_retention(&mut self, retention: usize) -> Result<(), String> {
+        self.history_retention = retention;
+        self.store.prune_history(retention)
+    }
+
+    /// Saves content byte-exact, unless the slot opted into a transform.
+    pub fn save(&mut self, slot: SlotId, content: &ClipPayload) -> Result<(), String> {
+        self.store.save(slot, &self.transform(slot).apply(content))
+    }
+
+    pub fn transform(&self, slot: SlotId) -> SlotTransform {
+        if self.trimmed.contains(&slot) {
+            SlotTransform::Trim
+        } else {
+            SlotTransform::None
+        }
+    }
+
+    /// Trims the slots listed in `slots.trim` and stores every other slot byte-exact.
+    pub fn set_trimmed_slots(&mut self, trim: &[String]) {
+        self.trimmed = SlotId::ALL
+            .into_iter()
+            .filter(|slot| trim.iter().any(|id| id == slot.label()))
+            .collect();
+    }
+
+    pub fn get(&self, slot: SlotId) -> Result<Option<ClipPayload>, String> {
+        self.store.get(slot)
+    }
+
+    pub fn clear(&mut self, slot: SlotId) -> Result<(), String> {
+        self.store.clear(slot)
+    }
+
+    pub fn list(&self) -> Result<Vec<SlotMetadata>, String> {
+        self.store.list()
+    }
+
+    pub fn metadata(&self, slot: SlotId) -> Result<Option<SlotMetadata>, String> {
+        self.store.metadata(slot)
+    }
+
+    /// Appends clipboard content to history. Skipped if identical to the newest entry.
+    /// Returns the new entry's id, or None if skipped.
+    pub fn record_history(&mut self, content: &str, source: &str) -> Result<Option<i64>, String> {
+        if content.is_empty() || self.history_retention == 0 {
+            return Ok(None);
+        }
+        let content_hash = crate::persistence::content_hash(content);
+        let newest = self.store.history(1)?.into_iter().next();
+        if newest
+            .as_ref()
+            .map(|e| e.content_hash == content_hash)
+            .unwrap_or(false)
+        {
+            debug!("history: duplicate of newest entry, skipped");
+            return Ok(None);
+        }
+        let entry = NewHistoryEntry {
+            content: content.to_string(),
+            content_hash,
+            created_at: crate::persistence::unix_now(),
+            source: source.to_string(),
+        };
+        let id = self.store.push_history(&entry)?;
+        self.store.prune_history(self.history_retention)?;
+        Ok(Some(id))
+    }
+
+    /// Lists up to `limit` history entries, newest first.
+    pub fn list_history(&self, limit: usize) -> Result<Vec<HistoryEntry>, String> {
+        self.store.history(limit)
+    }
+
+    /// Fetches a history entry by id.
+    pub fn history_entry(&self, id: i64) -> Result<Option<HistoryEntry>, String> {
+        self.store.history_entry(id)
+    }
+
+    /// Copies a history entry into a slot.
+    pub fn promote_history(&mut self, id: i64, slot: SlotId) -> Result<(), String> {
+        let entry = self
+            .history_entry(id)?
+            .ok_or_else(|| format!("history entry {} not found", id))?;
+        self.save(slot, &ClipPayload::from_text(&entry.content))?;
+        info!("Promoted history #{} → Slot {}", id, slot.label());
+        Ok(())
+    }
+}
+
+impl Default for SlotStorage {
+    fn default() -> Self {
+        Self::in_memory()
+    }
+}
+
+/// Everything the state machine touches outside itself.
+pub struct Services {
+    pub clock: Box<dyn Clock>,
+    pub ui: Box<dyn ChooserUi>,
+    pub clipboard: Box<dyn ClipboardBackend>,
+    pub paste: Box<dyn PasteInjector>,
+}
+
+/// Runs the state machine loop. Returns when Quit or a stop request is received.
+pub fn run(
+    rx: Receiver<Event>,
+    mode: Arc<AtomicU8>,
+    store: Box<dyn SlotStore>,
+    config: Config,
+    services: Services,
+) {
+    let mut machine = StateMachine::new(mode, store, config, services);
+    loop {
+        let event = match machine.next_deadline() {
+            Some(deadline) => {
+                let wait = deadline.saturating_duration_since(machine.services.clock.now());
+                match rx.recv_timeout(wait) {
+                    Ok(e) => e,
+                    Err(RecvTimeoutError::Timeout) => {
+                        machine.fire_due_timers();
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            None => match rx.recv() {
+                Ok(e) => e,
+                Err(_) => break,
+            },
+        };
+        if !machine.handle(event) {
+            break;
+        }
+    }
+}
+
+/// Work scheduled on the state machine's timer queue.
+#[derive(Debug)]
+enum Timer {
+    /// The chooser with this token closes as cancelled ("timeout").
+    ChooserDeadline { token: String },
+    /// Read the clipboard into history now that the copy has landed.
+    HistoryCapture,
+}
+
+/// The chooser UI the agent last heard from.
+#[derive(Debug)]
+struct SeenUi {
+    client: String,
+    transport: &'static str,
+    at: Instant,
+}
+
+/// Chooser state machine. All time comes from `services.clock`; chooser deadlines and the
+/// history capture delay are entries in `timers`, fired by `fire_due_timers`.
+pub struct StateMachine {
+    state: State,
+    ui: Option<SeenUi>,
+    /// Whether the open chooser was sent to the UI (false: keyboard-only).
+    chooser_on_ui: bool,
+    slots: SlotStorage,
+    config: Config,
+    keymap: Keymap,
+    next_token: u64,
+    mode: Arc<AtomicU8>,
+    services: Services,
+    timers: Timers<Timer>,
+    subscribers: Vec<Sender<Value>>,
+}
+
+impl StateMachine {
+    pub fn new(
+        mode: Arc<AtomicU8>,
+        store: Box<dyn SlotStore>,
+        config: Config,
+        services: Services,
+    ) -> Self {
+        let mut slots = SlotStorage::new(store);
+        match slots.list() {
+            Ok(filled) => info!("loaded {} slots", filled.len()),
+            Err(e) => error!("slot storage unreadable: {}", e),
+        }
+        if let Err(e) = slots.set_history_retention(config.history.retention) {
+            error!("history prune failed: {}", e);
+        }
+        slots.set_trimmed_slots(&config.slots.trim);
+        let keymap = match Keymap::from_config(&config.keys) {
+            Ok(k) => k,
+            Err(e) => {
+                error!("{}; using default keys", e);
+                Keymap::default()
+            }
+        };
+        mode.store(MODE_IDLE, Ordering::Release);
+        Self {
+            state: State::Idle,
+            ui: None,
+            chooser_on_ui: false,
+            slots,
+            config,
+            keymap,
+            next_token: 0,
+            mode,
+            services,
+            timers: Timers::new(),
+            subscribers: Vec::new(),
+        }
+    }
+
+    /// Earliest pending timer; the caller should call `fire_due_timers` once it passes.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.timers.next_deadline()
+    }
+
+    /// Runs every timer due at the clock's current time.
+    pub fn fire_due_timers(&mut self) {
+        for timer in self.timers.pop_due(self.services.clock.now()) {
+            match timer {
+                Timer::ChooserDeadline { token } => {
+                    info!("chooser deadline passed (token={})", token);
+                    self.handle(Event::ChooserCancel {
+                        token,
+                        reason: "timeout".to_string(),
+                    });
+                }
+                Timer::HistoryCapture => {
+                    if let Some(content) = self.services.clipboard.read_text() {
+                        self.record_history(&content);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Handles one event. Returns false once Quit or a stop request is received.
+    pub fn handle(&mut self, event: Event) -> bool {
+        // A late timer must not let a reply slip past the deadline.
+        self.fire_due_timers();
+
+        let event = match event {
+            Event::KeyDown(key, modifiers) => {
+                match resolve_key(&self.keymap, &self.state, key, modifiers) {
+                    Some(resolved) => resolved,
+                    None => return true,
+                }
+            }
+            other => other,
+        };
+
+        match &event {
+            Event::Quit => {
+                debug!("Received Quit");
+                self.close_open_chooser();
+                return false;
+            }
+            Event::Request(Request::Stop, reply) => {
+                info!("stop requested over IPC; agent stopping");
+                self.close_open_chooser();
+                let _ = reply.send(Ok(json!({"stopping": true})));
+                return false;
+            }
+            Event::SaveTrigger => {
+                let at =
+                    self.services.clock.now() + Duration::from_millis(HISTORY_CAPTURE_DELAY_MS);
+                self.timers.schedule(at, Timer::HistoryCapture);
+            }
+            Event::ConfigReloaded(new) => {
+                self.apply_config((**new).clone());
+                return true;
+            }
+            Event::Request(request, reply) => {
+                let _ = reply.send(self.handle_request(request.clone()));
+                return true;
+            }
+            Event::Subscribe(subscriber) => {
+                self.subscribers.push(subscriber.clone());
+                return true;
+            }
+            Event::UiUnreachable { token, error } => {
+                self.report_unreachable_ui(token, error);
+                return true;
+            }
+            Event::UiSeen { client, transport } => {
+                if !self.ui_present() {
+                    info!("chooser UI running: {} over {}", client, transport);
+                }
+                let at = self.services.clock.now();
+                self.ui = Some(SeenUi {
+                    client: client.clone(),
+                    transport,
+                    at,
+                });
+                return true;
+            }
+            Event::UiGone => {
+                info!("chooser UI disconnected; choosers are keyboard-only until it is back");
+                self.ui = None;
+                return true;
+            }
+            _ => {}
+        }
+
+        let state = std::mem::replace(&mut self.state, State::Idle);
+        self.state = match state {
+            State::Idle => self.handle_idle(event),
+            State::SaveChooserPending { token, deadline } => {
+                self.handle_save_chooser_pending(event, token, deadline)
+            }
+            State::PasteChooserActive { token, deadline } => {
+                self.handle_paste_chooser_active(event, token, deadline)
+            }
+        };
+        set_mode_for_state(&self.state, &self.mode);
+        true
+    }
+
+    fn record_history(&mut self, content: &str) {
+        let source = self.config.keys.save_trigger.to_string();
+        match self.slots.record_history(content, &source) {
+            Ok(Some(id)) => debug!("history #{}: \"{}\"", id, preview_for_log(content)),
+            Ok(None) => {}
+            Err(e) => error!("history append failed: {}", e),
+        }
+    }
+
+    fn apply_config(&mut self, new: Config) {
+        if new.history.retention != self.config.history.retention {
+            if let Err(e) = self.slots.set_history_retention(new.history.retention) {
+                error!("history prune failed: {}", e);
+            }
+        }
+        if new.slots.trim != self.config.slots.trim {
+            self.slots.set_trimmed_slots(&new.slots.trim);
+        }
+        match Keymap::from_config(&new.keys) {
+            Ok(k) => self.keymap = k,
+            Err(e) => error!("{}; keeping previous keys", e),
+        }
+        // An open chooser keeps its deadline; the new timeout applies to the next one.
+        self.config = new;
+    }
+
+    /// Sends an event to every subscriber, dropping the ones that went away.
+    fn publish(&mut self, event: Value) {
+        self.subscribers.retain(|s| s.send(event.clone()).is_ok());
+    }
+
+    /// Whether a UI has said hello or sent a heartbeat within `PRESENCE_TIMEOUT`.
+    fn ui_present(&self) -> bool {
+        let now = self.services.clock.now();
+        self.ui
+            .as_ref()
+            .is_some_and(|ui| now.saturating_duration_since(ui.at) < PRESENCE_TIMEOUT)
+    }
+
+    /// Shows a chooser, or opens it keyboard-only when no UI is running, and schedules its
+    /// deadline.
+    fn open_chooser(&mut self, mode: &str) -> (String, TimerId) {
+        let timeout_ms = self.config.chooser.timeout_ms;
+        let token = match clip_config::auth::random_token() {
+            Ok(token) => token,
+            Err(e) => {
+                // Still safe to use: the UI's reply has to be signed either way.
+                error!("chooser token: {}; using a sequence number", e);
+                self.next_token += 1;
+                self.next_token.to_string()
+            }
+        };
+        let deadline = self.services.clock.now() + Duration::from_millis(timeout_ms);
+        self.chooser_on_ui = self.ui_present();
+        if self.chooser_on_ui {
+            info!("send_show({}, token={}) -> UI", mode, token);
+            let keys = chooser_keys(&self.config.keys, mode);
+            self.services
+                .ui
+                .show(&self.config.ipc, mode, &token, timeout_ms, keys);
+        } else {
+            info!(
+                "no chooser UI running; {} chooser is keyboard-only for {} ms",
+                mode, timeout_ms
+            );
+        }
+        let timer = self.timers.schedule(
+            deadline,
+            Timer::ChooserDeadline {
+                token: token.clone(),
+            },
+        );
+        self.publish(json!({
+            "event": "chooser_opened", "mode": mode, "timeout_ms": timeout_ms, "ui": self.chooser_on_ui,
+        }));
+        (token, timer)
+    }
+
+    /// Hides the chooser and cancels its deadline timer. `reason` is "chosen" or the cancel
+    /// reason.
+    fn close_chooser(&mut self, mode: &str, token: &str, timer: TimerId, reason: &str) {
+        self.timers.cancel(timer);
+        if self.chooser_on_ui {
+            info!("send_hide(token={}) -> UI", token);
+            self.services.ui.hide(&self.config.ipc, token);
+        }
+        self.publish(json!({"event": "chooser_closed", "mode": mode, "reason": reason}));
+    }
+
+    /// Closes the open chooser, if any, because the agent is stopping.
+    fn close_open_chooser(&mut self) {
+        match std::mem::replace(&mut self.state, State::Idle) {
+            State::Idle => {}
+            State::SaveChooserPending { token, deadline } => {
+                self.close_chooser("save", &token, deadline, "stopped")
+            }
+            State::PasteChooserActive { token, deadline } => {
+                self.close_chooser("paste", &token, deadline, "stopped")
+            }
+        }
+        set_mode_for_state(&self.state, &self.mode);
+    }
+
+    /// Logs and publishes that the UI missed the open chooser's show. The chooser stays open
+    /// until its deadline, keyboard-only, and later ones are keyboard-only until the UI is heard
+    /// from again.
+    fn report_unreachable_ui(&mut self, token: &str, error: &str) {
+        let mode = match &self.state {
+            State::SaveChooserPending { token: t, .. } if t == token => "save",
+            State::PasteChooserActive { token: t, .. } if t == token => "paste",
+            _ => return,
+        };
+        error!(
+            "chooser UI is not responding ({}); is clip-ui running?",
+            error
+        );
+        self.ui = None;
+        self.chooser_on_ui = false;
+        self.publish(json!({"event": "ui_unreachable", "mode": mode, "error": error}));
+    }
+
+    fn save_slot_from_clipboard(&mut self, slot: SlotId) {
+        match self.services.clipboard.read() {
+            Some(content) => {
+                let preview = describe_for_log(&content);
+                match self.slots.save(slot, &content) {
+                    Ok(()) => {
+                        info!("Saved → Slot {}: {}", slot.label(), preview);
+                        self.publish(slot_event("slot_saved", slot, "chooser"));
+                    }
+                    Err(e) => error!("Save to Slot {} failed: {}", slot.label(), e),
+                }
+            }
+            None => info!("Nothing to save (clipboard is empty)"),
+        }
+    }
+
+    fn handle_idle(&mut self, event: Event) -> State {
+        match event {
+            Event::SaveTrigger => {
+                info!(
+                    "{} detected -> save chooser flow",
+                    self.config.keys.save_trigger
+                );
+                let (token, timer) = self.open_chooser("save");
+                info!(
+                    "Chooser show (save) token={} -> UI, state=SaveChooserPending",
+                    token
+                );
+                State::SaveChooserPending {
+                    token,
+                    deadline: timer,
+                }
+            }
+            Event::PasteTrigger => {
+                info!(
+                    "{} detected -> paste chooser flow",
+                    self.config.keys.paste_trigger
+                );
+                let (token, timer) = self.open_chooser("paste");
+                info!(
+                    "Chooser show (paste) token={} -> UI, state=PasteChooserActive",
+                    token
+                );
+                State::PasteChooserActive {
+                    token,
+                    deadline: timer,
+                }
+            }
+            _ => State::Idle,
+        }
+    }
+
+    fn handle_save_chooser_pending(
+        &mut self,
+        event: Event,
+        token: String,
+        deadline: TimerId,
+    ) -> State {
+        match event {
+            Event::ChooserChosen { token: t, slot_num } if t == token => {
+                info!("Save chooser: user chose slot {} (token={})", slot_num, t);
+                if let Some(slot) = SlotId::from_slot_num(slot_num) {
+                    self.save_slot_from_clipboard(slot);
+                }
+                self.close_chooser("save", &token, deadline, "chosen");
+                State::Idle
+            }
+            Event::ChooserCancel { token: t, reason } if t == token => {
+                info!("Save chooser cancelled: {} (token={})", reason, t);
+                self.close_chooser("save", &token, deadline, &reason);
+                State::Idle
+            }
+            _ => State::SaveChooserPending { token, deadline },
+        }
+    }
+
+    fn handle_paste_chooser_active(
+        &mut self,
+        event: Event,
+        token: String,
+        deadline: TimerId,
+    ) -> State {
+        match event {
+            Event::ChooserChosen { token: t, slot_num } if t == token => {
+                info!("Paste chooser: user chose slot {} (token={})", slot_num, t);
+                if let Some(slot) = SlotId::from_slot_num(slot_num) {
+                    match self.slots.get(slot) {
+                        Ok(Some(content)) if !content.is_empty() => {
+                            info!("Pasted ← Slot {}", slot.label());
+                            let restore_delay =
+                                Duration::from_millis(self.config.paste.restore_delay_ms);
+                            self.services.paste.paste(content, restore_delay);
+                            self.publish(slot_event("slot_pasted", slot, "chooser"));
+                        }
+                        Ok(_) => info!("Slot {} is empty", slot.label()),
+                        Err(e) => error!("Slot {} read failed: {}", slot.label(), e),
+                    }
+                }
+                self.close_chooser("paste", &token, deadline, "chosen");
+                State::Idle
+            }
+            Event::ChooserCancel { token: t, reason } if t == token => {
+                info!("Paste chooser cancelled: {} (token={})", reason, t);
+                self.close_chooser("paste", &token, deadline, &reason);
+                State::Idle
+            }
+            _ => State::PasteChooserActive { token, deadline },
+        }
+    }
+}
+
+/// The bindings the UI should answer to in a `mode` chooser.
+fn chooser_keys(keys: &KeysConfig, mode: &str) -> ChooserKeys {
+    let slots = if mode == "paste" {
+        &keys.paste_slots
+    } else {
+        &keys.save_slots
+    };
+    ChooserKeys {
+        slots: slots.iter().map(ToString::to_string).collect(),
+        cancel: keys.cancel.to_string(),
+    }
+}
+
+/// A `slot_*` event for subscribers; `source` is "chooser" or "ipc".
+fn slot_event(event: &str, slot: SlotId, source: &str) -> Value {
+    json!({"event": event, "slot": slot.slot_num(), "id": slot.label(), "source": source})
+}
+
+fn mode_for_state(state: &State) -> u8 {
+    match state {
+        State::Idle => MODE_IDLE,
+        State::SaveChooserPending { .. } => MODE_SAVE_PENDING,
+        State::PasteChooserActive { .. } => MODE_PASTE_ACTIVE,
+    }
+}
+
+fn set_mode_for_state(state: &State, mode: &AtomicU8) {
+    mode.store(mode_for_state(state), Ordering::Release);
+}
+
+/// Turns a key-down into the event its keymap action stands for: triggers open a chooser, slot
+/// and cancel keys act like the UI's chosen/cancel for the open chooser. None if unbound.
+fn resolve_key(keymap: &Keymap, state: &State, key: Key, modifiers: Modifiers) -> Option<Event> {
+    let token = match state {
+        State::Idle => None,
+        State::SaveChooserPending { token, .. } | State::PasteChooserActive { token, .. } => {
+            Some(token.clone())
+        }
+    };
+    match keymap.action(mode_for_state(state), key, modifiers)? {
+        KeyAction::SaveTrigger => Some(Event::SaveTrigger),
+        KeyAction::PasteTrigger => Some(Event::PasteTrigger),
+        KeyAction::Slot(slot_num) => Some(Event::ChooserChosen {
+            token: token?,
+            slot_num,
+        }),
+        KeyAction::Cancel => Some(Event::ChooserCancel {
+            token: token?,
+            reason: "esc".to_string(),
+        }),
+    }
+}
+
+/// Text preview if the payload has text, else its types and size.
+fn describe_for_log(payload: &ClipPayload) -> String {
+    match payload.text() {
+        Some(text) => format!("\"{}\"", preview_for_log(text)),
+        None => format!(
+            "[{}] ({} bytes)",
+            payload.types().join(", "),
+            payload.size()
+        ),
+    }
+}
+
+fn preview_for_log(s: &str) -> String {
+    let trimmed: String = s.trim().replace(['\n', '\r'], " ");
+    let chars: Vec<_> = trimmed.chars().collect();
+    if chars.len() <= 30 {
+        trimmed
+    } else {
+        chars.iter().take(30).collect::<String>() + "..."
+    }
+}
+
+mod requests;
+pub use requests::answer_slots;
+#[cfg(test)]
+mod scenarios;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::sqlite::SqliteStore;
+    use crate::platform::fake::{FakeClipboard, RecordingPaste, RecordingUi};
+    use crate::timer::ManualClock;
+
+    const SAMPLES: &[&str] = &[
+        "    fn indented() {\n        body();\n    }\n",
+        "echo hello\n",
+        "a\tb\tc",
+        "line\r\nwith crlf\r\n",
+        "  leading and trailing  ",
+        "\n",
+    ];
+
+    fn sqlite_storage() -> SlotStorage {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::persistence::migrations::migrate(&mut conn).unwrap();
+        SlotStorage::new(Box::new(SqliteStore::new(conn)))
+    }
+
+    fn assert_round_trip(storage: &mut SlotStorage) {
+        for (i, text) in SAMPLES.iter().enumerate() {
+            let slot = SlotId::ALL[i % SlotId::ALL.len()];
+            storage.save(slot, &ClipPayload::from_text(text)).unwrap();
+            let stored = storage.get(slot).unwrap().unwrap();
+            assert_eq!(stored.text(), Some(*text), "slot {}", slot.label());
+        }
+    }
+
+    #[test]
+    fn memory_storage_is_byte_exact() {
+        assert_round_trip(&mut SlotStorage::in_memory());
+    }
+
+    #[test]
+    fn sqlite_storage_is_byte_exact() {
+        assert_round_trip(&mut sqlite_storage());
+    }
+
+    #[test]
+    fn trim_is_opt_in_per_slot() {
+        let mut storage = sqlite_storage();
+        storage.set_trimmed_slots(&["K".to_string()]);
+        storage
+            .save(SlotId::J, &ClipPayload::from_text("  j\n"))
+            .unwrap();
+        storage
+            .save(SlotId::K, &ClipPayload::from_text("  k\n"))
+            .unwrap();
+        assert_eq!(
+            storage.get(SlotId::J).unwrap().unwrap().text(),
+            Some("  j\n")
+        );
+        assert_eq!(storage.get(SlotId::K).unwrap().unwrap().text(), Some("k"));
+    }
+
+    #[test]
+    fn history_is_byte_exact() {
+        let mut storage = sqlite_storage();
+        let id = storage.record_history("  x\n", "cmd+c").unwrap().unwrap();
+        assert_eq!(storage.history_entry(id).unwrap().unwrap().content, "  x\n");
+        storage.promote_history(id, SlotId::O).unwrap();
+        assert_eq!(
+            storage.get(SlotId::O).unwrap().unwrap().text(),
+            Some("  x\n")
+        );
+    }
+
+    fn machine(clock: &ManualClock) -> (StateMachine, Arc<AtomicU8>) {
+        let mode = Arc::new(AtomicU8::new(MODE_IDLE));
+        let services = Services {
+            clock: Box::new(clock.clone()),
+            ui: Box::new(RecordingUi::default()),
+            clipboard: Box::new(FakeClipboard::default()),
+            paste: Box::new(RecordingPaste::default()),
+        };
+        let m = StateMachine::new(
+            mode.clone(),
+            Box::new(MemoryStore::new()),
+            Config::default(),
+            services,
+        );
+        (m, mode)
+    }
+
+    fn token(m: &StateMachine) -> String {
+        match &m.state {
+            State::SaveChooserPending { token, .. } | State::PasteChooserActive { token, .. } => {
+                token.clone()
+            }
+            State::Idle => panic!("no chooser open"),
+        }
+    }
+
+    #[test]
+    fn deadline_closes_chooser_without_ui_reply() {
+        let clock = ManualClock::new();
+        let (mut m, mode) = machine(&clock);
+        m.handle(Event::PasteTrigger);
+        assert_eq!(mode.load(Ordering::Acquire), MODE_PASTE_ACTIVE);
+        let deadline = m.next_deadline().unwrap();
+        assert_eq!(deadline, clock.now() + Duration::from_millis(800));
+
+        clock.advance(Duration::from_millis(799));
+        m.fire_due_timers();
+        assert_eq!(mode.load(Ordering::Acquire), MODE_PASTE_ACTIVE);
+
+        clock.advance(Duration::from_millis(1));
+        m.fire_due_timers();
+        assert_eq!(mode.load(Ordering::Acquire), MODE_IDLE);
+        assert_eq!(m.next_deadline(), None);
+    }
+
+    #[test]
+    fn reply_after_deadline_is_refused_even_before_timer_runs() {
+        let clock = ManualClock::new();
+        let (mut m, mode) = machine(&clock);
+        m.slots
+            .save(SlotId::J, &ClipPayload::from_text("j"))
+            .unwrap();
+        m.handle(Event::PasteTrigger);
+        let token = token(&m);
+        clock.advance(Duration::from_secs(5));
+        // The chooser expired first, so the late pick finds no open chooser.
+        m.handle(Event::ChooserChosen { token, slot_num: 1 });
+        assert_eq!(mode.load(Ordering::Acquire), MODE_IDLE);
+        assert!(matches!(m.state, State::Idle));
+    }
+
+    #[test]
+    fn closing_a_chooser_cancels_its_timer() {
+        let clock = ManualClock::new();
+        let (mut m, _mode) = machine(&clock);
+        m.handle(Event::PasteTrigger);
+        let first = token(&m);
+        m.handle(Event::ChooserCancel {
+            token: first.clone(),
+            reason: "esc".to_string(),
+        });
+        assert_eq!(m.next_deadline(), None);
+
+        clock.advance(Duration::from_millis(500));
+        m.handle(Event::PasteTrigger);
+        let second = token(&m);
+        assert_ne!(first, second);
+        // The first chooser's deadline (t=800ms) must not close the second one.
+        clock.advance(Duration::from_millis(400));
+        m.fire_due_timers();
+        assert_eq!(token(&m), second);
+        clock.advance(Duration::from_millis(400));
+        m.fire_due_timers();
+        assert!(matches!(m.state, State::Idle));
+    }
+
+    #[test]
+    fn reloaded_timeout_applies_to_the_next_chooser() {
+        let clock = ManualClock::new();
+        let (mut m, _mode) = machine(&clock);
+        m.handle(Event::PasteTrigger);
+        let mut config = Config::default();
+        config.chooser.timeout_ms = 2000;
+        m.handle(Event::ConfigReloaded(Box::new(config)));
+        assert_eq!(
+            m.next_deadline(),
+            Some(clock.now() + Duration::from_millis(800))
+        );
+        clock.advance(Duration::from_millis(800));
+        m.fire_due_timers();
+        m.handle(Event::PasteTrigger);
+        assert_eq!(
+            m.next_deadline(),
+            Some(clock.now() + Duration::from_millis(2000))
+        );
+    }
+}
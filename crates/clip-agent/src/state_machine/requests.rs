@@ -0,0 +1,189 @@
+//! Slot requests from IPC (`ipc::request`). They are answered between events and leave any
+//! open chooser alone.
+
+use super::{describe_for_log, preview_for_log, slot_event, SlotStorage, State, StateMachine};
+use crate::ipc::request::Request;
+use crate::keys::SlotId;
+use clip_config::Config;
+use serde_json::{json, Value};
+use std::time::Duration;
+use tracing::info;
+
+impl StateMachine {
+    /// Answers a request and tells subscribers about any slot it changed, pasted or copied.
+    pub(super) fn handle_request(&mut self, request: Request) -> Result<Value, String> {
+        let event = match &request {
+            Request::Save { slot, .. } => Some(("slot_saved", *slot)),
+            Request::Clear { slot } => Some(("slot_cleared", *slot)),
+            Request::Paste { slot } => Some(("slot_pasted", *slot)),
+            Request::Copy { slot } => Some(("slot_copied", *slot)),
+            Request::Promote { slot, .. } => Some(("slot_saved", *slot)),
+            Request::List
+            | Request::Get { .. }
+            | Request::Status
+            | Request::Stop
+            | Request::History => None,
+        };
+        let result = self.answer(request);
+        if let (Ok(_), Some((event, slot))) = (&result, event) {
+            self.publish(slot_event(event, slot, "ipc"));
+        }
+        result
+    }
+
+    fn answer(&mut self, request: Request) -> Result<Value, String> {
+        match request {
+            Request::Save {
+                slot,
+                content: None,
+            } => {
+                let content = self.services.clipboard.read().ok_or("clipboard is empty")?;
+                answer_slots(
+                    &mut self.slots,
+                    &self.config,
+                    Request::Save {
+                        slot,
+                        content: Some(content),
+                    },
+                )
+            }
+            Request::Paste { slot } => {
+                let content = filled(&self.slots, slot)?;
+                info!("Pasted ← Slot {} (ipc)", slot.label());
+                let restore_delay = Duration::from_millis(self.config.paste.restore_delay_ms);
+                self.services.paste.paste(content, restore_delay);
+                Ok(json!({"slot": slot.slot_num(), "id": slot.label()}))
+            }
+            Request::Copy { slot } => {
+                let content = filled(&self.slots, slot)?;
+                self.services.paste.copy(content)?;
+                info!("Copied ← Slot {} (ipc)", slot.label());
+                Ok(json!({"slot": slot.slot_num(), "id": slot.label()}))
+            }
+            Request::Status => Ok(self.status()),
+            other => answer_slots(&mut self.slots, &self.config, other),
+        }
+    }
+
+    /// The agent's version, the open chooser (and whether the UI shows it) and the running UI.
+    fn status(&self) -> Value {
+        let chooser = match &self.state {
+            State::Idle => Value::Null,
+            State::SaveChooserPending { .. } => json!({"mode": "save", "ui": self.chooser_on_ui}),
+            State::PasteChooserActive { .. } => json!({"mode": "paste", "ui": self.chooser_on_ui}),
+        };
+        let now = self.services.clock.now();
+        let ui = self.ui.as_ref().filter(|_| self.ui_present()).map(|ui| {
+            json!({
+                "client": ui.client,
+                "transport": ui.transport,
+                "last_seen_ms": now.saturating_duration_since(ui.at).as_millis() as u64,
+            })
+        });
+        json!({"agent": env!("CARGO_PKG_VERSION"), "chooser": chooser, "ui": ui})
+    }
+}
+
+/// Answers the requests that only touch slot storage. The others need the agent's clipboard, so
+/// without a running agent (`clip-agent request` on the DB) they fail.
+pub fn answer_slots(
+    slots: &mut SlotStorage,
+    config: &Config,
+    request: Request,
+) -> Result<Value, String> {
+    match request {
+        Request::List => {
+            let filled = slots.list()?;
+            let mut out = Vec::new();
+            for slot in SlotId::ALL {
+                let meta = filled.iter().find(|m| m.slot == slot);
+                let preview = match meta {
+                    Some(_) => slots.get(slot)?.and_then(|p| p.text().map(preview_for_log)),
+                    None => None,
+                };
+                out.push(json!({
+                    "slot": slot.slot_num(),
+                    "id": slot.label(),
+                    "label": config.slots.labels.get(slot.slot_num() as usize - 1),
+                    "filled": meta.is_some(),
+                    "size": meta.map(|m| m.size).unwrap_or(0),
+                    "types": meta.map(|m| m.types.clone()).unwrap_or_default(),
+                    "updated_at": meta.map(|m| m.updated_at),
+                    "preview": preview,
+                    "transform": slots.transform(slot).as_str(),
+                }));
+            }
+            Ok(Value::Array(out))
+        }
+        Request::Get { slot } => Ok(match (slots.get(slot)?, slots.metadata(slot)?) {
+            (Some(content), Some(meta)) => json!({
+                "slot": slot.slot_num(),
+                "id": slot.label(),
+                "types": content.types(),
+                "size": meta.size,
+                "updated_at": meta.updated_at,
+                "text": content.text(),
+                // The rest, so a slot without text does not read as empty.
+                "representations": content
+                    .representations
+                    .iter()
+                    .filter(|r| r.uti != crate::payload::UTI_PLAIN_TEXT)
+                    .map(|r| json!({"item": r.item, "type": r.uti, "size": r.data.len()}))
+                    .collect::<Vec<_>>(),
+            }),
+            _ => Value::Null,
+        }),
+        Request::Save {
+            slot,
+            content: Some(content),
+        } => {
+            slots.save(slot, &content)?;
+            info!(
+                "Saved → Slot {} (ipc): {}",
+                slot.label(),
+                describe_for_log(&content)
+            );
+            Ok(json!({"slot": slot.slot_num(), "id": slot.label(), "size": content.size()}))
+        }
+        Request::Clear { slot } => {
+            slots.clear(slot)?;
+            info!("Cleared Slot {} (ipc)", slot.label());
+            Ok(json!({"slot": slot.slot_num(), "id": slot.label()}))
+        }
+        Request::History => {
+            let entries = slots.list_history(config.history.retention)?;
+            Ok(entries
+                .iter()
+                .map(|e| {
+                    json!({
+                        "entry": e.id,
+                        "created_at": e.created_at,
+                        "source": e.source,
+                        "size": e.content.len(),
+                        "preview": preview_for_log(&e.content),
+                    })
+                })
+                .collect())
+        }
+        Request::Promote { entry, slot } => {
+            slots.promote_history(entry, slot)?;
+            Ok(json!({"slot": slot.slot_num(), "id": slot.label(), "entry": entry}))
+        }
+        Request::Save { content: None, .. } => {
+            Err("saving the clipboard needs a running agent".to_string())
+        }
+        Request::Paste { .. } => Err("pasting needs a running agent".to_string()),
+        Request::Copy { .. } => Err("copying to the clipboard needs a running agent".to_string()),
+        // Answered here only when no agent runs.
+        Request::Status => Ok(json!({"agent": null, "chooser": null, "ui": null})),
+        Request::Stop => Ok(json!({"stopping": false})),
+    }
+}
+
+/// A slot's content, or an error if it is empty.
+fn filled(slots: &SlotStorage, slot: SlotId) -> Result<crate::payload::ClipPayload, String> {
+    slots
+        .get(slot)?
+        .filter(|c| !c.is_empty())
+        .ok_or_else(|| format!("slot {} is empty", slot.label()))
+}
@@ -0,0 +1,884 @@
+//! Scenario tests for the chooser flows in the README's "Behavior" section: key and UI events go
+//! into the state machine, time moves on a virtual clock, and the fake UI, clipboard and paste
+//! sinks record what the agent did.
+
+use super::*;
+use crate::keys::keycode_for_name;
+use crate::platform::fake::{FakeClipboard, RecordingPaste, RecordingUi, UiCall};
+use crate::timer::ManualClock;
+use clip_config::hotkey::Hotkey;
+use std::sync::mpsc;
+
+struct Scenario {
+    machine: StateMachine,
+    mode: Arc<AtomicU8>,
+    clock: ManualClock,
+    ui: RecordingUi,
+    clipboard: FakeClipboard,
+    paste: RecordingPaste,
+}
+
+impl Scenario {
+    /// A machine that has heard from a running UI.
+    fn new() -> Self {
+        let mut s = Self::without_ui();
+        s.send(ui_seen());
+        s
+    }
+
+    fn without_ui() -> Self {
+        let clock = ManualClock::new();
+        let ui = RecordingUi::default();
+        let clipboard = FakeClipboard::default();
+        let paste = RecordingPaste::default();
+        let mode = Arc::new(AtomicU8::new(MODE_IDLE));
+        let services = Services {
+            clock: Box::new(clock.clone()),
+            ui: Box::new(ui.clone()),
+            clipboard: Box::new(clipboard.clone()),
+            paste: Box::new(paste.clone()),
+        };
+        let machine = StateMachine::new(
+            mode.clone(),
+            Box::new(MemoryStore::new()),
+            Config::default(),
+            services,
+        );
+        Self {
+            machine,
+            mode,
+            clock,
+            ui,
+            clipboard,
+            paste,
+        }
+    }
+
+    fn send(&mut self, event: Event) {
+        assert!(self.machine.handle(event));
+    }
+
+    /// Key-down for a binding string, as the event tap would report it.
+    fn key(&mut self, binding: &str) {
+        let (key, modifiers) = key_event(binding);
+        self.send(Event::KeyDown(key, modifiers));
+    }
+
+    /// Whether the event tap would hide this key from the focused app right now.
+    fn swallows(&self, binding: &str) -> bool {
+        let (key, modifiers) = key_event(binding);
+        self.machine
+            .keymap
+            .action(self.mode(), key, modifiers)
+            .map(KeyAction::swallows)
+            .unwrap_or(false)
+    }
+
+    fn advance(&mut self, ms: u64) {
+        self.clock.advance(Duration::from_millis(ms));
+        self.machine.fire_due_timers();
+    }
+
+    fn mode(&self) -> u8 {
+        self.mode.load(Ordering::Acquire)
+    }
+
+    fn token(&self) -> String {
+        match &self.machine.state {
+            State::SaveChooserPending { token, .. } | State::PasteChooserActive { token, .. } => {
+                token.clone()
+            }
+            State::Idle => panic!("no chooser open"),
+        }
+    }
+
+    fn fill(&mut self, slot: SlotId, text: &str) {
+        self.machine
+            .slots
+            .save(slot, &ClipPayload::from_text(text))
+            .unwrap();
+    }
+
+    fn slot_text(&self, slot: SlotId) -> Option<String> {
+        let payload = self.machine.slots.get(slot).unwrap()?;
+        payload.text().map(str::to_string)
+    }
+
+    fn history(&self) -> Vec<String> {
+        self.machine
+            .slots
+            .list_history(10)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.content)
+            .collect()
+    }
+
+    /// Opens a chooser with its trigger and returns the token the UI was shown.
+    fn open(&mut self, trigger: &str, mode: &str) -> String {
+        self.key(trigger);
+        let token = self.token();
+        let keys = chooser_keys(&self.machine.config.keys, mode);
+        assert_eq!(
+            self.ui.take(),
+            vec![UiCall::Show {
+                mode: mode.to_string(),
+                token: token.clone(),
+                timeout_ms: 800,
+                keys
+            }]
+        );
+        token
+    }
+}
+
+fn key_event(binding: &str) -> (Key, Modifiers) {
+    let hotkey = Hotkey::parse(binding).unwrap();
+    let key = Key(keycode_for_name(&hotkey.key).unwrap());
+    let modifiers = hotkey
+        .modifiers
+        .iter()
+        .map(|&m| Modifiers::from(m))
+        .collect();
+    (key, modifiers)
+}
+
+fn ui_seen() -> Event {
+    Event::UiSeen {
+        client: "clip-ui 0.1.0".into(),
+        transport: "unix",
+    }
+}
+
+fn hide(token: &str) -> Vec<UiCall> {
+    vec![UiCall::Hide {
+        token: token.to_string(),
+    }]
+}
+
+// Save flow
+
+#[test]
+fn save_trigger_opens_save_chooser_and_passes_through() {
+    let mut s = Scenario::new();
+    assert!(!s.swallows("cmd+c"));
+    s.open("cmd+c", "save");
+    assert_eq!(s.mode(), MODE_SAVE_PENDING);
+}
+
+#[test]
+fn option_digit_saves_clipboard_to_slot() {
+    let mut s = Scenario::new();
+    s.clipboard.set_text("hello");
+    let token = s.open("cmd+c", "save");
+    assert!(s.swallows("option+1"));
+    s.key("option+1");
+    assert_eq!(s.slot_text(SlotId::J).as_deref(), Some("hello"));
+    assert_eq!(s.ui.take(), hide(&token));
+    assert_eq!(s.mode(), MODE_IDLE);
+}
+
+#[test]
+fn every_slot_number_maps_to_its_slot() {
+    let mut s = Scenario::new();
+    for (n, slot) in (1..=6).zip(SlotId::ALL) {
+        s.clipboard.set_text(slot.label());
+        let token = s.open("cmd+c", "save");
+        s.key(&format!("option+{}", n));
+        assert_eq!(s.slot_text(slot).as_deref(), Some(slot.label()));
+        assert_eq!(s.ui.take(), hide(&token));
+    }
+}
+
+#[test]
+fn ui_click_saves_clipboard_to_slot() {
+    let mut s = Scenario::new();
+    s.clipboard.set_text("clicked");
+    let token = s.open("cmd+c", "save");
+    s.send(Event::ChooserChosen {
+        token: token.clone(),
+        slot_num: 3,
+    });
+    assert_eq!(s.slot_text(SlotId::L).as_deref(), Some("clicked"));
+    assert_eq!(s.ui.take(), hide(&token));
+    assert_eq!(s.mode(), MODE_IDLE);
+}
+
+#[test]
+fn save_chooser_times_out_without_side_effects() {
+    let mut s = Scenario::new();
+    s.clipboard.set_text("unsaved");
+    let token = s.open("cmd+c", "save");
+    s.advance(799);
+    assert_eq!(s.mode(), MODE_SAVE_PENDING);
+    s.advance(1);
+    assert_eq!(s.mode(), MODE_IDLE);
+    assert_eq!(s.ui.take(), hide(&token));
+    assert!(s.machine.slots.list().unwrap().is_empty());
+    assert!(s.paste.texts().is_empty());
+}
+
+#[test]
+fn plain_digit_does_not_pick_in_save_chooser() {
+    let mut s = Scenario::new();
+    s.clipboard.set_text("x");
+    s.open("cmd+c", "save");
+    assert!(!s.swallows("1"));
+    s.key("1");
+    assert_eq!(s.mode(), MODE_SAVE_PENDING);
+    assert!(s.machine.slots.list().unwrap().is_empty());
+}
+
+#[test]
+fn saving_an_empty_clipboard_saves_nothing() {
+    let mut s = Scenario::new();
+    s.clipboard.clear();
+    let token = s.open("cmd+c", "save");
+    s.key("option+2");
+    assert_eq!(s.slot_text(SlotId::K), None);
+    assert_eq!(s.ui.take(), hide(&token));
+}
+
+#[test]
+fn escape_cancels_save_chooser() {
+    let mut s = Scenario::new();
+    s.clipboard.set_text("x");
+    let token = s.open("cmd+c", "save");
+    s.key("escape");
+    assert_eq!(s.mode(), MODE_IDLE);
+    assert_eq!(s.ui.take(), hide(&token));
+    assert!(s.machine.slots.list().unwrap().is_empty());
+}
+
+// Paste flow
+
+#[test]
+fn paste_trigger_opens_paste_chooser_and_is_swallowed() {
+    let mut s = Scenario::new();
+    assert!(s.swallows("cmd+option+v"));
+    s.open("cmd+option+v", "paste");
+    assert_eq!(s.mode(), MODE_PASTE_ACTIVE);
+}
+
+#[test]
+fn digit_pastes_slot() {
+    let mut s = Scenario::new();
+    s.fill(SlotId::K, "from k");
+    let token = s.open("cmd+option+v", "paste");
+    assert!(s.swallows("2"));
+    s.key("2");
+    assert_eq!(s.paste.texts(), vec!["from k"]);
+    assert_eq!(s.ui.take(), hide(&token));
+    assert_eq!(s.mode(), MODE_IDLE);
+}
+
+#[test]
+fn the_ui_is_shown_the_chooser_keys_in_effect() {
+    let mut s = Scenario::new();
+    let digits = || ["1", "2", "3", "4", "5", "6"].map(String::from).to_vec();
+    s.key("cmd+option+v");
+    let shown = s.ui.take();
+    assert!(
+        matches!(&shown[..], [UiCall::Show { keys, .. }] if keys.slots == digits() && keys.cancel == "escape"),
+        "{:?}",
+        shown
+    );
+    s.key("escape");
+
+    let mut config = Config::default();
+    config.keys.paste_slots = ["j", "k", "l", "u", "i", "o"]
+        .map(|k| Hotkey::parse(k).unwrap())
+        .to_vec();
+    config.keys.cancel = Hotkey::parse("esc").unwrap();
+    s.send(Event::ConfigReloaded(Box::new(config)));
+    s.ui.take();
+    s.key("cmd+option+v");
+    match &s.ui.take()[..] {
+        [UiCall::Show { keys, .. }] => assert_eq!(keys.slots, ["j", "k", "l", "u", "i", "o"]),
+        other => panic!("expected a show, got {:?}", other),
+    }
+    s.key("escape");
+    s.ui.take();
+    s.key("cmd+c");
+    match &s.ui.take()[..] {
+        [UiCall::Show { keys, .. }] => assert_eq!(keys.slots[0], "option+1"),
+        other => panic!("expected a show, got {:?}", other),
+    }
+}
+
+#[test]
+fn ui_click_pastes_slot() {
+    let mut s = Scenario::new();
+    s.fill(SlotId::O, "from o");
+    let token = s.open("cmd+option+v", "paste");
+    s.send(Event::ChooserChosen { token, slot_num: 6 });
+    assert_eq!(s.paste.texts(), vec!["from o"]);
+}
+
+#[test]
+fn pasting_an_empty_slot_pastes_nothing() {
+    let mut s = Scenario::new();
+    let token = s.open("cmd+option+v", "paste");
+    s.key("1");
+    assert!(s.paste.texts().is_empty());
+    assert_eq!(s.ui.take(), hide(&token));
+    assert_eq!(s.mode(), MODE_IDLE);
+}
+
+#[test]
+fn escape_cancels_paste_chooser() {
+    let mut s = Scenario::new();
+    s.fill(SlotId::J, "j");
+    let token = s.open("cmd+option+v", "paste");
+    assert!(s.swallows("escape"));
+    s.key("escape");
+    assert!(s.paste.texts().is_empty());
+    assert_eq!(s.ui.take(), hide(&token));
+    assert_eq!(s.mode(), MODE_IDLE);
+}
+
+#[test]
+fn paste_chooser_times_out() {
+    let mut s = Scenario::new();
+    s.fill(SlotId::J, "j");
+    let token = s.open("cmd+option+v", "paste");
+    s.advance(800);
+    assert_eq!(s.mode(), MODE_IDLE);
+    assert_eq!(s.ui.take(), hide(&token));
+    // The key no longer picks once the chooser is gone.
+    s.key("1");
+    assert!(s.paste.texts().is_empty());
+}
+
+#[test]
+fn option_digit_does_not_pick_in_paste_chooser() {
+    let mut s = Scenario::new();
+    s.fill(SlotId::J, "j");
+    s.open("cmd+option+v", "paste");
+    s.key("option+1");
+    assert_eq!(s.mode(), MODE_PASTE_ACTIVE);
+    assert!(s.paste.texts().is_empty());
+}
+
+#[test]
+fn cmd_v_is_never_touched() {
+    let mut s = Scenario::new();
+    assert!(!s.swallows("cmd+v"));
+    s.key("cmd+v");
+    assert_eq!(s.mode(), MODE_IDLE);
+    assert!(s.ui.take().is_empty());
+    s.open("cmd+option+v", "paste");
+    assert!(!s.swallows("cmd+v"));
+    s.key("cmd+v");
+    assert_eq!(s.mode(), MODE_PASTE_ACTIVE);
+}
+
+// Tokens and overlapping input
+
+#[test]
+fn stale_token_is_ignored() {
+    let mut s = Scenario::new();
+    s.fill(SlotId::J, "j");
+    let first = s.open("cmd+option+v", "paste");
+    s.key("escape");
+    s.ui.take();
+    let second = s.open("cmd+option+v", "paste");
+    assert_ne!(first, second);
+
+    s.send(Event::ChooserCancel {
+        token: first.clone(),
+        reason: "timeout".to_string(),
+    });
+    s.send(Event::ChooserChosen {
+        token: first,
+        slot_num: 1,
+    });
+    assert_eq!(s.mode(), MODE_PASTE_ACTIVE);
+    assert!(s.paste.texts().is_empty());
+    assert!(s.ui.take().is_empty());
+
+    s.send(Event::ChooserChosen {
+        token: second,
+        slot_num: 1,
+    });
+    assert_eq!(s.paste.texts(), vec!["j"]);
+}
+
+#[test]
+fn out_of_range_slot_closes_without_action() {
+    let mut s = Scenario::new();
+    s.fill(SlotId::J, "j");
+    let token = s.open("cmd+option+v", "paste");
+    s.send(Event::ChooserChosen {
+        token: token.clone(),
+        slot_num: 9,
+    });
+    assert!(s.paste.texts().is_empty());
+    assert_eq!(s.ui.take(), hide(&token));
+}
+
+#[test]
+fn save_trigger_while_chooser_open_keeps_chooser_and_records_history() {
+    let mut s = Scenario::new();
+    let token = s.open("cmd+option+v", "paste");
+    s.clipboard.set_text("copied meanwhile");
+    s.key("cmd+c");
+    assert_eq!(s.mode(), MODE_PASTE_ACTIVE);
+    assert_eq!(s.token(), token);
+    assert!(s.ui.take().is_empty());
+    s.advance(HISTORY_CAPTURE_DELAY_MS);
+    assert_eq!(s.history(), vec!["copied meanwhile"]);
+}
+
+#[test]
+fn paste_trigger_while_save_chooser_open_is_ignored() {
+    let mut s = Scenario::new();
+    let token = s.open("cmd+c", "save");
+    s.key("cmd+option+v");
+    assert_eq!(s.mode(), MODE_SAVE_PENDING);
+    assert_eq!(s.token(), token);
+    assert!(s.ui.take().is_empty());
+}
+
+#[test]
+fn history_is_read_after_the_copy_lands() {
+    let mut s = Scenario::new();
+    s.clipboard.set_text("first");
+    s.key("cmd+c");
+    s.advance(HISTORY_CAPTURE_DELAY_MS - 1);
+    assert!(s.history().is_empty());
+    s.advance(1);
+    assert_eq!(s.history(), vec!["first"]);
+}
+
+// IPC requests
+
+impl Scenario {
+    fn request(
+        &mut self,
+        request: crate::ipc::request::Request,
+    ) -> Result<serde_json::Value, String> {
+        let (tx, rx) = mpsc::channel();
+        self.send(Event::Request(request, tx));
+        rx.try_recv().expect("request answered synchronously")
+    }
+}
+
+#[test]
+fn requests_save_get_and_clear_slots() {
+    use crate::ipc::request::Request;
+    let mut s = Scenario::new();
+    let content = Some(ClipPayload::from_text("hello"));
+    let saved = s
+        .request(Request::Save {
+            slot: SlotId::K,
+            content,
+        })
+        .unwrap();
+    assert_eq!(saved, serde_json::json!({"slot": 2, "id": "K", "size": 5}));
+    assert_eq!(s.slot_text(SlotId::K).as_deref(), Some("hello"));
+
+    let got = s.request(Request::Get { slot: SlotId::K }).unwrap();
+    assert_eq!(got["text"], "hello");
+    let list = s.request(Request::List).unwrap();
+    let filled: Vec<_> = list
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["filled"].as_bool().unwrap())
+        .collect();
+    assert_eq!(filled, [false, true, false, false, false, false]);
+    assert_eq!(list[1]["preview"], "hello");
+
+    assert_eq!(got["representations"], serde_json::json!([]));
+
+    s.request(Request::Clear { slot: SlotId::K }).unwrap();
+    assert_eq!(
+        s.request(Request::Get { slot: SlotId::K }),
+        Ok(serde_json::Value::Null)
+    );
+
+    let image = crate::payload::Representation {
+        item: 0,
+        uti: "image/png".to_string(),
+        data: vec![0x89, b'P'],
+    };
+    s.request(Request::Save {
+        slot: SlotId::U,
+        content: Some(ClipPayload {
+            representations: vec![image],
+        }),
+    })
+    .unwrap();
+    let got = s.request(Request::Get { slot: SlotId::U }).unwrap();
+    assert_eq!(got["text"], serde_json::Value::Null);
+    assert_eq!(
+        got["representations"],
+        serde_json::json!([{"item": 0, "type": "image/png", "size": 2}])
+    );
+    assert!(s.ui.take().is_empty());
+}
+
+#[test]
+fn request_save_without_text_reads_the_clipboard() {
+    use crate::ipc::request::Request;
+    let mut s = Scenario::new();
+    let save = Request::Save {
+        slot: SlotId::J,
+        content: None,
+    };
+    assert_eq!(
+        s.request(save.clone()),
+        Err("clipboard is empty".to_string())
+    );
+    s.clipboard.set_text("copied");
+    s.request(save).unwrap();
+    assert_eq!(s.slot_text(SlotId::J).as_deref(), Some("copied"));
+}
+
+#[test]
+fn slots_listed_in_slots_trim_save_trimmed_text() {
+    use crate::ipc::request::Request;
+    let mut s = Scenario::new();
+    let mut config = Config::default();
+    config.slots.trim = vec!["J".to_string()];
+    s.send(Event::ConfigReloaded(Box::new(config)));
+    for slot in [SlotId::J, SlotId::K] {
+        s.request(Request::Save {
+            slot,
+            content: Some(ClipPayload::from_text("  ls -la\n")),
+        })
+        .unwrap();
+    }
+    assert_eq!(s.slot_text(SlotId::J).as_deref(), Some("ls -la"));
+    assert_eq!(s.slot_text(SlotId::K).as_deref(), Some("  ls -la\n"));
+    assert_eq!(s.request(Request::List).unwrap()[0]["transform"], "trim");
+
+    s.send(Event::ConfigReloaded(Box::default()));
+    s.clipboard.set_text("\tcell\n");
+    s.request(Request::Save {
+        slot: SlotId::J,
+        content: None,
+    })
+    .unwrap();
+    assert_eq!(s.slot_text(SlotId::J).as_deref(), Some("\tcell\n"));
+}
+
+#[test]
+fn requests_list_history_and_promote_an_entry() {
+    use crate::ipc::request::Request;
+    let mut s = Scenario::new();
+    let first = s
+        .machine
+        .slots
+        .record_history("echo one\n", "cmd+c")
+        .unwrap()
+        .unwrap();
+    s.machine.slots.record_history("two", "cmd+c").unwrap();
+    let history = s.request(Request::History).unwrap();
+    let previews: Vec<_> = history
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["preview"].as_str().unwrap())
+        .collect();
+    assert_eq!(previews, ["two", "echo one"]);
+    assert_eq!(history[1]["size"], 9);
+    assert!(
+        history[1].get("text").is_none(),
+        "entries carry previews, not their full text"
+    );
+    assert_eq!(history[1]["entry"], first);
+
+    let (tx, events) = mpsc::channel();
+    s.send(Event::Subscribe(tx));
+    s.request(Request::Promote {
+        entry: first,
+        slot: SlotId::L,
+    })
+    .unwrap();
+    assert_eq!(s.slot_text(SlotId::L).as_deref(), Some("echo one\n"));
+    assert_eq!(events.try_recv().unwrap()["event"], "slot_saved");
+    let missing = s.request(Request::Promote {
+        entry: 999,
+        slot: SlotId::L,
+    });
+    assert_eq!(missing, Err("history entry 999 not found".to_string()));
+}
+
+#[test]
+fn request_paste_uses_the_paste_sink_and_keeps_the_chooser() {
+    use crate::ipc::request::Request;
+    let mut s = Scenario::new();
+    assert_eq!(
+        s.request(Request::Paste { slot: SlotId::O }),
+        Err("slot O is empty".to_string())
+    );
+    s.fill(SlotId::O, "sig");
+    let token = s.open("cmd+option+v", "paste");
+    s.request(Request::Paste { slot: SlotId::O }).unwrap();
+    s.request(Request::Copy { slot: SlotId::O }).unwrap();
+    assert_eq!(s.paste.texts(), vec!["sig"]);
+    assert_eq!(s.paste.copied_texts(), vec!["sig"]);
+    assert_eq!(s.mode(), MODE_PASTE_ACTIVE);
+    assert_eq!(s.token(), token);
+}
+
+#[test]
+fn stop_request_closes_the_chooser_replies_and_stops() {
+    use crate::ipc::request::Request;
+    let mut s = Scenario::new();
+    let (tx, events) = mpsc::channel();
+    s.send(Event::Subscribe(tx));
+    let token = s.open("cmd+option+v", "paste");
+    events.try_iter().for_each(drop);
+
+    let (reply, answer) = mpsc::channel();
+    assert!(
+        !s.machine.handle(Event::Request(Request::Stop, reply)),
+        "the loop ends"
+    );
+    assert_eq!(answer.try_recv().unwrap(), Ok(json!({"stopping": true})));
+    assert_eq!(s.ui.take(), hide(&token));
+    assert_eq!(s.mode(), MODE_IDLE);
+    assert_eq!(
+        events.try_recv().unwrap(),
+        json!({"event": "chooser_closed", "mode": "paste", "reason": "stopped"})
+    );
+}
+
+#[test]
+fn slot_answers_without_an_agent_refuse_clipboard_requests() {
+    use crate::ipc::request::Request;
+    let mut slots = SlotStorage::in_memory();
+    let config = Config::default();
+    let content = Some(ClipPayload::from_text("x"));
+    answer_slots(
+        &mut slots,
+        &config,
+        Request::Save {
+            slot: SlotId::J,
+            content,
+        },
+    )
+    .unwrap();
+    let list = answer_slots(&mut slots, &config, Request::List).unwrap();
+    assert_eq!(list[0]["filled"], true);
+    for request in [
+        Request::Save {
+            slot: SlotId::J,
+            content: None,
+        },
+        Request::Paste { slot: SlotId::J },
+        Request::Copy { slot: SlotId::J },
+    ] {
+        let err = answer_slots(&mut slots, &config, request).unwrap_err();
+        assert!(err.contains("needs a running agent"), "{}", err);
+    }
+    let status = answer_slots(&mut slots, &config, Request::Status).unwrap();
+    assert_eq!(status, json!({"agent": null, "chooser": null, "ui": null}));
+    assert_eq!(
+        answer_slots(&mut slots, &config, Request::Stop),
+        Ok(json!({"stopping": false}))
+    );
+}
+
+#[test]
+fn subscribers_see_chooser_and_slot_events() {
+    let mut s = Scenario::new();
+    let (tx, events) = mpsc::channel();
+    s.send(Event::Subscribe(tx));
+    s.clipboard.set_text("copied");
+    let token = s.open("cmd+c", "save");
+    s.send(Event::ChooserChosen {
+        token: token.clone(),
+        slot_num: 1,
+    });
+    assert_eq!(s.ui.take(), hide(&token));
+    s.open("cmd+option+v", "paste");
+    s.advance(800);
+
+    let names: Vec<String> = events
+        .try_iter()
+        .map(|e| e["event"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "chooser_opened",
+            "slot_saved",
+            "chooser_closed",
+            "chooser_opened",
+            "chooser_closed"
+        ]
+    );
+    drop(events);
+    s.key("cmd+c");
+    assert!(
+        s.machine.subscribers.is_empty(),
+        "closed subscribers are dropped"
+    );
+}
+
+#[test]
+fn unreachable_ui_is_reported_for_the_open_chooser_only() {
+    let mut s = Scenario::new();
+    let (tx, events) = mpsc::channel();
+    s.send(Event::Subscribe(tx));
+    let token = s.open("cmd+option+v", "paste");
+    s.send(Event::UiUnreachable {
+        token: "stale".into(),
+        error: "no ack after 3 attempts".into(),
+    });
+    s.send(Event::UiUnreachable {
+        token: token.clone(),
+        error: "no ack after 3 attempts".into(),
+    });
+
+    // Slot keys still work without the UI.
+    s.fill(SlotId::J, "j");
+    s.key("1");
+    assert_eq!(s.paste.texts(), ["j"]);
+    let events: Vec<Value> = events.try_iter().collect();
+    assert_eq!(
+        events[1],
+        json!({"event": "ui_unreachable", "mode": "paste", "error": "no ack after 3 attempts"})
+    );
+    assert_eq!(
+        events
+            .iter()
+            .filter(|e| e["event"] == "ui_unreachable")
+            .count(),
+        1
+    );
+    assert!(
+        s.ui.take().is_empty(),
+        "no hide for a UI that missed the show"
+    );
+
+    // Keyboard-only until the UI is heard from again.
+    s.key("cmd+option+v");
+    assert!(s.ui.take().is_empty());
+    s.key("escape");
+    s.send(ui_seen());
+    s.open("cmd+option+v", "paste");
+}
+
+// No UI running
+
+#[test]
+fn without_a_ui_choosers_are_keyboard_only() {
+    let mut s = Scenario::without_ui();
+    let (tx, events) = mpsc::channel();
+    s.send(Event::Subscribe(tx));
+    s.clipboard.set_text("hello");
+    s.key("cmd+c");
+    assert_eq!(s.mode(), MODE_SAVE_PENDING);
+    assert!(s.swallows("option+2"));
+    s.key("option+2");
+    assert_eq!(s.slot_text(SlotId::K).as_deref(), Some("hello"));
+
+    s.key("cmd+option+v");
+    s.advance(800);
+    assert_eq!(s.mode(), MODE_IDLE);
+    assert!(
+        s.ui.take().is_empty(),
+        "nothing is sent to a UI that is not running"
+    );
+    let opened: Vec<Value> = events
+        .try_iter()
+        .filter(|e| e["event"] == "chooser_opened")
+        .collect();
+    assert_eq!(
+        opened[0],
+        json!({"event": "chooser_opened", "mode": "save", "timeout_ms": 800, "ui": false})
+    );
+}
+
+#[test]
+fn ui_presence_follows_heartbeats_and_disconnects() {
+    use crate::ipc::request::Request;
+    let mut s = Scenario::new();
+    let status = s.request(Request::Status).unwrap();
+    assert_eq!(
+        status["ui"],
+        json!({"client": "clip-ui 0.1.0", "transport": "unix", "last_seen_ms": 0})
+    );
+    assert_eq!(status["chooser"], Value::Null);
+
+    // Heartbeats keep it present; missing them for PRESENCE_TIMEOUT does not.
+    s.advance(PRESENCE_TIMEOUT.as_millis() as u64 - 1);
+    s.send(ui_seen());
+    s.advance(PRESENCE_TIMEOUT.as_millis() as u64 - 1);
+    let token = s.open("cmd+option+v", "paste");
+    assert_eq!(
+        s.request(Request::Status).unwrap()["chooser"],
+        json!({"mode": "paste", "ui": true})
+    );
+    s.key("escape");
+    assert_eq!(s.ui.take(), hide(&token));
+    s.advance(1);
+    s.key("cmd+option+v");
+    assert!(s.ui.take().is_empty());
+    let status = s.request(Request::Status).unwrap();
+    assert_eq!(
+        (&status["ui"], &status["chooser"]),
+        (&Value::Null, &json!({"mode": "paste", "ui": false}))
+    );
+    s.key("escape");
+
+    s.send(ui_seen());
+    s.open("cmd+c", "save");
+    s.key("escape");
+    s.ui.take();
+    s.send(Event::UiGone);
+    s.key("cmd+c");
+    assert!(s.ui.take().is_empty());
+}
+
+// The real loop
+
+#[test]
+fn run_enforces_deadline_and_stops_on_quit() {
+    let ui = RecordingUi::default();
+    let mode = Arc::new(AtomicU8::new(MODE_IDLE));
+    let mut config = Config::default();
+    config.chooser.timeout_ms = 100;
+    let services = Services {
+        clock: Box::new(crate::timer::SystemClock),
+        ui: Box::new(ui.clone()),
+        clipboard: Box::new(FakeClipboard::default()),
+        paste: Box::new(RecordingPaste::default()),
+    };
+    let (tx, rx) = mpsc::channel();
+    let handle = {
+        let mode = mode.clone();
+        std::thread::spawn(move || run(rx, mode, Box::new(MemoryStore::new()), config, services))
+    };
+
+    tx.send(ui_seen()).unwrap();
+    tx.send(Event::PasteTrigger).unwrap();
+    let start = Instant::now();
+    while ui.calls().len() < 2 {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "deadline never fired"
+        );
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let token = match &ui.calls()[0] {
+        UiCall::Show {
+            mode,
+            token,
+            timeout_ms: 100,
+            ..
+        } if mode == "paste" => token.clone(),
+        other => panic!("expected paste show, got {:?}", other),
+    };
+    assert_eq!(ui.take()[1..], [UiCall::Hide { token }]);
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    assert_eq!(mode.load(Ordering::Acquire), MODE_IDLE);
+
+    tx.send(Event::Quit).unwrap();
+    handle.join().unwrap();
+}
@@ -0,0 +1,128 @@
+//! Clock and timer queue for the state machine. The agent runs on `SystemClock`; tests use
+//! `ManualClock` and advance virtual time explicitly, so no test sleeps.
+
+use std::time::Instant;
+
+/// Source of the current time.
+pub trait Clock: Send {
+    fn now(&self) -> Instant;
+}
+
+/// Wall-clock time.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Virtual time that only moves when `advance` is called. Clones share the same time.
+#[cfg(test)]
+#[derive(Clone)]
+pub struct ManualClock {
+    base: Instant,
+    elapsed: std::sync::Arc<std::sync::Mutex<std::time::Duration>>,
+}
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn new() -> Self {
+        Self {
+            base: Instant::now(),
+            elapsed: Default::default(),
+        }
+    }
+
+    pub fn advance(&self, by: std::time::Duration) {
+        *self.elapsed.lock().unwrap() += by;
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.base + *self.elapsed.lock().unwrap()
+    }
+}
+
+/// Handle for cancelling a scheduled timer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerId(u64);
+
+/// Pending timers, each carrying a payload handed back when it comes due.
+pub struct Timers<T> {
+    next_id: u64,
+    pending: Vec<(Instant, TimerId, T)>,
+}
+
+impl<T> Timers<T> {
+    pub fn new() -> Self {
+        Self {
+            next_id: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn schedule(&mut self, deadline: Instant, payload: T) -> TimerId {
+        self.next_id += 1;
+        let id = TimerId(self.next_id);
+        self.pending.push((deadline, id, payload));
+        id
+    }
+
+    /// Drops a timer before it fires. Returns false if it already fired or was cancelled.
+    pub fn cancel(&mut self, id: TimerId) -> bool {
+        let before = self.pending.len();
+        self.pending.retain(|(_, t, _)| *t != id);
+        self.pending.len() != before
+    }
+
+    /// Earliest pending deadline.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pending.iter().map(|(d, _, _)| *d).min()
+    }
+
+    /// Removes and returns every timer due at `now`, earliest first (ties in scheduling order).
+    pub fn pop_due(&mut self, now: Instant) -> Vec<T> {
+        let (mut due, pending): (Vec<_>, Vec<_>) =
+            self.pending.drain(..).partition(|(d, _, _)| *d <= now);
+        self.pending = pending;
+        due.sort_by_key(|(d, id, _)| (*d, id.0));
+        due.into_iter().map(|(_, _, payload)| payload).collect()
+    }
+}
+
+impl<T> Default for Timers<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn fires_due_timers_in_order_and_skips_cancelled() {
+        let clock = ManualClock::new();
+        let at = |ms| clock.now() + Duration::from_millis(ms);
+        let mut timers = Timers::new();
+        timers.schedule(at(300), "c");
+        let b = timers.schedule(at(200), "b");
+        timers.schedule(at(100), "a");
+        timers.schedule(at(100), "a2");
+        assert_eq!(timers.next_deadline(), Some(at(100)));
+
+        assert!(timers.pop_due(clock.now()).is_empty());
+        clock.advance(Duration::from_millis(150));
+        assert_eq!(timers.pop_due(clock.now()), vec!["a", "a2"]);
+
+        assert!(timers.cancel(b));
+        assert!(!timers.cancel(b));
+        clock.advance(Duration::from_millis(1000));
+        assert_eq!(timers.pop_due(clock.now()), vec!["c"]);
+        assert_eq!(timers.next_deadline(), None);
+    }
+}
@@ -0,0 +1,270 @@
+//! Runs a headless agent in a scratch HOME and drives its JSON-RPC control socket the way a
+//! third-party tool would.
+#![cfg(unix)]
+
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+struct Agent {
+    child: Child,
+    socket: PathBuf,
+    home: tempfile::TempDir,
+}
+
+impl Agent {
+    fn start() -> Self {
+        let home = tempfile::tempdir().unwrap();
+        let free_port = || {
+            std::net::UdpSocket::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap()
+                .port()
+        };
+        let config = home.path().join("config.toml");
+        std::fs::write(
+            &config,
+            format!(
+                "[ipc]\nui_port = {}\nagent_port = {}\n",
+                free_port(),
+                free_port()
+            ),
+        )
+        .unwrap();
+        let runtime = home.path().join("run");
+        std::fs::create_dir(&runtime).unwrap();
+        let child = clip_agent(home.path())
+            .arg("--headless")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let socket = runtime.join("slotpaste").join("control.sock");
+        wait_for(&socket);
+        Agent {
+            child,
+            socket,
+            home,
+        }
+    }
+
+    /// `clip-agent` with `args`, for the same user as this agent.
+    fn run(&self, args: &[&str]) -> std::process::Output {
+        clip_agent(self.home.path()).args(args).output().unwrap()
+    }
+
+    fn connect(&self) -> Client {
+        let stream = UnixStream::connect(&self.socket).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        Client {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            stream,
+            next_id: 0,
+        }
+    }
+}
+
+impl Drop for Agent {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// `clip-agent` for a user whose HOME is `home`.
+fn clip_agent(home: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_clip-agent"));
+    command
+        .env("HOME", home)
+        .env("XDG_RUNTIME_DIR", home.join("run"))
+        .env("SLOTPASTE_CONFIG", home.join("config.toml"));
+    command
+}
+
+fn wait_for(socket: &Path) {
+    let start = Instant::now();
+    while UnixStream::connect(socket).is_err() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "agent never opened {}",
+            socket.display()
+        );
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+struct Client {
+    stream: UnixStream,
+    reader: BufReader<UnixStream>,
+    next_id: u64,
+}
+
+impl Client {
+    fn send(&mut self, message: &Value) {
+        writeln!(self.stream, "{}", message).unwrap();
+    }
+
+    fn read(&mut self) -> Value {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    /// The full response to `method`.
+    fn call(&mut self, method: &str, params: Value) -> Value {
+        self.next_id += 1;
+        self.send(
+            &json!({"jsonrpc": "2.0", "id": self.next_id, "method": method, "params": params}),
+        );
+        let response = self.read();
+        assert_eq!(response["id"], self.next_id);
+        response
+    }
+
+    fn result(&mut self, method: &str, params: Value) -> Value {
+        let response = self.call(method, params);
+        assert!(
+            response.get("error").is_none(),
+            "{} failed: {}",
+            method,
+            response
+        );
+        response["result"].clone()
+    }
+}
+
+#[test]
+fn slots_round_trip_over_the_control_socket() {
+    let agent = Agent::start();
+    let mut client = agent.connect();
+
+    let saved = client.result("set_slot", json!({"slot": 2, "text": "hello\nworld"}));
+    assert_eq!(saved, json!({"slot": 2, "id": "K", "size": 11}));
+    let slot = client.result("get_slot", json!({"slot": "K"}));
+    assert_eq!(slot["text"], "hello\nworld");
+    assert_eq!(slot["size"], 11);
+
+    let list = client.result("list_slots", json!({}));
+    let filled: Vec<bool> = list
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["filled"].as_bool().unwrap())
+        .collect();
+    assert_eq!(filled, [false, true, false, false, false, false]);
+
+    // Headless, paste and copy go to the agent's own clipboard, which set_slot without text saves.
+    client.result("paste_slot", json!([2]));
+    client.result("set_slot", json!({"slot": "O"}));
+    assert_eq!(
+        client.result("get_slot", json!([6]))["text"],
+        "hello\nworld"
+    );
+
+    client.result("clear_slot", json!({"slot": 2}));
+    assert_eq!(client.result("get_slot", json!({"slot": 2})), Value::Null);
+    let empty = client.call("paste_slot", json!({"slot": 2}));
+    assert_eq!(
+        empty["error"],
+        json!({"code": -32000, "message": "slot K is empty"})
+    );
+    let unknown = client.call("rename_slot", json!({}));
+    assert_eq!(unknown["error"]["code"], -32601);
+}
+
+#[test]
+fn subscribers_see_changes_made_by_other_clients() {
+    let agent = Agent::start();
+    let mut watcher = agent.connect();
+    assert_eq!(
+        watcher.result("subscribe_events", json!(null)),
+        json!({"subscribed": true})
+    );
+
+    let mut editor = agent.connect();
+    editor.result("set_slot", json!({"slot": 1, "text": "x"}));
+    editor.result("clear_slot", json!({"slot": 1}));
+
+    let events: Vec<Value> = (0..2).map(|_| watcher.read()).collect();
+    assert_eq!(
+        events,
+        [
+            json!({"jsonrpc": "2.0", "method": "event",
+                   "params": {"event": "slot_saved", "slot": 1, "id": "J", "source": "ipc"}}),
+            json!({"jsonrpc": "2.0", "method": "event",
+                   "params": {"event": "slot_cleared", "slot": 1, "id": "J", "source": "ipc"}}),
+        ]
+    );
+}
+
+#[test]
+fn a_second_agent_is_refused_and_stop_shuts_the_first_down() {
+    let mut agent = Agent::start();
+    let second = agent.run(&["--headless"]);
+    assert_eq!(
+        second.status.code(),
+        Some(3),
+        "the status `RestartPreventExitStatus=` names"
+    );
+    let pid = agent.child.id();
+    let stderr = String::from_utf8_lossy(&second.stderr);
+    assert!(
+        stderr.contains(&format!("another clip-agent is running (pid {})", pid)),
+        "{}",
+        stderr
+    );
+
+    let stop = agent.run(&["stop"]);
+    assert!(
+        stop.status.success(),
+        "{}",
+        String::from_utf8_lossy(&stop.stderr)
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&stop.stdout),
+        format!("clip-agent stopped (pid {})\n", pid)
+    );
+    assert!(agent.child.wait().unwrap().success());
+    assert!(
+        !agent.socket.exists(),
+        "the control socket is removed on the way out"
+    );
+
+    let again = agent.run(&["stop"]);
+    assert_eq!(
+        String::from_utf8_lossy(&again.stdout),
+        "clip-agent is not running\n"
+    );
+}
+
+#[test]
+fn an_agent_that_lost_its_pid_file_keeps_its_secret() {
+    let agent = Agent::start();
+    let run = agent.home.path().join("run").join("slotpaste");
+    let secret = std::fs::read(run.join("ipc.secret")).unwrap();
+    std::fs::remove_file(run.join("agent.pid")).unwrap();
+
+    let second = agent.run(&["--headless"]);
+    assert_eq!(second.status.code(), Some(3));
+    let stderr = String::from_utf8_lossy(&second.stderr);
+    assert!(stderr.contains("another clip-agent answers"), "{}", stderr);
+    assert_eq!(
+        std::fs::read(run.join("ipc.secret")).unwrap(),
+        secret,
+        "the first agent's secret is left alone"
+    );
+    assert_eq!(
+        agent
+            .connect()
+            .result("list_slots", json!({}))
+            .as_array()
+            .map(Vec::len),
+        Some(6)
+    );
+}
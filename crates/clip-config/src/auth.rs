@@ -0,0 +1,195 @@
+//! Authentication for the agent's UDP messages (chooser show/hide, chosen/cancel, triggers and
+//! slot requests).
+//!
+//! The agent generates a fresh `Secret` at start and writes it to `runtime_dir()/ipc.secret`
+//! (mode 0600), so only processes of the same user can read it. Every datagram is an envelope
+//! `{"msg":"<message JSON>","mac":"<hex HMAC-SHA256 of msg>"}`; receivers drop anything that
+//! does not verify. Chooser tokens come from `random_token` so a reply cannot be guessed either.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::path::{Path, PathBuf};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const SECRET_LEN: usize = 32;
+const SECRET_FILE: &str = "ipc.secret";
+
+/// Why an envelope was rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthError {
+    /// Not an envelope at all (for example a message from an old, unauthenticated sender).
+    Malformed,
+    /// The MAC does not match: wrong secret or a modified message.
+    BadMac,
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthError::Malformed => write!(f, "not a signed message"),
+            AuthError::BadMac => write!(f, "bad signature"),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+/// Shared key for one agent run.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret([u8; SECRET_LEN]);
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret(..)")
+    }
+}
+
+impl Secret {
+    /// A new random secret.
+    pub fn generate() -> Result<Self, String> {
+        let mut key = [0u8; SECRET_LEN];
+        getrandom::getrandom(&mut key).map_err(|e| format!("random: {}", e))?;
+        Ok(Self(key))
+    }
+
+    /// Where the running agent keeps its secret.
+    pub fn path() -> Result<PathBuf, String> {
+        Ok(crate::runtime_dir()?.join(SECRET_FILE))
+    }
+
+    /// Reads the running agent's secret.
+    pub fn load() -> Result<Self, String> {
+        Self::load_from(&Self::path()?)
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self, String> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| format!("read {}: {}", path.display(), e))?;
+        let bytes =
+            from_hex(text.trim()).ok_or_else(|| format!("{}: not a secret", path.display()))?;
+        let key = bytes
+            .try_into()
+            .map_err(|_| format!("{}: not a secret", path.display()))?;
+        Ok(Self(key))
+    }
+
+    /// Replaces the file at `path` with this secret, readable only by the current user.
+    pub fn write_to(&self, path: &Path) -> Result<(), String> {
+        let tmp = path.with_extension("tmp");
+        let _ = std::fs::remove_file(&tmp);
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options
+            .open(&tmp)
+            .map_err(|e| format!("create {}: {}", tmp.display(), e))?;
+        std::io::Write::write_all(&mut file, to_hex(&self.0).as_bytes())
+            .map_err(|e| format!("write {}: {}", tmp.display(), e))?;
+        std::fs::rename(&tmp, path).map_err(|e| format!("rename to {}: {}", path.display(), e))
+    }
+
+    /// Wraps `msg` (one JSON message) in a signed envelope.
+    pub fn sign(&self, msg: &str) -> String {
+        let envelope =
+            serde_json::json!({"msg": msg, "mac": to_hex(&self.mac(msg).finalize().into_bytes())});
+        envelope.to_string()
+    }
+
+    /// The message inside a signed envelope, if its MAC verifies.
+    pub fn open(&self, envelope: &str) -> Result<String, AuthError> {
+        let v: serde_json::Value =
+            serde_json::from_str(envelope).map_err(|_| AuthError::Malformed)?;
+        let (Some(msg), Some(mac)) = (
+            v.get("msg").and_then(|m| m.as_str()),
+            v.get("mac").and_then(|m| m.as_str()),
+        ) else {
+            return Err(AuthError::Malformed);
+        };
+        let mac = from_hex(mac).ok_or(AuthError::Malformed)?;
+        // verify_slice compares in constant time.
+        self.mac(msg)
+            .verify_slice(&mac)
+            .map_err(|_| AuthError::BadMac)?;
+        Ok(msg.to_string())
+    }
+
+    fn mac(&self, msg: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.0).expect("HMAC takes keys of any length");
+        mac.update(msg.as_bytes());
+        mac
+    }
+}
+
+/// 128 random bits as hex, for chooser tokens.
+pub fn random_token() -> Result<String, String> {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).map_err(|e| format!("random: {}", e))?;
+    Ok(to_hex(&bytes))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signed_messages_open_only_with_the_same_secret() {
+        let secret = Secret::generate().unwrap();
+        let msg = r#"{"type":"chosen","token":"a\"b","slot":1}"#;
+        let envelope = secret.sign(msg);
+        assert_eq!(secret.open(&envelope).as_deref(), Ok(msg));
+
+        let other = Secret::generate().unwrap();
+        assert_ne!(secret, other);
+        assert_eq!(other.open(&envelope), Err(AuthError::BadMac));
+
+        let tampered = envelope.replace(r#"\"slot\":1"#, r#"\"slot\":2"#);
+        assert_ne!(tampered, envelope);
+        assert_eq!(secret.open(&tampered), Err(AuthError::BadMac));
+
+        assert_eq!(secret.open(msg), Err(AuthError::Malformed));
+        assert_eq!(
+            secret.open(r#"{"msg":"{}","mac":"zz"}"#),
+            Err(AuthError::Malformed)
+        );
+        assert_eq!(secret.open("not json"), Err(AuthError::Malformed));
+    }
+
+    #[test]
+    fn secret_file_round_trips_and_is_private() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(SECRET_FILE);
+        let secret = Secret::generate().unwrap();
+        secret.write_to(&path).unwrap();
+        Secret::generate().unwrap().write_to(&path).unwrap();
+        secret.write_to(&path).unwrap();
+        assert_eq!(Secret::load_from(&path).unwrap(), secret);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        std::fs::write(&path, "abc").unwrap();
+        assert!(Secret::load_from(&path).is_err());
+
+        let (a, b) = (random_token().unwrap(), random_token().unwrap());
+        assert_eq!(a.len(), 32);
+        assert_ne!(a, b);
+    }
+}
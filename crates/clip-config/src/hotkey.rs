@@ -0,0 +1,254 @@
+//! Hotkey binding strings: `cmd+option+v`, `ctrl+shift+1`.
+//!
+//! A binding is zero or more modifiers followed by exactly one key, joined with `+`.
+//! Names are case-insensitive; modifiers have aliases (`opt`/`alt`, `control`, `command`).
+//! Keys are a letter, a digit, or one of `NAMED_KEYS` (punctuation may also be written as the
+//! character itself, e.g. `cmd+/`).
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Modifier key in a binding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Modifier {
+    Cmd,
+    Option,
+    Ctrl,
+    Shift,
+}
+
+impl Modifier {
+    fn parse(s: &str) -> Option<Modifier> {
+        match s {
+            "cmd" | "command" => Some(Modifier::Cmd),
+            "option" | "opt" | "alt" => Some(Modifier::Option),
+            "ctrl" | "control" => Some(Modifier::Ctrl),
+            "shift" => Some(Modifier::Shift),
+            _ => None,
+        }
+    }
+
+    /// Canonical name used when formatting a binding.
+    pub fn name(self) -> &'static str {
+        match self {
+            Modifier::Cmd => "cmd",
+            Modifier::Option => "option",
+            Modifier::Ctrl => "ctrl",
+            Modifier::Shift => "shift",
+        }
+    }
+}
+
+/// A parsed binding: modifiers (sorted, unique) plus one key name (lowercase).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Hotkey {
+    pub modifiers: Vec<Modifier>,
+    pub key: String,
+}
+
+impl Hotkey {
+    /// Parses a binding string such as `cmd+option+v`.
+    pub fn parse(s: &str) -> Result<Hotkey, String> {
+        let lower = s.trim().to_ascii_lowercase();
+        if lower.is_empty() {
+            return Err("empty key binding".to_string());
+        }
+        let parts: Vec<&str> = lower.split('+').map(str::trim).collect();
+        let (key, mods) = parts.split_last().expect("split yields at least one part");
+        let mut modifiers = Vec::new();
+        for m in mods {
+            let modifier = Modifier::parse(m).ok_or_else(|| {
+                if m.is_empty() {
+                    format!("{:?}: empty part in key binding", s)
+                } else {
+                    format!(
+                        "{:?}: unknown modifier {:?} (expected cmd, option, ctrl, shift)",
+                        s, m
+                    )
+                }
+            })?;
+            if modifiers.contains(&modifier) {
+                return Err(format!("{:?}: modifier {:?} repeated", s, m));
+            }
+            modifiers.push(modifier);
+        }
+        if Modifier::parse(key).is_some() {
+            return Err(format!("{:?}: binding needs a key after the modifiers", s));
+        }
+        if !is_known_key(key) {
+            return Err(format!("{:?}: unknown key {:?}", s, key));
+        }
+        modifiers.sort();
+        Ok(Hotkey {
+            modifiers,
+            key: canonical_key(key).to_string(),
+        })
+    }
+
+    pub fn has(&self, modifier: Modifier) -> bool {
+        self.modifiers.contains(&modifier)
+    }
+}
+
+/// Key names accepted besides single letters and digits, in canonical form.
+pub const NAMED_KEYS: &[&str] = &[
+    "escape",
+    "return",
+    "tab",
+    "space",
+    "delete",
+    "forward_delete",
+    "home",
+    "end",
+    "page_up",
+    "page_down",
+    "left",
+    "right",
+    "up",
+    "down",
+    "f1",
+    "f2",
+    "f3",
+    "f4",
+    "f5",
+    "f6",
+    "f7",
+    "f8",
+    "f9",
+    "f10",
+    "f11",
+    "f12",
+    "f13",
+    "f14",
+    "f15",
+    "f16",
+    "f17",
+    "f18",
+    "f19",
+    "f20",
+    "minus",
+    "equal",
+    "left_bracket",
+    "right_bracket",
+    "backslash",
+    "semicolon",
+    "quote",
+    "comma",
+    "period",
+    "slash",
+    "grave",
+];
+
+/// Alternative spellings, mapped to their canonical `NAMED_KEYS` entry.
+const KEY_ALIASES: &[(&str, &str)] = &[
+    ("esc", "escape"),
+    ("enter", "return"),
+    ("backspace", "delete"),
+    ("del", "forward_delete"),
+    ("pageup", "page_up"),
+    ("pgup", "page_up"),
+    ("pagedown", "page_down"),
+    ("pgdn", "page_down"),
+    ("-", "minus"),
+    ("=", "equal"),
+    ("[", "left_bracket"),
+    ("]", "right_bracket"),
+    ("\\", "backslash"),
+    (";", "semicolon"),
+    ("'", "quote"),
+    (",", "comma"),
+    (".", "period"),
+    ("/", "slash"),
+    ("`", "grave"),
+];
+
+fn is_known_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_alphanumeric() => true,
+        _ => NAMED_KEYS.contains(&canonical_key(key)),
+    }
+}
+
+fn canonical_key(key: &str) -> &str {
+    KEY_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == key)
+        .map(|(_, name)| *name)
+        .unwrap_or(key)
+}
+
+impl fmt::Display for Hotkey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for m in &self.modifiers {
+            write!(f, "{}+", m.name())?;
+        }
+        f.write_str(&self.key)
+    }
+}
+
+impl TryFrom<String> for Hotkey {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Hotkey::parse(&s)
+    }
+}
+
+impl From<Hotkey> for String {
+    fn from(h: Hotkey) -> String {
+        h.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_normalizes() {
+        let h = Hotkey::parse("Opt+CMD+V").unwrap();
+        assert_eq!(h.modifiers, vec![Modifier::Cmd, Modifier::Option]);
+        assert_eq!(h.key, "v");
+        assert_eq!(h.to_string(), "cmd+option+v");
+        assert_eq!(
+            Hotkey::parse("ctrl+shift+1").unwrap().to_string(),
+            "ctrl+shift+1"
+        );
+        assert_eq!(Hotkey::parse("esc").unwrap().to_string(), "escape");
+        assert_eq!(Hotkey::parse("cmd+/").unwrap().to_string(), "cmd+slash");
+        assert_eq!(
+            Hotkey::parse("Shift+PgUp").unwrap().to_string(),
+            "shift+page_up"
+        );
+        assert_eq!(Hotkey::parse("ctrl+F12").unwrap().to_string(), "ctrl+f12");
+    }
+
+    #[test]
+    fn named_keys_round_trip() {
+        for name in NAMED_KEYS {
+            assert_eq!(Hotkey::parse(name).unwrap().key, *name);
+        }
+        for (alias, name) in KEY_ALIASES {
+            assert!(NAMED_KEYS.contains(name), "{} -> {}", alias, name);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_bindings() {
+        for bad in [
+            "",
+            "cmd+",
+            "cmd+option",
+            "hyper+v",
+            "cmd+cmd+v",
+            "cmd+zz",
+            "+v",
+            "f21",
+            "cmd++",
+        ] {
+            assert!(Hotkey::parse(bad).is_err(), "{:?} should be rejected", bad);
+        }
+    }
+}
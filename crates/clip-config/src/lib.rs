@@ -0,0 +1,584 @@
+//! Slotpaste configuration, shared by the agent, the chooser UI and the CLI.
+//!
+//! Loaded from `$SLOTPASTE_CONFIG`, else `$XDG_CONFIG_HOME/slotpaste/config.toml`, else
+//! `~/.config/slotpaste/config.toml`. A missing file means all defaults; every section and
+//! field is optional. Defaults:
+//!
+//! ```toml
+//! [chooser]
+//! timeout_ms = 800          # chooser overlay lifetime, 100..=10000
+//!
+//! [ipc]
+//! transport = "unix"        # "unix": stream socket in runtime_dir(); "udp": the ports below
+//! ui_port = 45454           # UI listens here (show/hide)
+//! agent_port = 45455        # agent listens here (chosen/cancel)
+//!
+//! [paste]
+//! restore_delay_ms = 250    # wait before restoring the clipboard after a paste, 0..=5000
+//!
+//! [keys]
+//! save_trigger = "cmd+c"            # opens the save chooser; passed through so the copy happens
+//! paste_trigger = "cmd+option+v"    # opens the paste chooser; swallowed
+//! save_slots = ["option+1", "option+2", "option+3", "option+4", "option+5", "option+6"]
+//! paste_slots = ["1", "2", "3", "4", "5", "6"]
+//! cancel = "escape"                 # closes an open chooser
+//!
+//! [slots]
+//! labels = ["J", "K", "L", "U", "I", "O"]   # display labels for slots 1..6
+//! trim = []                 # slot ids (J, K, L, U, I, O) that save trimmed text
+//!
+//! [history]
+//! retention = 200           # clipboard history entries kept; 0 disables history
+//! ```
+
+pub mod auth;
+pub mod hotkey;
+
+use hotkey::Hotkey;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Environment variable overriding the config file path.
+pub const CONFIG_ENV: &str = "SLOTPASTE_CONFIG";
+
+/// Number of chooser slots.
+pub const SLOT_COUNT: usize = 6;
+
+/// Fixed slot ids, in chooser order; `slots.trim` names slots by these.
+pub const SLOT_IDS: [&str; SLOT_COUNT] = ["J", "K", "L", "U", "I", "O"];
+
+const TIMEOUT_MS_RANGE: (u64, u64) = (100, 10_000);
+const RESTORE_DELAY_MS_MAX: u64 = 5_000;
+const LABEL_MAX_CHARS: usize = 8;
+
+/// Full configuration. Unknown sections or fields are rejected so typos surface.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub chooser: ChooserConfig,
+    pub ipc: IpcConfig,
+    pub paste: PasteConfig,
+    pub keys: KeysConfig,
+    pub slots: SlotsConfig,
+    pub history: HistoryConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChooserConfig {
+    /// How long the chooser stays open before cancelling, in ms.
+    pub timeout_ms: u64,
+}
+
+impl Default for ChooserConfig {
+    fn default() -> Self {
+        Self { timeout_ms: 800 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IpcConfig {
+    /// How the agent, the UI and scripts reach each other.
+    pub transport: IpcTransport,
+    /// UDP port the UI listens on for show/hide.
+    pub ui_port: u16,
+    /// UDP port the agent listens on for chosen/cancel.
+    pub agent_port: u16,
+}
+
+impl Default for IpcConfig {
+    fn default() -> Self {
+        Self {
+            transport: IpcTransport::Unix,
+            ui_port: 45454,
+            agent_port: 45455,
+        }
+    }
+}
+
+/// IPC transport between the agent and its clients. With `Unix`, UDP is still the fallback
+/// where Unix sockets are unavailable or the agent cannot bind its socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IpcTransport {
+    /// Length-prefixed frames on the per-user stream socket `agent_socket_path()`.
+    Unix,
+    /// Datagrams on 127.0.0.1 `ui_port` / `agent_port`.
+    Udp,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PasteConfig {
+    /// Delay after the synthesized paste before the user's clipboard is restored, in ms.
+    pub restore_delay_ms: u64,
+}
+
+impl Default for PasteConfig {
+    fn default() -> Self {
+        Self {
+            restore_delay_ms: 250,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeysConfig {
+    /// Opens the save chooser; passed through to the focused app.
+    pub save_trigger: Hotkey,
+    /// Opens the paste chooser; swallowed by the agent.
+    pub paste_trigger: Hotkey,
+    /// Pick slot 1..6 while the save chooser is open; swallowed then.
+    pub save_slots: Vec<Hotkey>,
+    /// Pick slot 1..6 while the paste chooser is open; swallowed then.
+    pub paste_slots: Vec<Hotkey>,
+    /// Closes an open chooser; swallowed then.
+    pub cancel: Hotkey,
+}
+
+impl Default for KeysConfig {
+    fn default() -> Self {
+        let hotkey = |s: &str| Hotkey::parse(s).expect("valid default");
+        Self {
+            save_trigger: hotkey("cmd+c"),
+            paste_trigger: hotkey("cmd+option+v"),
+            save_slots: (1..=SLOT_COUNT)
+                .map(|n| hotkey(&format!("option+{}", n)))
+                .collect(),
+            paste_slots: (1..=SLOT_COUNT).map(|n| hotkey(&n.to_string())).collect(),
+            cancel: hotkey("escape"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SlotsConfig {
+    /// Display labels for slots 1..6.
+    pub labels: Vec<String>,
+    /// Slot ids whose saved text is trimmed; every other slot stores content byte-exact.
+    pub trim: Vec<String>,
+}
+
+impl Default for SlotsConfig {
+    fn default() -> Self {
+        Self {
+            labels: SLOT_IDS.map(String::from).to_vec(),
+            trim: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// Clipboard history entries kept; 0 disables history.
+    pub retention: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self { retention: 200 }
+    }
+}
+
+/// Why a config could not be loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The file exists but could not be read.
+    Io { path: PathBuf, message: String },
+    /// Not valid TOML, or a field has the wrong type / unknown name.
+    Parse {
+        path: Option<PathBuf>,
+        message: String,
+    },
+    /// Well-formed but a value is out of range.
+    Invalid {
+        path: Option<PathBuf>,
+        field: &'static str,
+        message: String,
+    },
+    /// No config location could be determined (HOME unset).
+    NoPath,
+}
+
+impl ConfigError {
+    fn with_path(self, p: &Path) -> Self {
+        match self {
+            ConfigError::Parse { message, .. } => ConfigError::Parse {
+                path: Some(p.to_path_buf()),
+                message,
+            },
+            ConfigError::Invalid { field, message, .. } => ConfigError::Invalid {
+                path: Some(p.to_path_buf()),
+                field,
+                message,
+            },
+            other => other,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let shown = |p: &Option<PathBuf>| {
+            p.as_ref()
+                .map(|p| p.display().to_string())
+                .unwrap_or_else(|| "config".to_string())
+        };
+        match self {
+            ConfigError::Io { path, message } => {
+                write!(f, "{}: cannot read: {}", path.display(), message)
+            }
+            ConfigError::Parse { path, message } => {
+                write!(f, "{}: {}", shown(path), message.trim_end())
+            }
+            ConfigError::Invalid {
+                path,
+                field,
+                message,
+            } => {
+                write!(f, "{}: invalid `{}`: {}", shown(path), field, message)
+            }
+            ConfigError::NoPath => write!(f, "cannot locate config: HOME not set"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Config file location (the file may not exist).
+pub fn config_path() -> Result<PathBuf, ConfigError> {
+    if let Some(p) = std::env::var_os(CONFIG_ENV) {
+        return Ok(PathBuf::from(p));
+    }
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME").ok_or(ConfigError::NoPath)?).join(".config"),
+    };
+    Ok(base.join("slotpaste").join("config.toml"))
+}
+
+/// Per-user directory for the agent's sockets and IPC secret: `$XDG_RUNTIME_DIR/slotpaste`,
+/// else `slotpaste-<uid>` in the temp dir. Created 0700; refused if another user owns it or
+/// anyone else can open it.
+#[cfg(unix)]
+pub fn runtime_dir() -> Result<PathBuf, String> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    // SAFETY: getuid has no preconditions and cannot fail.
+    let uid = unsafe { libc::getuid() };
+    let dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(base) if !base.is_empty() => PathBuf::from(base).join("slotpaste"),
+        _ => std::env::temp_dir().join(format!("slotpaste-{}", uid)),
+    };
+    match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(format!("create {}: {}", dir.display(), e)),
+    }
+    let meta = std::fs::symlink_metadata(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    if !meta.is_dir() || meta.uid() != uid || meta.mode() & 0o077 != 0 {
+        return Err(format!(
+            "{} must be a directory private to this user (mode 0700)",
+            dir.display()
+        ));
+    }
+    Ok(dir)
+}
+
+/// `slotpaste` in the temp dir, which is per-user on the platforms without Unix permissions.
+#[cfg(not(unix))]
+pub fn runtime_dir() -> Result<PathBuf, String> {
+    let dir = std::env::temp_dir().join("slotpaste");
+    std::fs::create_dir_all(&dir).map_err(|e| format!("create {}: {}", dir.display(), e))?;
+    Ok(dir)
+}
+
+/// The agent's socket for the `unix` IPC transport.
+pub fn agent_socket_path() -> Result<PathBuf, String> {
+    Ok(runtime_dir()?.join("agent.sock"))
+}
+
+impl Config {
+    /// Loads from `config_path()`; defaults if the file does not exist.
+    pub fn load() -> Result<Config, ConfigError> {
+        Self::load_from(&config_path()?)
+    }
+
+    /// Loads from `path`; defaults if the file does not exist.
+    pub fn load_from(path: &Path) -> Result<Config, ConfigError> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Self::from_toml_str(&text).map_err(|e| e.with_path(path)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(ConfigError::Io {
+                path: path.to_path_buf(),
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    /// Parses and validates TOML text.
+    pub fn from_toml_str(text: &str) -> Result<Config, ConfigError> {
+        let config: Config = toml::from_str(text).map_err(|e| ConfigError::Parse {
+            path: None,
+            message: e.to_string(),
+        })?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks ranges and cross-field rules not expressed by the types.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |field, message: String| ConfigError::Invalid {
+            path: None,
+            field,
+            message,
+        };
+
+        let (min, max) = TIMEOUT_MS_RANGE;
+        if !(min..=max).contains(&self.chooser.timeout_ms) {
+            return Err(invalid(
+                "chooser.timeout_ms",
+                format!("{} is outside {}..={}", self.chooser.timeout_ms, min, max),
+            ));
+        }
+        if self.ipc.ui_port == 0 {
+            return Err(invalid("ipc.ui_port", "must not be 0".to_string()));
+        }
+        if self.ipc.agent_port == 0 {
+            return Err(invalid("ipc.agent_port", "must not be 0".to_string()));
+        }
+        if self.ipc.ui_port == self.ipc.agent_port {
+            return Err(invalid(
+                "ipc.agent_port",
+                format!("must differ from ipc.ui_port ({})", self.ipc.ui_port),
+            ));
+        }
+        if self.paste.restore_delay_ms > RESTORE_DELAY_MS_MAX {
+            return Err(invalid(
+                "paste.restore_delay_ms",
+                format!(
+                    "{} exceeds {}",
+                    self.paste.restore_delay_ms, RESTORE_DELAY_MS_MAX
+                ),
+            ));
+        }
+        self.validate_keys()?;
+        let labels = &self.slots.labels;
+        if labels.len() != SLOT_COUNT {
+            return Err(invalid(
+                "slots.labels",
+                format!("expected {} labels, got {}", SLOT_COUNT, labels.len()),
+            ));
+        }
+        for (i, label) in labels.iter().enumerate() {
+            let chars = label.trim().chars().count();
+            if chars == 0 || chars > LABEL_MAX_CHARS {
+                return Err(invalid(
+                    "slots.labels",
+                    format!(
+                        "label {} ({:?}) must be 1..={} characters",
+                        i + 1,
+                        label,
+                        LABEL_MAX_CHARS
+                    ),
+                ));
+            }
+            if labels[..i].contains(label) {
+                return Err(invalid(
+                    "slots.labels",
+                    format!("label {:?} is used twice", label),
+                ));
+            }
+        }
+        let trim = &self.slots.trim;
+        for (i, id) in trim.iter().enumerate() {
+            if !SLOT_IDS.contains(&id.as_str()) {
+                return Err(invalid(
+                    "slots.trim",
+                    format!("{:?} is not a slot id (one of {})", id, SLOT_IDS.join(", ")),
+                ));
+            }
+            if trim[..i].contains(id) {
+                return Err(invalid("slots.trim", format!("{} is listed twice", id)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Bindings must not shadow each other within the mode where they are active, and the
+    /// triggers, which work in every mode, must not double as chooser keys.
+    fn validate_keys(&self) -> Result<(), ConfigError> {
+        let invalid = |field, message: String| ConfigError::Invalid {
+            path: None,
+            field,
+            message,
+        };
+        let keys = &self.keys;
+        if keys.save_trigger == keys.paste_trigger {
+            return Err(invalid(
+                "keys.paste_trigger",
+                format!("same as keys.save_trigger ({})", keys.save_trigger),
+            ));
+        }
+        let trigger = |key: &Hotkey| {
+            [
+                ("keys.save_trigger", &keys.save_trigger),
+                ("keys.paste_trigger", &keys.paste_trigger),
+            ]
+            .into_iter()
+            .find(|(_, t)| *t == key)
+            .map(|(name, _)| name)
+        };
+        if let Some(name) = trigger(&keys.cancel) {
+            return Err(invalid(
+                "keys.cancel",
+                format!("{} is also {}", keys.cancel, name),
+            ));
+        }
+        for (field, slots) in [
+            ("keys.save_slots", &keys.save_slots),
+            ("keys.paste_slots", &keys.paste_slots),
+        ] {
+            if slots.len() != SLOT_COUNT {
+                return Err(invalid(
+                    field,
+                    format!("expected {} bindings, got {}", SLOT_COUNT, slots.len()),
+                ));
+            }
+            for (i, key) in slots.iter().enumerate() {
+                if slots[..i].contains(key) {
+                    return Err(invalid(field, format!("{} is bound to two slots", key)));
+                }
+                if *key == keys.cancel {
+                    return Err(invalid(field, format!("{} is also keys.cancel", key)));
+                }
+                if let Some(name) = trigger(key) {
+                    return Err(invalid(field, format!("{} is also {}", key, name)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Display label for chooser slot 1..6.
+    pub fn slot_label(&self, slot_num: u8) -> Option<&str> {
+        let idx = (slot_num as usize).checked_sub(1)?;
+        self.slots.labels.get(idx).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_file_is_all_defaults() {
+        assert_eq!(Config::from_toml_str("").unwrap(), Config::default());
+        Config::default().validate().unwrap();
+    }
+
+    #[test]
+    fn module_docs_show_the_defaults() {
+        let doc: String = include_str!("lib.rs")
+            .lines()
+            .skip_while(|l| !l.starts_with("//! ```toml"))
+            .skip(1)
+            .take_while(|l| !l.starts_with("//! ```"))
+            .map(|l| l.trim_start_matches("//!").trim_start_matches(' '))
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(Config::from_toml_str(&doc).unwrap(), Config::default());
+    }
+
+    #[test]
+    fn partial_file_overrides_only_given_fields() {
+        let c = Config::from_toml_str(
+            "[chooser]\ntimeout_ms = 1500\n[keys]\npaste_trigger = \"ctrl+shift+p\"\n",
+        )
+        .unwrap();
+        assert_eq!(c.chooser.timeout_ms, 1500);
+        assert_eq!(c.keys.paste_trigger.to_string(), "ctrl+shift+p");
+        assert_eq!(c.ipc, IpcConfig::default());
+        assert_eq!(c.slot_label(1), Some("J"));
+
+        let udp = Config::from_toml_str("[ipc]\ntransport = \"udp\"\n").unwrap();
+        assert_eq!(udp.ipc.transport, IpcTransport::Udp);
+        assert!(Config::from_toml_str("[ipc]\ntransport = \"tcp\"\n").is_err());
+        assert_eq!(c.slot_label(7), None);
+    }
+
+    #[test]
+    fn rejects_out_of_range_values() {
+        let cases = [
+            ("[chooser]\ntimeout_ms = 5", "chooser.timeout_ms"),
+            ("[ipc]\nui_port = 0", "ipc.ui_port"),
+            ("[ipc]\nui_port = 45455", "ipc.agent_port"),
+            (
+                "[paste]\nrestore_delay_ms = 60000",
+                "paste.restore_delay_ms",
+            ),
+            ("[slots]\nlabels = [\"a\"]", "slots.labels"),
+            ("[keys]\npaste_trigger = \"cmd+c\"", "keys.paste_trigger"),
+            ("[keys]\npaste_slots = [\"1\"]", "keys.paste_slots"),
+            (
+                "[keys]\nsave_slots = [\"1\", \"2\", \"3\", \"4\", \"5\", \"1\"]",
+                "keys.save_slots",
+            ),
+            ("[keys]\ncancel = \"3\"", "keys.paste_slots"),
+            ("[keys]\ncancel = \"cmd+c\"", "keys.cancel"),
+            (
+                "[keys]\nsave_slots = [\"cmd+c\", \"2\", \"3\", \"4\", \"5\", \"6\"]",
+                "keys.save_slots",
+            ),
+            ("[keys]\npaste_trigger = \"6\"", "keys.paste_slots"),
+            (
+                "[slots]\nlabels = [\"a\", \"b\", \"c\", \"d\", \"e\", \"a\"]",
+                "slots.labels",
+            ),
+            (
+                "[slots]\nlabels = [\"a\", \"b\", \"c\", \"d\", \"e\", \" \"]",
+                "slots.labels",
+            ),
+            ("[slots]\ntrim = [\"1\"]", "slots.trim"),
+            ("[slots]\ntrim = [\"J\", \"J\"]", "slots.trim"),
+        ];
+        for (text, field) in cases {
+            match Config::from_toml_str(text) {
+                Err(ConfigError::Invalid { field: f, .. }) => assert_eq!(f, field, "{}", text),
+                other => panic!("{:?} -> {:?}", text, other),
+            }
+        }
+    }
+
+    #[test]
+    fn reports_parse_errors_with_location() {
+        for text in [
+            "[chooser]\ntimeout = 5",
+            "[keys]\npaste_trigger = \"cmd+hyper\"",
+            "[ipc]\nui_port = -1",
+        ] {
+            let err = Config::from_toml_str(text).unwrap_err();
+            assert!(matches!(err, ConfigError::Parse { .. }), "{:?}", err);
+            assert!(err.to_string().contains("line"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn missing_file_is_defaults_and_errors_name_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        assert_eq!(Config::load_from(&path).unwrap(), Config::default());
+        std::fs::write(&path, "[chooser]\ntimeout_ms = 1").unwrap();
+        let err = Config::load_from(&path).unwrap_err();
+        assert!(
+            err.to_string().starts_with(&path.display().to_string()),
+            "{}",
+            err
+        );
+    }
+}
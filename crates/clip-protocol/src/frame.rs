@@ -0,0 +1,82 @@
+//! Length-prefixed framing for stream transports: a 4-byte big-endian length, then that many
+//! bytes (one signed message).
+
+use std::io::{self, Read, Write};
+
+/// Largest frame accepted; well above any slot text a request carries.
+pub const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+pub fn write_frame<W: Write>(w: &mut W, payload: &[u8]) -> io::Result<()> {
+    if payload.len() > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "frame too large",
+        ));
+    }
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    // One write, so frames from threads sharing a stream behind a lock never interleave.
+    w.write_all(&frame)?;
+    w.flush()
+}
+
+/// The next frame, or None when the stream ends cleanly between frames.
+pub fn read_frame<R: Read>(r: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    let mut got = 0;
+    while got < len.len() {
+        match r.read(&mut len[got..]) {
+            Ok(0) if got == 0 => return Ok(None),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => got += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {} bytes is too large", len),
+        ));
+    }
+    let mut payload = vec![0u8; len];
+    r.read_exact(&mut payload)?;
+    Ok(Some(payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_round_trip_and_reject_garbage() {
+        let mut stream = Vec::new();
+        write_frame(&mut stream, b"{\"msg\":1}").unwrap();
+        write_frame(&mut stream, b"").unwrap();
+        assert_eq!(&stream[..4], &[0, 0, 0, 9]);
+
+        let mut r = stream.as_slice();
+        assert_eq!(read_frame(&mut r).unwrap().unwrap(), b"{\"msg\":1}");
+        assert_eq!(read_frame(&mut r).unwrap().unwrap(), b"");
+        assert_eq!(read_frame(&mut r).unwrap(), None);
+
+        let truncated = &stream[..6];
+        let mut r = truncated;
+        assert_eq!(
+            read_frame(&mut r).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+        let mut r: &[u8] = &[0, 0];
+        assert_eq!(
+            read_frame(&mut r).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+        let mut r: &[u8] = &[0xff, 0xff, 0xff, 0xff];
+        assert_eq!(
+            read_frame(&mut r).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+}
@@ -0,0 +1,624 @@
+//! Messages between the agent, the chooser UI and scripts, as they go over UDP datagrams or
+//! Unix socket [`frame`]s (inside the signed envelope from `clip_config::auth`).
+//!
+//! Every message is a JSON object tagged by `"type"` and carrying the sender's `"version"`
+//! ([`PROTOCOL_VERSION`]). Adding a message type or an optional field does not change the
+//! version; receivers ignore fields they do not know and report types they do not know. A
+//! version bump means an incompatible change, and receivers reject versions outside
+//! [`MIN_PROTOCOL_VERSION`]`..=`[`PROTOCOL_VERSION`]. Messages without a version come from
+//! senders older than the field and are read as version 1.
+//!
+//! Handshake: the UI sends `hello` when it starts; the agent answers `welcome`, or
+//! `unsupported` with the range it speaks. `unsupported` keeps its shape in every version.
+//! After that the UI sends `heartbeat` every [`HEARTBEAT_INTERVAL`]; the agent takes a UI it
+//! has not heard from for [`PRESENCE_TIMEOUT`] to be gone.
+//!
+//! Delivery: chooser messages (`show`, `hide`, `chosen`, `cancel`) carry a `msg_id`. The
+//! receiver answers each with an `ack` to the sending socket and handles an id only once
+//! ([`Dedupe`]); the sender resends until acked, [`SEND_ATTEMPTS`] times at most, waiting
+//! [`ACK_TIMEOUT`] each time. Messages without a `msg_id` are handled without an ack.
+
+pub mod frame;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Version this build speaks.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Oldest version this build still understands.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// How long a sender waits for an `ack` before sending again.
+pub const ACK_TIMEOUT: Duration = Duration::from_millis(100);
+/// How many times a sender sends a message before giving up on an `ack`.
+pub const SEND_ATTEMPTS: u32 = 3;
+/// How many recent message ids a receiver remembers.
+const DEDUPE_WINDOW: usize = 256;
+
+/// How often a running UI sends `heartbeat`.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(2);
+/// How long after its last `hello` or `heartbeat` a UI still counts as running: three missed
+/// heartbeats.
+pub const PRESENCE_TIMEOUT: Duration = Duration::from_secs(6);
+
+/// Which chooser: save into a slot or paste from one.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    Save,
+    Paste,
+}
+
+impl Mode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Mode::Save => "save",
+            Mode::Paste => "paste",
+        }
+    }
+}
+
+impl std::str::FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "save" => Ok(Mode::Save),
+            "paste" => Ok(Mode::Paste),
+            other => Err(format!(
+                "unknown mode \"{}\" (expected save or paste)",
+                other
+            )),
+        }
+    }
+}
+
+/// Where the UI places the chooser.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Anchor {
+    /// Next to the mouse pointer.
+    #[default]
+    Mouse,
+}
+
+/// Messages to the agent (`ipc.agent_port`).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ToAgent {
+    /// First message from a UI; answered with `welcome` or `unsupported`.
+    Hello { client: String },
+    /// The UI `client` is still running; no answer.
+    Heartbeat { client: String },
+    /// The user picked slot `slot` (1..6) in the chooser `token`.
+    Chosen {
+        token: String,
+        slot: u8,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        msg_id: Option<u64>,
+    },
+    /// The chooser `token` closed without a pick.
+    Cancel {
+        token: String,
+        #[serde(default = "default_cancel_reason")]
+        reason: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        msg_id: Option<u64>,
+    },
+    /// The UI got the agent's message `msg_id`.
+    Ack { msg_id: u64 },
+    /// Open a chooser, as the trigger key would (`clip-agent trigger`).
+    Trigger { action: Mode },
+    /// A slot request from a script, answered with `response` to the sender.
+    Request(SlotRequest),
+}
+
+fn default_cancel_reason() -> String {
+    "timeout".to_string()
+}
+
+impl ToAgent {
+    /// The id to acknowledge, if the sender wants an `ack`.
+    pub fn msg_id(&self) -> Option<u64> {
+        match self {
+            ToAgent::Chosen { msg_id, .. } | ToAgent::Cancel { msg_id, .. } => *msg_id,
+            _ => None,
+        }
+    }
+}
+
+/// Messages from the agent: to the UI (`ipc.ui_port`), or replies to the sender.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FromAgent {
+    Show {
+        mode: Mode,
+        token: String,
+        timeout_ms: u64,
+        #[serde(default)]
+        anchor: Anchor,
+        /// The chooser's keys; None from agents that predate them.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        keys: Option<ChooserKeys>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        msg_id: Option<u64>,
+    },
+    Hide {
+        token: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        msg_id: Option<u64>,
+    },
+    /// The agent got the UI's message `msg_id`.
+    Ack {
+        msg_id: u64,
+    },
+    /// Reply to `hello`: the agent speaks the sender's version. `agent` is its build version.
+    Welcome {
+        agent: String,
+    },
+    /// Reply to a message whose version the agent does not speak.
+    Unsupported {
+        min_version: u32,
+        max_version: u32,
+    },
+    Response(SlotResponse),
+}
+
+impl FromAgent {
+    /// The id to acknowledge, if the sender wants an `ack`.
+    pub fn msg_id(&self) -> Option<u64> {
+        match self {
+            FromAgent::Show { msg_id, .. } | FromAgent::Hide { msg_id, .. } => *msg_id,
+            _ => None,
+        }
+    }
+}
+
+/// Keys that work in the chooser being shown, as binding strings (`cmd+option+v`): `slots`
+/// pick slots 1..6 in order and `cancel` closes it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChooserKeys {
+    pub slots: Vec<String>,
+    pub cancel: String,
+}
+
+/// Message ids for one sender. They count up from the start time in microseconds, so a
+/// restarted sender does not reuse ids a receiver still remembers.
+#[derive(Debug)]
+pub struct MessageIds(AtomicU64);
+
+impl MessageIds {
+    pub fn new() -> Self {
+        let start = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(1);
+        Self(AtomicU64::new(start))
+    }
+
+    pub fn next(&self) -> u64 {
+        self.0.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+impl Default for MessageIds {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Recently handled message ids, so a resent message is acked again but handled once.
+#[derive(Debug, Default)]
+pub struct Dedupe(VecDeque<u64>);
+
+impl Dedupe {
+    /// Records `msg_id`; false if it was already seen.
+    pub fn first_time(&mut self, msg_id: u64) -> bool {
+        if self.0.contains(&msg_id) {
+            return false;
+        }
+        if self.0.len() == DEDUPE_WINDOW {
+            self.0.pop_front();
+        }
+        self.0.push_back(msg_id);
+        true
+    }
+}
+
+/// One slot operation; the agent's `ipc::request` module gives it meaning.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SlotRequest {
+    /// Echoed in the response.
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub id: Value,
+    pub op: Op,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slot: Option<SlotRef>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// History entry id, for `promote`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entry: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Op {
+    List,
+    Get,
+    Save,
+    Clear,
+    Paste,
+    Copy,
+    /// Whether the agent is running and which UI it has heard from; takes no slot.
+    Status,
+    /// Asks the agent to shut down; takes no slot.
+    Stop,
+    /// Clipboard history, newest first; takes no slot.
+    History,
+    /// Saves history entry `entry` into `slot`.
+    Promote,
+}
+
+/// A slot as its chooser number or its id (`"J"`), unchecked.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum SlotRef {
+    Number(u64),
+    Name(String),
+}
+
+impl std::fmt::Display for SlotRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SlotRef::Number(n) => write!(f, "{}", n),
+            SlotRef::Name(s) => write!(f, "{:?}", s),
+        }
+    }
+}
+
+/// The answer to a `SlotRequest`: `result` when `ok`, else `error`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SlotResponse {
+    #[serde(default)]
+    pub id: Value,
+    pub ok: bool,
+    #[serde(default)]
+    pub result: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl SlotResponse {
+    pub fn new(id: Value, result: Result<Value, String>) -> Self {
+        match result {
+            Ok(result) => Self {
+                id,
+                ok: true,
+                result,
+                error: None,
+            },
+            Err(error) => Self {
+                id,
+                ok: false,
+                result: Value::Null,
+                error: Some(error),
+            },
+        }
+    }
+
+    pub fn into_result(self) -> Result<Value, String> {
+        if self.ok {
+            Ok(self.result)
+        } else {
+            Err(self.error.unwrap_or_else(|| "unknown error".to_string()))
+        }
+    }
+}
+
+/// Why a message could not be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// Not a JSON object, or not one of the known messages.
+    Invalid(String),
+    /// Sent by a version this build does not speak.
+    UnsupportedVersion(u32),
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Invalid(e) => write!(f, "invalid message: {}", e),
+            DecodeError::UnsupportedVersion(v) => write!(
+                f,
+                "protocol version {} is not supported (this build speaks {}..={})",
+                v, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// `message` as JSON, stamped with `PROTOCOL_VERSION`.
+pub fn encode<T: Serialize>(message: &T) -> String {
+    let mut v = serde_json::to_value(message).expect("protocol messages serialize");
+    if let Value::Object(fields) = &mut v {
+        fields.insert("version".to_string(), PROTOCOL_VERSION.into());
+    }
+    v.to_string()
+}
+
+/// Reads one message, checking its version first. `unsupported` is read whatever its version, since
+/// it is how a peer on another version says so.
+pub fn decode<T: DeserializeOwned>(text: &str) -> Result<T, DecodeError> {
+    let v: Value = serde_json::from_str(text).map_err(|e| DecodeError::Invalid(e.to_string()))?;
+    let version = match v.get("version") {
+        None => 1,
+        Some(n) => n
+            .as_u64()
+            .and_then(|n| u32::try_from(n).ok())
+            .ok_or_else(|| DecodeError::Invalid(format!("bad version {}", n)))?,
+    };
+    let unsupported = v.get("type").and_then(Value::as_str) == Some("unsupported");
+    if !unsupported && !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+    T::deserialize(v).map_err(|e| DecodeError::Invalid(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn round_trip<T: Serialize + DeserializeOwned + PartialEq + std::fmt::Debug>(message: T) {
+        assert_eq!(decode::<T>(&encode(&message)), Ok(message));
+    }
+
+    #[test]
+    fn every_message_round_trips() {
+        let token = r#"a"b\c"#.to_string();
+        round_trip(ToAgent::Hello {
+            client: "clip-ui 0.1.0".into(),
+        });
+        round_trip(ToAgent::Heartbeat {
+            client: "clip-ui 0.1.0".into(),
+        });
+        round_trip(ToAgent::Chosen {
+            token: token.clone(),
+            slot: 3,
+            msg_id: Some(7),
+        });
+        round_trip(ToAgent::Cancel {
+            token: token.clone(),
+            reason: "esc".into(),
+            msg_id: None,
+        });
+        round_trip(ToAgent::Ack { msg_id: 8 });
+        round_trip(ToAgent::Trigger {
+            action: Mode::Paste,
+        });
+        round_trip(ToAgent::Request(SlotRequest {
+            id: json!("r1"),
+            op: Op::Save,
+            slot: Some(SlotRef::Name("J".into())),
+            text: Some("hi\n".into()),
+            entry: None,
+        }));
+        round_trip(ToAgent::Request(SlotRequest {
+            id: Value::Null,
+            op: Op::List,
+            slot: None,
+            text: None,
+            entry: None,
+        }));
+        round_trip(ToAgent::Request(SlotRequest {
+            id: json!(4),
+            op: Op::Promote,
+            slot: Some(SlotRef::Number(1)),
+            text: None,
+            entry: Some(12),
+        }));
+        round_trip(ToAgent::Request(SlotRequest {
+            id: json!(2),
+            op: Op::Status,
+            slot: None,
+            text: None,
+            entry: None,
+        }));
+        round_trip(ToAgent::Request(SlotRequest {
+            id: json!(3),
+            op: Op::Stop,
+            slot: None,
+            text: None,
+            entry: None,
+        }));
+        round_trip(FromAgent::Show {
+            mode: Mode::Save,
+            token: token.clone(),
+            timeout_ms: 800,
+            anchor: Anchor::Mouse,
+            keys: Some(ChooserKeys {
+                slots: vec!["option+1".into(), "j".into()],
+                cancel: "escape".into(),
+            }),
+            msg_id: Some(u64::MAX),
+        });
+        round_trip(FromAgent::Hide {
+            token,
+            msg_id: Some(1),
+        });
+        round_trip(FromAgent::Ack { msg_id: 2 });
+        round_trip(FromAgent::Welcome {
+            agent: "0.1.0".into(),
+        });
+        round_trip(FromAgent::Unsupported {
+            min_version: 1,
+            max_version: 1,
+        });
+        round_trip(FromAgent::Response(SlotResponse::new(
+            json!(7),
+            Ok(json!({"slot": 1})),
+        )));
+        round_trip(FromAgent::Response(SlotResponse::new(
+            json!(8),
+            Err("slot J is empty".into()),
+        )));
+    }
+
+    #[test]
+    fn wire_format_is_stable() {
+        let show = FromAgent::Show {
+            mode: Mode::Paste,
+            token: "t".into(),
+            timeout_ms: 800,
+            anchor: Anchor::Mouse,
+            keys: None,
+            msg_id: Some(5),
+        };
+        assert_eq!(
+            serde_json::from_str::<Value>(&encode(&show)).unwrap(),
+            json!({"type": "show", "version": 1, "mode": "paste", "token": "t", "timeout_ms": 800, "anchor": "mouse", "msg_id": 5})
+        );
+        assert_eq!(show.msg_id(), Some(5));
+        let request = ToAgent::Request(SlotRequest {
+            id: json!(1),
+            op: Op::Get,
+            slot: Some(SlotRef::Number(2)),
+            text: None,
+            entry: None,
+        });
+        assert_eq!(
+            serde_json::from_str::<Value>(&encode(&request)).unwrap(),
+            json!({"type": "request", "version": 1, "id": 1, "op": "get", "slot": 2})
+        );
+        let empty = FromAgent::Response(SlotResponse::new(json!(1), Ok(Value::Null)));
+        assert_eq!(
+            serde_json::from_str::<Value>(&encode(&empty)).unwrap(),
+            json!({"type": "response", "version": 1, "id": 1, "ok": true, "result": null})
+        );
+    }
+
+    #[test]
+    fn reads_unversioned_messages_and_ignores_unknown_fields() {
+        // As sent before the protocol had versions.
+        assert_eq!(
+            decode::<ToAgent>(r#"{"type":"chosen","token":"t1","slot":3}"#),
+            Ok(ToAgent::Chosen {
+                token: "t1".into(),
+                slot: 3,
+                msg_id: None
+            })
+        );
+        assert_eq!(
+            decode::<ToAgent>(r#"{"type":"cancel","token":"t1"}"#),
+            Ok(ToAgent::Cancel {
+                token: "t1".into(),
+                reason: "timeout".into(),
+                msg_id: None
+            })
+        );
+        assert_eq!(
+            decode::<FromAgent>(r#"{"type":"show","mode":"save","token":"t","timeout_ms":800}"#),
+            Ok(FromAgent::Show {
+                mode: Mode::Save,
+                token: "t".into(),
+                timeout_ms: 800,
+                anchor: Anchor::Mouse,
+                keys: None,
+                msg_id: None
+            })
+        );
+        assert_eq!(
+            decode::<ToAgent>(r#"{"type":"request","op":"save","slot":"1","text":"x"}"#),
+            Ok(ToAgent::Request(SlotRequest {
+                id: Value::Null,
+                op: Op::Save,
+                slot: Some(SlotRef::Name("1".into())),
+                text: Some("x".into()),
+                entry: None,
+            }))
+        );
+        // A newer sender adding an optional field.
+        assert_eq!(
+            decode::<FromAgent>(r#"{"type":"hide","version":1,"token":"t","animate":true}"#),
+            Ok(FromAgent::Hide {
+                token: "t".into(),
+                msg_id: None
+            })
+        );
+    }
+
+    #[test]
+    fn ids_count_up_and_duplicates_are_seen_once() {
+        let ids = MessageIds::new();
+        let (a, b) = (ids.next(), ids.next());
+        assert_eq!(b, a + 1);
+        std::thread::sleep(Duration::from_millis(2));
+        assert!(
+            MessageIds::new().next() > b,
+            "a restarted sender starts past the old ids"
+        );
+
+        let mut seen = Dedupe::default();
+        assert!(seen.first_time(a));
+        assert!(seen.first_time(b));
+        assert!(!seen.first_time(a));
+        for id in 0..DEDUPE_WINDOW as u64 {
+            seen.first_time(1_000 + id);
+        }
+        assert!(seen.first_time(a), "old ids are forgotten");
+    }
+
+    #[test]
+    fn rejects_unknown_versions_and_messages() {
+        assert_eq!(
+            decode::<ToAgent>(r#"{"type":"hello","version":2,"client":"x"}"#),
+            Err(DecodeError::UnsupportedVersion(2))
+        );
+        assert_eq!(
+            decode::<ToAgent>(r#"{"type":"hello","version":0,"client":"x"}"#),
+            Err(DecodeError::UnsupportedVersion(0))
+        );
+        assert!(matches!(
+            decode::<ToAgent>(r#"{"type":"hello","version":"1"}"#),
+            Err(DecodeError::Invalid(_))
+        ));
+        assert!(matches!(
+            decode::<ToAgent>(r#"{"type":"wave"}"#),
+            Err(DecodeError::Invalid(_))
+        ));
+        assert!(matches!(
+            decode::<ToAgent>(r#"{"type":"chosen","token":"t","slot":"one"}"#),
+            Err(DecodeError::Invalid(_))
+        ));
+        assert!(matches!(
+            decode::<FromAgent>("not json"),
+            Err(DecodeError::Invalid(_))
+        ));
+        // Whatever version the peer speaks, its unsupported reply gets through.
+        let newer = PROTOCOL_VERSION + 1;
+        let reply = format!(
+            r#"{{"type":"unsupported","version":{},"min_version":{},"max_version":{}}}"#,
+            newer, newer, newer
+        );
+        assert_eq!(
+            decode::<FromAgent>(&reply),
+            Ok(FromAgent::Unsupported {
+                min_version: newer,
+                max_version: newer
+            })
+        );
+        // Agent-bound messages are not UI messages.
+        assert!(decode::<FromAgent>(&encode(&ToAgent::Trigger { action: Mode::Save })).is_err());
+
+        assert_eq!("paste".parse::<Mode>(), Ok(Mode::Paste));
+        assert!("copy".parse::<Mode>().is_err());
+        assert_eq!(SlotRef::Name("J".into()).to_string(), "\"J\"");
+    }
+}
@@ -0,0 +1,72 @@
+//! `clip history` subcommands: browse the clipboard history and save an entry into a slot.
+//!
+//! Requests go through `clip-agent request` like `clip slot`'s, so they work with or without a
+//! running agent.
+
+use anyhow::Result;
+use clap::Subcommand;
+use serde_json::{json, Value};
+use std::io::Write;
+
+#[derive(Subcommand)]
+pub enum HistoryCommand {
+    /// List clipboard history, newest first
+    List,
+    /// Save a history entry into a slot
+    Promote {
+        /// Entry number, as shown by `clip history list`
+        entry: i64,
+        /// Slot number 1..6 or id (J, K, L, U, I, O)
+        slot: String,
+    },
+}
+
+pub fn run(command: HistoryCommand, as_json: bool) -> Result<()> {
+    let request = match &command {
+        HistoryCommand::List => json!({"op": "history"}),
+        HistoryCommand::Promote { entry, slot } => {
+            json!({"op": "promote", "entry": entry, "slot": slot})
+        }
+    };
+    let result = crate::slot::send(&request)?;
+
+    let mut out = std::io::stdout().lock();
+    if as_json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&result)?)?;
+        return Ok(());
+    }
+    if let HistoryCommand::List = command {
+        write!(out, "{}", format_history(&result))?;
+    }
+    Ok(())
+}
+
+/// One line per entry: its number, then a preview and the size.
+fn format_history(entries: &Value) -> String {
+    let mut out = String::new();
+    for entry in entries.as_array().map(Vec::as_slice).unwrap_or_default() {
+        let id = entry["entry"].as_i64().unwrap_or(0);
+        let preview = entry["preview"].as_str().unwrap_or("");
+        let size = entry["size"].as_u64().unwrap_or(0);
+        out.push_str(&format!("{:>4}  {}  ({} B)\n", id, preview, size));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_history_for_the_terminal() {
+        let entries = json!([
+            {"entry": 12, "preview": "git status", "size": 10},
+            {"entry": 3, "preview": "hello", "size": 6},
+        ]);
+        assert_eq!(
+            format_history(&entries),
+            "  12  git status  (10 B)\n   3  hello  (6 B)\n"
+        );
+        assert_eq!(format_history(&json!([])), "");
+    }
+}
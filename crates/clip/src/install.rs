@@ -0,0 +1,575 @@
+//! `clip install` / `clip uninstall`: run the agent at login as a launchd LaunchAgent (macOS) or
+//! a systemd user unit (Linux). Both restart the agent when it fails but not when it is stopped
+//! on purpose (`clip stop` exits 0), and append its output to a log file. `--dry-run` prints the
+//! service file and the commands instead, so the generators run anywhere.
+
+use anyhow::{bail, Context, Result};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// launchd label, also the plist's file name.
+const LAUNCHD_LABEL: &str = "com.slotpaste.agent";
+/// systemd unit name.
+const SYSTEMD_UNIT: &str = "clip-agent.service";
+/// clip-agent's exit status when another agent already runs; not a failure to restart after.
+const ALREADY_RUNNING_STATUS: i32 = 3;
+/// What the agent needs from the session to reach the display; a systemd user manager only has
+/// it once imported.
+const SESSION_ENV: [&str; 3] = ["DISPLAY", "WAYLAND_DISPLAY", "XAUTHORITY"];
+/// The target desktops with systemd integration start at login.
+const SESSION_TARGET: &str = "graphical-session.target";
+/// The fallback for window managers that never start `SESSION_TARGET`.
+const DEFAULT_TARGET: &str = "default.target";
+
+/// The service manager that starts user programs at login.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Manager {
+    Launchd,
+    Systemd,
+}
+
+/// What the service runs: the agent binary, where its output goes and its environment, and the
+/// systemd target that starts it.
+struct Service {
+    agent: PathBuf,
+    log: PathBuf,
+    env: Vec<(&'static str, String)>,
+    wanted_by: &'static str,
+}
+
+/// One command to run; `may_fail` for cleanup of something that may not be there.
+#[derive(Debug, PartialEq)]
+struct Step {
+    args: Vec<String>,
+    may_fail: bool,
+}
+
+impl Manager {
+    fn current() -> Result<Self> {
+        if cfg!(target_os = "macos") {
+            Ok(Manager::Launchd)
+        } else if cfg!(target_os = "linux") {
+            Ok(Manager::Systemd)
+        } else {
+            bail!("clip install supports macOS (launchd) and Linux (systemd user units)")
+        }
+    }
+
+    /// Where the service file goes, from the user's environment.
+    fn unit_path(self, env: impl Fn(&str) -> Option<OsString>) -> Result<PathBuf> {
+        let home = PathBuf::from(env("HOME").context("HOME not set")?);
+        Ok(match self {
+            Manager::Launchd => home
+                .join("Library/LaunchAgents")
+                .join(format!("{}.plist", LAUNCHD_LABEL)),
+            Manager::Systemd => {
+                let config = match env("XDG_CONFIG_HOME") {
+                    Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+                    _ => home.join(".config"),
+                };
+                config.join("systemd/user").join(SYSTEMD_UNIT)
+            }
+        })
+    }
+
+    /// The agent's log, next to the slot DB's directory on each platform.
+    fn log_path(self, env: impl Fn(&str) -> Option<OsString>) -> Result<PathBuf> {
+        let home = PathBuf::from(env("HOME").context("HOME not set")?);
+        Ok(match self {
+            Manager::Launchd => home.join("Library/Logs/Slotpaste/agent.log"),
+            Manager::Systemd => home.join(".slotpaste/agent.log"),
+        })
+    }
+
+    fn render(self, service: &Service) -> String {
+        match self {
+            Manager::Launchd => launchd_plist(service),
+            Manager::Systemd => systemd_unit(service),
+        }
+    }
+
+    /// Commands that (re)load the written service file and start the agent. An agent started
+    /// outside the service (`clip start`) is stopped first, or the service's agent would find the
+    /// one-agent lock held. systemd also gets this session's display variables.
+    fn install_steps(self, agent: &Path, unit: &Path, uid: u32) -> Vec<Step> {
+        let stop_agent = step(&[&agent.to_string_lossy(), "stop"], false);
+        match self {
+            Manager::Launchd => vec![
+                step(
+                    &[
+                        "launchctl",
+                        "bootout",
+                        &format!("gui/{}/{}", uid, LAUNCHD_LABEL),
+                    ],
+                    true,
+                ),
+                stop_agent,
+                step(
+                    &[
+                        "launchctl",
+                        "bootstrap",
+                        &format!("gui/{}", uid),
+                        &unit.to_string_lossy(),
+                    ],
+                    false,
+                ),
+            ],
+            Manager::Systemd => vec![
+                step(
+                    &[
+                        &["systemctl", "--user", "import-environment"][..],
+                        &SESSION_ENV,
+                    ]
+                    .concat(),
+                    true,
+                ),
+                step(&["systemctl", "--user", "daemon-reload"], false),
+                step(&["systemctl", "--user", "enable", SYSTEMD_UNIT], false),
+                stop_agent,
+                step(&["systemctl", "--user", "restart", SYSTEMD_UNIT], false),
+            ],
+        }
+    }
+
+    /// What the user has to add to their session when the unit cannot follow it.
+    fn session_note(self, wanted_by: &str) -> Option<String> {
+        (self == Manager::Systemd && wanted_by == DEFAULT_TARGET).then(|| {
+            format!(
+                "{} is not active, so the agent starts with {} and may miss the display.\n\
+                 Add these to your window manager's startup (e.g. ~/.xprofile):\n\
+                 \x20 systemctl --user import-environment {}\n\
+                 \x20 systemctl --user restart {}",
+                SESSION_TARGET,
+                DEFAULT_TARGET,
+                SESSION_ENV.join(" "),
+                SYSTEMD_UNIT
+            )
+        })
+    }
+
+    /// Commands that stop the agent and unregister it, before the file is removed.
+    fn uninstall_steps(self, uid: u32) -> Vec<Step> {
+        match self {
+            Manager::Launchd => vec![step(
+                &[
+                    "launchctl",
+                    "bootout",
+                    &format!("gui/{}/{}", uid, LAUNCHD_LABEL),
+                ],
+                true,
+            )],
+            Manager::Systemd => vec![step(
+                &["systemctl", "--user", "disable", "--now", SYSTEMD_UNIT],
+                true,
+            )],
+        }
+    }
+
+    /// Commands to run once the file is gone.
+    fn after_removal_steps(self) -> Vec<Step> {
+        match self {
+            Manager::Launchd => vec![],
+            Manager::Systemd => vec![step(&["systemctl", "--user", "daemon-reload"], false)],
+        }
+    }
+}
+
+fn step(args: &[&str], may_fail: bool) -> Step {
+    Step {
+        args: args.iter().map(|a| a.to_string()).collect(),
+        may_fail,
+    }
+}
+
+pub fn install(dry_run: bool) -> Result<()> {
+    let manager = Manager::current()?;
+    let env = |key: &str| std::env::var_os(key);
+    let agent = crate::find_agent_path().context(
+        "clip-agent not found in PATH or next to clip (build it with `cargo build -p clip-agent`)",
+    )?;
+    let agent = agent
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve {}", agent.display()))?;
+    let mut service_env = vec![
+        ("RUST_LOG", "info".to_string()),
+        ("NO_COLOR", "1".to_string()),
+    ];
+    // A custom config location has to follow the agent into the service.
+    if let Some(config) = std::env::var_os("SLOTPASTE_CONFIG") {
+        service_env.push(("SLOTPASTE_CONFIG", config.to_string_lossy().into_owned()));
+    }
+    let wanted_by = match manager {
+        Manager::Systemd if !session_target_active() => DEFAULT_TARGET,
+        _ => SESSION_TARGET,
+    };
+    let service = Service {
+        agent,
+        log: manager.log_path(env)?,
+        env: service_env,
+        wanted_by,
+    };
+    let unit = manager.unit_path(env)?;
+    let contents = manager.render(&service);
+    let steps = manager.install_steps(&service.agent, &unit, current_uid());
+
+    if dry_run {
+        println!("# {}", unit.display());
+        print!("{}", contents);
+        println!("# then run:");
+        for step in &steps {
+            println!("{}", step.args.join(" "));
+        }
+        if let Some(note) = manager.session_note(wanted_by) {
+            println!("# {}", note.replace('\n', "\n# "));
+        }
+        return Ok(());
+    }
+
+    for dir in [unit.parent(), service.log.parent()].into_iter().flatten() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    std::fs::write(&unit, contents)
+        .with_context(|| format!("Failed to write {}", unit.display()))?;
+    run_steps(&steps)?;
+    println!(
+        "Installed: {} runs at login ({})",
+        service.agent.display(),
+        unit.display()
+    );
+    println!("Logs: {}", service.log.display());
+    if let Some(note) = manager.session_note(wanted_by) {
+        println!("{}", note);
+    }
+    Ok(())
+}
+
+/// Whether this login started `graphical-session.target`; false without systemd.
+fn session_target_active() -> bool {
+    Command::new("systemctl")
+        .args(["--user", "is-active", "--quiet", SESSION_TARGET])
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+pub fn uninstall(dry_run: bool) -> Result<()> {
+    let manager = Manager::current()?;
+    let unit = manager.unit_path(|key| std::env::var_os(key))?;
+    let stop = manager.uninstall_steps(current_uid());
+    let reload = manager.after_removal_steps();
+
+    if dry_run {
+        for step in &stop {
+            println!("{}", step.args.join(" "));
+        }
+        println!("rm {}", unit.display());
+        for step in &reload {
+            println!("{}", step.args.join(" "));
+        }
+        return Ok(());
+    }
+
+    if !unit.exists() {
+        println!(
+            "clip-agent is not installed ({} does not exist)",
+            unit.display()
+        );
+        return Ok(());
+    }
+    run_steps(&stop)?;
+    std::fs::remove_file(&unit).with_context(|| format!("Failed to remove {}", unit.display()))?;
+    run_steps(&reload)?;
+    println!("Uninstalled: removed {}", unit.display());
+    Ok(())
+}
+
+fn run_steps(steps: &[Step]) -> Result<()> {
+    for step in steps {
+        let status = Command::new(&step.args[0])
+            .args(&step.args[1..])
+            .status()
+            .with_context(|| format!("Failed to run {}", step.args[0]))?;
+        if !status.success() && !step.may_fail {
+            bail!("`{}` failed ({})", step.args.join(" "), status);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn current_uid() -> u32 {
+    // SAFETY: getuid has no preconditions and cannot fail.
+    unsafe { libc::getuid() }
+}
+
+#[cfg(not(unix))]
+fn current_uid() -> u32 {
+    0
+}
+
+/// A LaunchAgent that starts at login and is relaunched after a crash, but not after a clean
+/// exit.
+fn launchd_plist(service: &Service) -> String {
+    let log = xml_escape(&service.log.to_string_lossy());
+    let env: String = service
+        .env
+        .iter()
+        .map(|(key, value)| {
+            format!(
+                "        <key>{}</key>\n        <string>{}</string>\n",
+                key,
+                xml_escape(value)
+            )
+        })
+        .collect();
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{label}</string>
+    <key>ProgramArguments</key>
+    <array>
+        <string>{agent}</string>
+    </array>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <dict>
+        <key>SuccessfulExit</key>
+        <false/>
+    </dict>
+    <key>ThrottleInterval</key>
+    <integer>10</integer>
+    <key>ProcessType</key>
+    <string>Interactive</string>
+    <key>EnvironmentVariables</key>
+    <dict>
+{env}    </dict>
+    <key>StandardOutPath</key>
+    <string>{log}</string>
+    <key>StandardErrorPath</key>
+    <string>{log}</string>
+</dict>
+</plist>
+"#,
+        label = LAUNCHD_LABEL,
+        agent = xml_escape(&service.agent.to_string_lossy()),
+        env = env,
+        log = log,
+    )
+}
+
+/// A user unit tied to the graphical session, so the agent sees its `DISPLAY` or
+/// `WAYLAND_DISPLAY`, restarted after a failure but not when another agent already runs. Stopped
+/// with SIGINT, the agent's Ctrl+C, so it shuts down cleanly. Installed into `wanted_by`, which
+/// is `default.target` where the session target never starts.
+fn systemd_unit(service: &Service) -> String {
+    let log = service.log.to_string_lossy().replace('%', "%%");
+    let env: String = service
+        .env
+        .iter()
+        .map(|(key, value)| {
+            format!(
+                "Environment={}\n",
+                systemd_quote(&format!("{}={}", key, value))
+            )
+        })
+        .collect();
+    format!(
+        "[Unit]
+Description=Slotpaste clipboard agent
+PartOf=graphical-session.target
+After=graphical-session.target
+
+[Service]
+ExecStart={agent}
+Restart=on-failure
+RestartSec=5
+RestartPreventExitStatus={already_running}
+KillSignal=SIGINT
+{env}StandardOutput=append:{log}
+StandardError=append:{log}
+
+[Install]
+WantedBy={wanted_by}
+",
+        agent = systemd_quote(&service.agent.to_string_lossy()),
+        already_running = ALREADY_RUNNING_STATUS,
+        env = env,
+        log = log,
+        wanted_by = service.wanted_by,
+    )
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// One systemd word: `%` doubled (specifiers), quoted when it has spaces or quotes.
+fn systemd_quote(s: &str) -> String {
+    let s = s.replace('%', "%%");
+    if s.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'' || c == '\\') {
+        format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(agent: &str, log: &str) -> Service {
+        Service {
+            agent: PathBuf::from(agent),
+            log: PathBuf::from(log),
+            env: vec![
+                ("RUST_LOG", "info".to_string()),
+                ("SLOTPASTE_CONFIG", "/home/me/my config.toml".to_string()),
+            ],
+            wanted_by: SESSION_TARGET,
+        }
+    }
+
+    #[test]
+    fn systemd_unit_restarts_on_failure_and_logs_to_a_file() {
+        let unit = systemd_unit(&service(
+            "/home/me/bin/clip agent",
+            "/home/me/.slotpaste/agent.log",
+        ));
+        assert!(
+            unit.contains("\nExecStart=\"/home/me/bin/clip agent\"\n"),
+            "{}",
+            unit
+        );
+        assert!(unit.contains(
+            "\nRestart=on-failure\nRestartSec=5\nRestartPreventExitStatus=3\nKillSignal=SIGINT\n"
+        ));
+        assert!(unit.contains("\nEnvironment=RUST_LOG=info\nEnvironment=\"SLOTPASTE_CONFIG=/home/me/my config.toml\"\n"));
+        assert!(unit.contains("\nStandardOutput=append:/home/me/.slotpaste/agent.log\n"));
+        assert!(unit.contains("\nStandardError=append:/home/me/.slotpaste/agent.log\n"));
+        assert!(unit.ends_with("[Install]\nWantedBy=graphical-session.target\n"));
+        assert_eq!(systemd_quote("100%"), "100%%");
+
+        let plain_wm = Service {
+            wanted_by: DEFAULT_TARGET,
+            ..service("/usr/bin/clip-agent", "/tmp/agent.log")
+        };
+        assert!(systemd_unit(&plain_wm).ends_with("[Install]\nWantedBy=default.target\n"));
+        let note = Manager::Systemd.session_note(DEFAULT_TARGET).unwrap();
+        assert!(
+            note.contains(
+                "systemctl --user import-environment DISPLAY WAYLAND_DISPLAY XAUTHORITY\n"
+            ),
+            "{}",
+            note
+        );
+        assert_eq!(Manager::Systemd.session_note(SESSION_TARGET), None);
+    }
+
+    #[test]
+    fn launchd_plist_keeps_the_agent_alive_after_crashes_only() {
+        let plist = launchd_plist(&service(
+            "/Applications/A&B/clip-agent",
+            "/Users/me/Library/Logs/Slotpaste/agent.log",
+        ));
+        assert!(plist.contains("<string>com.slotpaste.agent</string>"));
+        assert!(plist.contains(
+            "<array>\n        <string>/Applications/A&amp;B/clip-agent</string>\n    </array>"
+        ));
+        assert!(plist.contains("<key>SuccessfulExit</key>\n        <false/>"));
+        assert!(plist.contains(
+            "<key>SLOTPASTE_CONFIG</key>\n        <string>/home/me/my config.toml</string>"
+        ));
+        assert_eq!(
+            plist
+                .matches("<string>/Users/me/Library/Logs/Slotpaste/agent.log</string>")
+                .count(),
+            2
+        );
+        assert!(plist.ends_with("</dict>\n</plist>\n"));
+    }
+
+    #[test]
+    fn service_files_and_commands_per_manager() {
+        let env = |key: &str| match key {
+            "HOME" => Some(OsString::from("/home/me")),
+            "XDG_CONFIG_HOME" => Some(OsString::from("/home/me/.cfg")),
+            _ => None,
+        };
+        let unit = Manager::Systemd.unit_path(env).unwrap();
+        assert_eq!(
+            unit,
+            PathBuf::from("/home/me/.cfg/systemd/user/clip-agent.service")
+        );
+        assert_eq!(
+            Manager::Systemd.log_path(env).unwrap(),
+            PathBuf::from("/home/me/.slotpaste/agent.log")
+        );
+        let agent = Path::new("/usr/bin/clip-agent");
+        let steps = Manager::Systemd.install_steps(agent, &unit, 1000);
+        assert_eq!(
+            steps[0],
+            step(
+                &[
+                    "systemctl",
+                    "--user",
+                    "import-environment",
+                    "DISPLAY",
+                    "WAYLAND_DISPLAY",
+                    "XAUTHORITY"
+                ],
+                true
+            )
+        );
+        assert_eq!(
+            steps[3..],
+            [
+                step(&["/usr/bin/clip-agent", "stop"], false),
+                step(
+                    &["systemctl", "--user", "restart", "clip-agent.service"],
+                    false
+                ),
+            ]
+        );
+
+        let plist = Manager::Launchd.unit_path(env).unwrap();
+        assert_eq!(
+            plist,
+            PathBuf::from("/home/me/Library/LaunchAgents/com.slotpaste.agent.plist")
+        );
+        assert_eq!(
+            Manager::Launchd.install_steps(agent, &plist, 501),
+            [
+                step(
+                    &["launchctl", "bootout", "gui/501/com.slotpaste.agent"],
+                    true
+                ),
+                step(&["/usr/bin/clip-agent", "stop"], false),
+                step(
+                    &[
+                        "launchctl",
+                        "bootstrap",
+                        "gui/501",
+                        "/home/me/Library/LaunchAgents/com.slotpaste.agent.plist"
+                    ],
+                    false
+                ),
+            ]
+        );
+        assert_eq!(
+            Manager::Launchd.uninstall_steps(501),
+            [step(
+                &["launchctl", "bootout", "gui/501/com.slotpaste.agent"],
+                true
+            )]
+        );
+    }
+}
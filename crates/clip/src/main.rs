@@ -1,4 +1,8 @@
 mod doctor;
+mod history;
+mod install;
+mod slot;
+mod status;
 
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
@@ -17,10 +21,44 @@ struct Cli {
 enum Commands {
     /// Start the agent in foreground
     Start,
+    /// Ask the running agent to shut down
+    Stop,
     /// Check system configuration
     Doctor,
-    /// Install system hooks
-    Install,
+    /// Run the agent at login (launchd on macOS, a systemd user unit on Linux)
+    Install {
+        /// Print the service file and commands instead of installing
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Stop the agent from running at login and remove its service file
+    Uninstall {
+        /// Print the commands instead of uninstalling
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Read and write slots (through the agent when it is running, else the slot DB)
+    Slot {
+        #[command(subcommand)]
+        command: slot::SlotCommand,
+        /// Print results as JSON
+        #[arg(long, global = true)]
+        json: bool,
+    },
+    /// Browse the clipboard history and save entries into slots
+    History {
+        #[command(subcommand)]
+        command: history::HistoryCommand,
+        /// Print results as JSON
+        #[arg(long, global = true)]
+        json: bool,
+    },
+    /// Show whether the agent and the chooser UI are running
+    Status {
+        /// Print the agent's JSON status instead
+        #[arg(long)]
+        json: bool,
+    },
 }
 
 fn main() -> Result<()> {
@@ -32,11 +70,13 @@ fn main() -> Result<()> {
 
     match cli.command {
         Commands::Start => start_agent()?,
+        Commands::Stop => stop_agent()?,
         Commands::Doctor => doctor::run_accessibility_check(),
-        Commands::Install => {
-            println!("not implemented yet");
-            std::process::exit(0);
-        }
+        Commands::Install { dry_run } => install::install(dry_run)?,
+        Commands::Uninstall { dry_run } => install::uninstall(dry_run)?,
+        Commands::Slot { command, json } => slot::run(command, json)?,
+        Commands::History { command, json } => history::run(command, json)?,
+        Commands::Status { json } => status::run(json)?,
     }
 
     Ok(())
@@ -98,3 +138,17 @@ fn start_agent() -> Result<()> {
 
     Ok(())
 }
+
+fn stop_agent() -> Result<()> {
+    let agent_path = find_agent_path().context(
+        "clip-agent not found in PATH or next to clip (build it with `cargo build -p clip-agent`)",
+    )?;
+    let status = Command::new(&agent_path)
+        .arg("stop")
+        .status()
+        .with_context(|| format!("Failed to run {}", agent_path.display()))?;
+    if !status.success() {
+        std::process::exit(status.code().unwrap_or(1));
+    }
+    Ok(())
+}
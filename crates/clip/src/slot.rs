@@ -0,0 +1,207 @@
+//! `clip slot` subcommands: read and write slots from scripts.
+//!
+//! Each command is one request (see `clip-agent`'s `ipc::request`) run through
+//! `clip-agent request`, which asks the running agent and falls back to the slot DB when no
+//! agent is running. `copy` needs the agent, since it owns the clipboard.
+
+use anyhow::{bail, Context, Result};
+use clap::Subcommand;
+use serde_json::{json, Value};
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+
+#[derive(Subcommand)]
+pub enum SlotCommand {
+    /// List all slots with a preview of their content
+    List,
+    /// Print a slot's text to stdout
+    Get {
+        /// Slot number 1..6 or id (J, K, L, U, I, O)
+        slot: String,
+    },
+    /// Save text to a slot (read from stdin when TEXT is omitted)
+    Set {
+        /// Slot number 1..6 or id
+        slot: String,
+        text: Option<String>,
+    },
+    /// Empty a slot
+    Clear {
+        /// Slot number 1..6 or id
+        slot: String,
+    },
+    /// Put a slot's content on the clipboard (needs the agent)
+    Copy {
+        /// Slot number 1..6 or id
+        slot: String,
+    },
+}
+
+pub fn run(command: SlotCommand, as_json: bool) -> Result<()> {
+    let request = match &command {
+        SlotCommand::List => json!({"op": "list"}),
+        SlotCommand::Get { slot } => json!({"op": "get", "slot": slot}),
+        SlotCommand::Set { slot, text } => {
+            let text = match text {
+                Some(t) => t.clone(),
+                None => {
+                    let mut input = String::new();
+                    std::io::stdin()
+                        .read_to_string(&mut input)
+                        .context("Failed to read stdin")?;
+                    input
+                }
+            };
+            if text.is_empty() {
+                bail!("nothing to save (empty text)");
+            }
+            json!({"op": "save", "slot": slot, "text": text})
+        }
+        SlotCommand::Clear { slot } => json!({"op": "clear", "slot": slot}),
+        SlotCommand::Copy { slot } => json!({"op": "copy", "slot": slot}),
+    };
+    let result = send(&request)?;
+
+    let mut out = std::io::stdout().lock();
+    if as_json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&result)?)?;
+        return Ok(());
+    }
+    match command {
+        SlotCommand::List => write!(out, "{}", format_list(&result))?,
+        SlotCommand::Get { slot } => out.write_all(slot_text(&slot, &result)?.as_bytes())?,
+        SlotCommand::Set { .. } | SlotCommand::Clear { .. } | SlotCommand::Copy { .. } => {}
+    }
+    Ok(())
+}
+
+/// Runs `request` through `clip-agent request` and returns the response's result.
+pub(crate) fn send(request: &Value) -> Result<Value> {
+    let agent = crate::find_agent_path().context(
+        "clip-agent not found in PATH or next to clip (build it with `cargo build -p clip-agent`)",
+    )?;
+    let mut child = Command::new(&agent)
+        .arg("request")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .with_context(|| format!("Failed to run {}", agent.display()))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(request.to_string().as_bytes())
+            .context("Failed to send request")?;
+    }
+    let output = child
+        .wait_with_output()
+        .context("Failed to wait for clip-agent")?;
+    if !output.status.success() {
+        bail!("clip-agent request failed ({})", output.status);
+    }
+    let response: Value = serde_json::from_slice(&output.stdout)
+        .context("clip-agent returned an invalid response")?;
+    parse_response(response)
+}
+
+/// The `result` of an ok response, or its `error`.
+fn parse_response(response: Value) -> Result<Value> {
+    if response.get("ok").and_then(Value::as_bool) == Some(true) {
+        return Ok(response.get("result").cloned().unwrap_or(Value::Null));
+    }
+    let error = response
+        .get("error")
+        .and_then(Value::as_str)
+        .unwrap_or("unknown error");
+    bail!("{}", error)
+}
+
+/// One line per slot: number, label, then a preview or "(empty)".
+fn format_list(slots: &Value) -> String {
+    let mut out = String::new();
+    for slot in slots.as_array().map(Vec::as_slice).unwrap_or_default() {
+        let num = slot["slot"].as_u64().unwrap_or(0);
+        let label = slot["label"]
+            .as_str()
+            .or(slot["id"].as_str())
+            .unwrap_or("?");
+        let content = if slot["filled"].as_bool() != Some(true) {
+            "(empty)".to_string()
+        } else {
+            let size = slot["size"].as_u64().unwrap_or(0);
+            match slot["preview"].as_str() {
+                Some(preview) => format!("{}  ({} B)", preview, size),
+                None => format!("[{}]  ({} B)", types(slot), size),
+            }
+        };
+        out.push_str(&format!("{}  {:<8}  {}\n", num, label, content));
+    }
+    out
+}
+
+/// Text of a `get` result; errors for empty slots and content without text.
+fn slot_text(slot: &str, result: &Value) -> Result<String> {
+    if result.is_null() {
+        bail!("slot {} is empty", slot);
+    }
+    match result["text"].as_str() {
+        Some(text) => Ok(text.to_string()),
+        None => bail!(
+            "slot {} has no text (types: {}); use --json to inspect it",
+            slot,
+            types(result)
+        ),
+    }
+}
+
+fn types(slot: &Value) -> String {
+    let types: Vec<&str> = slot["types"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .collect();
+    types.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_results_for_the_terminal() {
+        let list = json!([
+            {"slot": 1, "id": "J", "label": "J", "filled": true, "size": 5, "types": ["public.utf8-plain-text"], "preview": "hello"},
+            {"slot": 2, "id": "K", "label": "work", "filled": false, "size": 0, "types": [], "preview": null},
+            {"slot": 3, "id": "L", "label": "L", "filled": true, "size": 70, "types": ["public.png"], "preview": null},
+        ]);
+        assert_eq!(
+            format_list(&list),
+            "1  J         hello  (5 B)\n2  work      (empty)\n3  L         [public.png]  (70 B)\n"
+        );
+
+        assert_eq!(
+            slot_text("1", &json!({"text": "a\nb", "types": []})).unwrap(),
+            "a\nb"
+        );
+        assert_eq!(
+            slot_text("K", &Value::Null).unwrap_err().to_string(),
+            "slot K is empty"
+        );
+        let image = json!({"text": null, "types": ["public.png"]});
+        assert!(slot_text("3", &image)
+            .unwrap_err()
+            .to_string()
+            .contains("types: public.png"));
+    }
+
+    #[test]
+    fn unwraps_responses() {
+        let ok = json!({"type": "response", "ok": true, "result": {"slot": 1}});
+        assert_eq!(parse_response(ok).unwrap(), json!({"slot": 1}));
+        let err = json!({"type": "response", "ok": false, "error": "slot J is empty"});
+        assert_eq!(
+            parse_response(err).unwrap_err().to_string(),
+            "slot J is empty"
+        );
+    }
+}
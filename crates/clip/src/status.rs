@@ -0,0 +1,77 @@
+//! `clip status`: whether the agent is running, its open chooser, and which chooser UI it has
+//! heard from. One `status` request through `clip-agent request`; without an agent the answer
+//! comes back with everything null.
+
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::io::Write;
+
+pub fn run(as_json: bool) -> Result<()> {
+    let status = crate::slot::send(&json!({"op": "status"}))?;
+    let mut out = std::io::stdout().lock();
+    if as_json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&status)?)?;
+    } else {
+        write!(out, "{}", format_status(&status))?;
+    }
+    Ok(())
+}
+
+fn format_status(status: &Value) -> String {
+    let Some(agent) = status["agent"].as_str() else {
+        return "agent:    not running\n".to_string();
+    };
+    let chooser = match (
+        status["chooser"]["mode"].as_str(),
+        status["chooser"]["ui"].as_bool(),
+    ) {
+        (Some(mode), Some(false)) => format!("{} open, keyboard-only", mode),
+        (Some(mode), _) => format!("{} open", mode),
+        (None, _) => "closed".to_string(),
+    };
+    let ui = &status["ui"];
+    let ui = match ui["client"].as_str() {
+        Some(client) => format!(
+            "{} over {} (last seen {:.1} s ago)",
+            client,
+            ui["transport"].as_str().unwrap_or("?"),
+            ui["last_seen_ms"].as_u64().unwrap_or(0) as f64 / 1000.0
+        ),
+        None => "not running; choosers are keyboard-only (slot keys while one is open)".to_string(),
+    };
+    format!(
+        "agent:    running (clip-agent {})\nchooser:  {}\nui:       {}\n",
+        agent, chooser, ui
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_status_for_the_terminal() {
+        let running = json!({
+            "agent": "0.1.0",
+            "chooser": null,
+            "ui": {"client": "clip-ui 0.1.0", "transport": "unix", "last_seen_ms": 1250},
+        });
+        assert_eq!(
+            format_status(&running),
+            "agent:    running (clip-agent 0.1.0)\nchooser:  closed\nui:       clip-ui 0.1.0 over unix (last seen 1.2 s ago)\n"
+        );
+        let no_ui =
+            json!({"agent": "0.1.0", "chooser": {"mode": "paste", "ui": false}, "ui": null});
+        let text = format_status(&no_ui);
+        assert!(
+            text.contains("chooser:  paste open, keyboard-only\n"),
+            "{}",
+            text
+        );
+        assert!(text.contains("ui:       not running"), "{}", text);
+        assert_eq!(
+            format_status(&json!({"agent": null, "chooser": null, "ui": null})),
+            "agent:    not running\n"
+        );
+    }
+}